@@ -0,0 +1,244 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+#[cfg(feature = "gui")]
+use tauri::AppHandle;
+
+#[cfg(feature = "gui")]
+use crate::config::app_data_dir;
+use crate::config::fs_error;
+use crate::events::EventSink;
+use crate::models::{
+    CommandPlan, CommandResult, ErrorRecord, GeekFlashError, InputFileRecord, LogLine,
+    OperationInfo, OperationRecord, OperationSummary, ProgressEvent, StepFinished, StepRecord,
+    StepStarted,
+};
+
+/// History directory under `$APP_DATA_DIR`, next to `config.json`.
+const HISTORY_DIR_NAME: &str = "history";
+
+#[cfg(feature = "gui")]
+pub fn history_dir(app: &AppHandle) -> Result<PathBuf, GeekFlashError> {
+    Ok(history_dir_in(&app_data_dir(app)?))
+}
+
+/// The history directory inside an app data directory.
+pub fn history_dir_in(data_dir: &Path) -> PathBuf {
+    data_dir.join(HISTORY_DIR_NAME)
+}
+
+/// Lowercase hex SHA-256 of a file's contents.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Size and hash every `(label, path)` input.
+pub fn hash_inputs(inputs: &[(String, String)]) -> Vec<InputFileRecord> {
+    inputs
+        .iter()
+        .map(|(label, path)| InputFileRecord {
+            label: label.clone(),
+            path: path.clone(),
+            size_bytes: fs::metadata(path).ok().map(|m| m.len()),
+            sha256: sha256_file(Path::new(path)).ok(),
+        })
+        .collect()
+}
+
+/// Reject IDs that could escape the history directory.
+fn record_path(dir: &Path, operation_id: &str) -> Result<PathBuf, GeekFlashError> {
+    let valid = !operation_id.is_empty()
+        && operation_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(GeekFlashError::invalid_input(
+            "operation_id",
+            format!("\"{operation_id}\" is not an operation ID"),
+        ));
+    }
+    Ok(dir.join(format!("{operation_id}.json")))
+}
+
+/// Write `record` to `<dir>/<operation_id>.json`, replacing any earlier copy.
+pub fn save_record(dir: &Path, record: &OperationRecord) -> Result<(), GeekFlashError> {
+    fs::create_dir_all(dir).map_err(|e| fs_error("create history directory", e))?;
+    let path = record_path(dir, &record.operation_id)?;
+    let json = serde_json::to_string_pretty(record)
+        .map_err(|e| GeekFlashError::io("serialize operation record", e))?;
+    fs::write(path, json).map_err(|e| fs_error("write operation record", e))
+}
+
+pub fn load_record(dir: &Path, operation_id: &str) -> Result<OperationRecord, GeekFlashError> {
+    let path = record_path(dir, operation_id)?;
+    let data = match fs::read_to_string(&path) {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(GeekFlashError::invalid_input(
+                "operation_id",
+                format!("no recorded operation \"{operation_id}\""),
+            ));
+        }
+        Err(e) => return Err(fs_error("read operation record", e)),
+    };
+    serde_json::from_str(&data).map_err(|e| GeekFlashError::io("parse operation record", e))
+}
+
+/// Summaries of every readable record in `dir`, newest first.
+///
+/// Unreadable or corrupted records are skipped rather than failing the list.
+pub fn list_records(dir: &Path) -> Vec<OperationSummary> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut summaries: Vec<OperationSummary> = entries
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|data| serde_json::from_str::<OperationRecord>(&data).ok())
+        .map(|r| r.summary())
+        .collect();
+    summaries.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+    summaries
+}
+
+/// Collects an operation's log lines and step results and persists them.
+///
+/// Plugged into the executor as an [`EventSink`], next to the UI's sink.
+#[derive(Clone)]
+pub struct AuditRecorder {
+    inner: Arc<Mutex<AuditState>>,
+}
+
+struct AuditState {
+    record: OperationRecord,
+    /// `None` if the history directory could not be resolved.
+    dir: Option<PathBuf>,
+}
+
+impl AuditRecorder {
+    /// Start recording an operation into `dir` and write its initial record,
+    /// so a crash mid-flash still leaves a trace. With no `dir`, the record is
+    /// only kept in memory.
+    pub fn start(
+        dir: Option<PathBuf>,
+        operation_id: &str,
+        kind: &str,
+        serial: Option<&str>,
+        inputs: Vec<InputFileRecord>,
+        plan: CommandPlan,
+    ) -> Self {
+        let record = OperationRecord {
+            operation_id: operation_id.to_string(),
+            kind: kind.to_string(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            finished_at: None,
+            serial: serial.map(str::to_string),
+            inputs,
+            plan,
+            log: Vec::new(),
+            steps: Vec::new(),
+            result: None,
+            error: None,
+        };
+
+        let recorder = AuditRecorder {
+            inner: Arc::new(Mutex::new(AuditState { record, dir })),
+        };
+        recorder.persist();
+        recorder
+    }
+
+    /// The record as it stands now (log so far, steps finished so far).
+    pub fn snapshot(&self) -> OperationRecord {
+        self.inner.lock().unwrap().record.clone()
+    }
+
+    /// Record the final outcome and write the completed record.
+    pub fn finish(&self, result: &Result<CommandResult, GeekFlashError>) {
+        {
+            let mut state = self.inner.lock().unwrap();
+            state.record.finished_at = Some(chrono::Utc::now().to_rfc3339());
+            match result {
+                Ok(r) => state.record.result = Some(r.clone()),
+                Err(e) => state.record.error = Some(ErrorRecord::from(e)),
+            }
+        }
+        self.persist();
+    }
+
+    fn persist(&self) {
+        let state = self.inner.lock().unwrap();
+        if let Some(dir) = &state.dir {
+            if let Err(e) = save_record(dir, &state.record) {
+                eprintln!("[audit] Warning: {e}");
+            }
+        }
+    }
+}
+
+impl EventSink for AuditRecorder {
+    fn operation_started(&self, _info: &OperationInfo) {}
+
+    fn log(&self, line: &LogLine) {
+        self.inner.lock().unwrap().record.log.push(line.clone());
+    }
+
+    fn progress(&self, _event: &ProgressEvent) {}
+
+    fn step_started(&self, _event: &StepStarted) {}
+
+    fn step_finished(&self, event: &StepFinished) {
+        self.inner.lock().unwrap().record.steps.push(StepRecord {
+            step_index: event.step_index,
+            command_line: event.command_line.clone(),
+            result: event.result.clone(),
+        });
+    }
+}
+
+// === Tauri Commands ===
+
+/// Past operations, newest first.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn list_operations(app: AppHandle) -> Result<Vec<OperationSummary>, GeekFlashError> {
+    Ok(list_records(&history_dir(&app)?))
+}
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_operation(
+    app: AppHandle,
+    operation_id: String,
+) -> Result<OperationRecord, GeekFlashError> {
+    load_record(&history_dir(&app)?, &operation_id)
+}
+
+/// Copy an operation's full record to `dest_path` as pretty-printed JSON.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn export_operation(
+    app: AppHandle,
+    operation_id: String,
+    dest_path: String,
+) -> Result<(), GeekFlashError> {
+    let record = load_record(&history_dir(&app)?, &operation_id)?;
+    let json = serde_json::to_string_pretty(&record)
+        .map_err(|e| GeekFlashError::io("serialize operation record", e))?;
+    fs::write(&dest_path, json).map_err(|e| fs_error("write export file", e))
+}
@@ -1,7 +1,11 @@
-use tauri::AppHandle;
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, State};
 
-use crate::executor::execute_sequence;
-use crate::models::{AvbParams, CommandResult, CommandSpec};
+#[cfg(feature = "gui")]
+use crate::executor::{run_operation, OperationRegistry, OperationRequest};
+use crate::models::{AvbParams, CommandSpec, OutputMatchers, StepKind};
+#[cfg(feature = "gui")]
+use crate::models::{CommandResult, GeekFlashError};
 
 /// Build the command sequence for AVB signing via python3 avbtool.
 ///
@@ -26,6 +30,11 @@ pub fn build_sign_avb_commands(
         ],
         sudo: false,
         delay_before_ms: 0,
+        wait_for: None,
+        kind: StepKind::Sign,
+        timeout_ms: None,
+        matchers: OutputMatchers::default(),
+        post_step: None,
     };
 
     // Command 2: add_hash_footer with all signing parameters
@@ -59,19 +68,38 @@ pub fn build_sign_avb_commands(
         args: add_args,
         sudo: false,
         delay_before_ms: 0,
+        wait_for: None,
+        kind: StepKind::Sign,
+        timeout_ms: None,
+        matchers: OutputMatchers::default(),
+        post_step: None,
     };
 
     vec![erase_cmd, add_cmd]
 }
 
+#[cfg(feature = "gui")]
 #[tauri::command]
 pub async fn sign_avb(
     app: AppHandle,
+    registry: State<'_, OperationRegistry>,
     boot_img: String,
     avbtool_path: String,
     key_path: String,
     params: AvbParams,
-) -> Result<CommandResult, String> {
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
     let commands = build_sign_avb_commands(&boot_img, &avbtool_path, &key_path, &params);
-    execute_sequence(&app, commands).await
+    let request = OperationRequest {
+        kind: "sign_avb",
+        inputs: vec![
+            ("boot_img", &boot_img),
+            ("avbtool", &avbtool_path),
+            ("key", &key_path),
+        ],
+        serial: None,
+        commands,
+        dry_run: dry_run.unwrap_or(false),
+    };
+    run_operation(&app, &registry, request).await
 }
@@ -0,0 +1,481 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, State};
+
+use crate::audit::sha256_file;
+#[cfg(feature = "gui")]
+use crate::config::app_data_dir;
+use crate::config::fs_error;
+#[cfg(feature = "gui")]
+use crate::executor::{run_operation, OperationRegistry, OperationRequest};
+use crate::flasher::build_flash_partition_commands;
+#[cfg(feature = "gui")]
+use crate::models::CommandResult;
+use crate::models::{
+    BackupManifest, BackupSet, CommandSpec, DeviceTarget, FlashTransport, GeekFlashError,
+    OutputMatchers, PartitionBackup, PostStep, StepKind,
+};
+
+/// Backups of device data, under `$APP_DATA_DIR`.
+const BACKUPS_DIR_NAME: &str = "backups";
+
+/// Lists what a backup directory holds; directories without one (GPT
+/// dumps) are not offered for restore.
+const MANIFEST_FILE_NAME: &str = "backup.json";
+
+#[cfg(feature = "gui")]
+pub fn backups_dir(app: &AppHandle) -> Result<PathBuf, GeekFlashError> {
+    Ok(backups_dir_in(&app_data_dir(app)?))
+}
+
+/// The backups directory inside an app data directory.
+pub fn backups_dir_in(data_dir: &Path) -> PathBuf {
+    data_dir.join(BACKUPS_DIR_NAME)
+}
+
+/// Keep a path component to `[A-Za-z0-9_-]`.
+fn path_safe(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// A new `<root>/<serial>/<timestamp>-<label>` directory path (not created),
+/// with `unknown` standing in for a missing serial. The timestamp has
+/// millisecond resolution.
+pub fn backup_dir_for(root: &Path, serial: Option<&str>, label: &str) -> PathBuf {
+    let serial = serial
+        .map(path_safe)
+        .unwrap_or_else(|| "unknown".to_string());
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f");
+    root.join(serial)
+        .join(format!("{stamp}-{}", path_safe(label)))
+}
+
+/// Create the backup directory `dir`, failing if it already exists rather
+/// than mixing two operations' dumps.
+pub fn create_backup_dir(dir: &Path) -> Result<(), GeekFlashError> {
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent).map_err(|e| fs_error("create backup directory", e))?;
+    }
+    fs::create_dir(dir).map_err(|e| fs_error("create backup directory", e))
+}
+
+fn load_manifest(dir: &Path) -> Result<BackupManifest, GeekFlashError> {
+    let data = fs::read_to_string(dir.join(MANIFEST_FILE_NAME))
+        .map_err(|e| fs_error("read backup manifest", e))?;
+    serde_json::from_str(&data).map_err(|e| GeekFlashError::io("parse backup manifest", e))
+}
+
+fn save_manifest(dir: &Path, manifest: &BackupManifest) -> Result<(), GeekFlashError> {
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| GeekFlashError::io("serialize backup manifest", e))?;
+    fs::write(dir.join(MANIFEST_FILE_NAME), json).map_err(|e| fs_error("write backup manifest", e))
+}
+
+/// Where `operation` saves the partitions it is about to overwrite.
+///
+/// Created up front with an empty manifest, since `edl` runs as root and
+/// only writes the dumps; a dry run just returns the path.
+pub fn create_backup_set(
+    root: &Path,
+    serial: Option<&str>,
+    operation: &str,
+    dry_run: bool,
+) -> Result<PathBuf, GeekFlashError> {
+    let dir = backup_dir_for(root, serial, operation);
+    if !dry_run {
+        create_backup_dir(&dir)?;
+        let manifest = BackupManifest {
+            operation: operation.to_string(),
+            serial: serial.map(str::to_string),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            partitions: Vec::new(),
+        };
+        save_manifest(&dir, &manifest)?;
+    }
+    Ok(dir)
+}
+
+/// Read every partition an `edl w` in `commands` overwrites into `dir`
+/// first, with the same loader and target arguments.
+///
+/// All reads go before the first write, so a failed read aborts the
+/// sequence before anything has been written.
+pub fn with_partition_backups(commands: Vec<CommandSpec>, dir: &Path) -> Vec<CommandSpec> {
+    // `edl w <partition> <image> ...`
+    let is_write = |c: &CommandSpec| {
+        c.program == "edl" && c.args.len() >= 3 && c.args.first().is_some_and(|a| a == "w")
+    };
+    let Some(first_write) = commands.iter().position(is_write) else {
+        return commands;
+    };
+
+    let mut commands = commands;
+    let mut reads: Vec<CommandSpec> = commands
+        .iter()
+        .filter(|c| is_write(c))
+        .map(|write| {
+            let partition = write.args[1].clone();
+            let path = dir
+                .join(format!("{}.img", path_safe(&partition)))
+                .to_string_lossy()
+                .into_owned();
+            let mut args = vec!["r".to_string(), partition.clone(), path.clone()];
+            args.extend_from_slice(&write.args[3..]);
+            CommandSpec {
+                program: "edl".to_string(),
+                args,
+                sudo: write.sudo,
+                delay_before_ms: 0,
+                wait_for: None,
+                kind: StepKind::EdlRead,
+                timeout_ms: None,
+                matchers: OutputMatchers::edl_read(),
+                post_step: Some(PostStep::RecordBackup { partition, path }),
+            }
+        })
+        .collect();
+
+    // The first read now waits for the device in place of the first write
+    let write = &mut commands[first_write];
+    reads[0].wait_for = write.wait_for.take();
+    reads[0].delay_before_ms = std::mem::take(&mut write.delay_before_ms);
+    commands.splice(first_write..first_write, reads);
+    commands
+}
+
+/// Hash the dump of `partition` at `path` and add it to the manifest in
+/// its directory. An empty or missing dump is an error, so the write that
+/// follows does not run.
+pub fn record_partition_backup(partition: &str, path: &str) -> Result<Vec<String>, GeekFlashError> {
+    let file = Path::new(path);
+    let size_bytes = fs::metadata(file)
+        .map_err(|e| fs_error("read partition backup", e))?
+        .len();
+    if size_bytes == 0 {
+        return Err(GeekFlashError::io(
+            "read partition backup",
+            format!("{path} is empty"),
+        ));
+    }
+    let sha256 = sha256_file(file).map_err(|e| fs_error("hash partition backup", e))?;
+
+    let dir = file.parent().unwrap_or(Path::new("."));
+    let mut manifest = load_manifest(dir)?;
+    manifest.partitions.retain(|b| b.partition != partition);
+    manifest.partitions.push(PartitionBackup {
+        partition: partition.to_string(),
+        path: path.to_string(),
+        size_bytes,
+        sha256: sha256.clone(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    });
+    save_manifest(dir, &manifest)?;
+    Ok(vec![format!(
+        "Backed up {partition} to {path} ({size_bytes} bytes, sha256 {sha256})"
+    )])
+}
+
+/// Every backup under `root` with at least one partition, newest first.
+/// Unreadable manifests are skipped.
+pub fn list_backups_in(root: &Path) -> Vec<BackupSet> {
+    let Ok(serials) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut sets: Vec<BackupSet> = serials
+        .flatten()
+        .filter_map(|serial| fs::read_dir(serial.path()).ok())
+        .flat_map(|dirs| dirs.flatten())
+        .filter_map(|dir| {
+            let manifest = load_manifest(&dir.path()).ok()?;
+            Some(BackupSet {
+                dir: dir.path().to_string_lossy().into_owned(),
+                manifest,
+            })
+        })
+        .filter(|set| !set.manifest.partitions.is_empty())
+        .collect();
+    sets.sort_by(|a, b| b.manifest.created_at.cmp(&a.manifest.created_at));
+    sets
+}
+
+/// Refuse a dump whose contents changed since it was recorded.
+pub fn verify_backup(backup: &PartitionBackup) -> Result<(), GeekFlashError> {
+    let sha256 =
+        sha256_file(Path::new(&backup.path)).map_err(|e| fs_error("hash partition backup", e))?;
+    if sha256 != backup.sha256 {
+        return Err(GeekFlashError::invalid_input(
+            "backup",
+            format!(
+                "{} has changed since it was saved (sha256 {sha256}, expected {})",
+                backup.path, backup.sha256
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The dumps of `partitions` (all, if empty) in the backup in `dir`, after
+/// checking each one's hash.
+pub fn selected_backups(
+    dir: &str,
+    partitions: &[String],
+) -> Result<Vec<PartitionBackup>, GeekFlashError> {
+    let manifest = load_manifest(Path::new(dir))?;
+    if let Some(missing) = partitions
+        .iter()
+        .find(|p| !manifest.partitions.iter().any(|b| &b.partition == *p))
+    {
+        return Err(GeekFlashError::invalid_input(
+            "partitions",
+            format!("{dir} has no backup of {missing}"),
+        ));
+    }
+
+    let backups: Vec<PartitionBackup> = manifest
+        .partitions
+        .into_iter()
+        .filter(|b| partitions.is_empty() || partitions.contains(&b.partition))
+        .collect();
+    for backup in &backups {
+        verify_backup(backup)?;
+    }
+    Ok(backups)
+}
+
+/// `edl w` steps putting `backups` back on the device.
+pub fn build_restore_commands(
+    backups: &[PartitionBackup],
+    loader: &str,
+    target: &DeviceTarget,
+) -> Vec<CommandSpec> {
+    let mut commands: Vec<CommandSpec> = backups
+        .iter()
+        .flat_map(|backup| {
+            build_flash_partition_commands(
+                &backup.path,
+                std::slice::from_ref(&backup.partition),
+                FlashTransport::Edl,
+                loader,
+                target,
+            )
+        })
+        .collect();
+    // Only the first write has to wait for the device to enumerate
+    for command in commands.iter_mut().skip(1) {
+        command.wait_for = None;
+    }
+    commands
+}
+
+/// What a restore reads: the loader and every dump it writes back.
+pub fn restore_inputs<'a>(
+    backups: &'a [PartitionBackup],
+    loader: &'a str,
+) -> Vec<(&'a str, &'a str)> {
+    let mut inputs = vec![("loader", loader)];
+    inputs.extend(
+        backups
+            .iter()
+            .map(|b| (b.partition.as_str(), b.path.as_str())),
+    );
+    inputs
+}
+
+// === Tauri Commands ===
+
+/// Partition backups that can be restored, newest first.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn list_backups(app: AppHandle) -> Result<Vec<BackupSet>, GeekFlashError> {
+    Ok(list_backups_in(&backups_dir(&app)?))
+}
+
+/// Write the partitions saved in backup directory `dir` back over EDL. The
+/// restore is filed under the serial the backup was taken from unless
+/// `serial` says otherwise; `edl_serial` pins the `edl` calls to one unit.
+/// The current contents are backed up first, like any other write.
+#[cfg(feature = "gui")]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn restore_backup(
+    app: AppHandle,
+    registry: State<'_, OperationRegistry>,
+    dir: String,
+    partitions: Option<Vec<String>>,
+    loader: String,
+    serial: Option<String>,
+    edl_serial: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    let serial = match serial {
+        Some(serial) => Some(serial),
+        None => load_manifest(Path::new(&dir))?.serial,
+    };
+    let dry_run = dry_run.unwrap_or(false);
+    // Already in EDL mode, where the unit no longer reports `serial`
+    let target = DeviceTarget {
+        serial: serial.clone(),
+        location: None,
+        edl_serial,
+    };
+    let backups = selected_backups(&dir, &partitions.unwrap_or_default())?;
+    let commands = build_restore_commands(&backups, &loader, &target);
+    let backup_dir = create_backup_set(
+        &backups_dir(&app)?,
+        serial.as_deref(),
+        "restore_backup",
+        dry_run,
+    )?;
+    let request = OperationRequest {
+        kind: "restore_backup",
+        inputs: restore_inputs(&backups, &loader),
+        serial: serial.as_deref(),
+        commands: with_partition_backups(commands, &backup_dir),
+        dry_run,
+    };
+    run_operation(&app, &registry, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flasher::build_flash_boot_commands;
+    use crate::models::Slot;
+    use crate::plan::command_line;
+
+    #[test]
+    fn test_backup_dir_layout() {
+        let dir = backup_dir_for(Path::new("/data/backups"), Some("a1/../b"), "gpt-lun4");
+        let parts: Vec<String> = dir
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(parts[..4], ["/", "data", "backups", "a1____b"]);
+        assert!(parts[4].ends_with("-gpt-lun4"), "{}", parts[4]);
+
+        let dir = backup_dir_for(Path::new("/b"), None, "boot_a");
+        assert!(dir.starts_with("/b/unknown"));
+    }
+
+    #[test]
+    fn test_backup_dir_is_never_reused() {
+        let root = tempfile::TempDir::new().unwrap();
+        let dir = root
+            .path()
+            .join("1234")
+            .join("20261018-101500.000-flash_boot");
+        create_backup_dir(&dir).unwrap();
+        assert!(create_backup_dir(&dir).is_err());
+    }
+
+    #[test]
+    fn test_every_write_is_preceded_by_a_read() {
+        let commands = build_flash_boot_commands(
+            "boot.img",
+            "prog.elf",
+            &[Slot::A, Slot::B],
+            &DeviceTarget::default(),
+        );
+        let commands = with_partition_backups(commands, Path::new("/bk"));
+        let lines: Vec<String> = commands.iter().map(command_line).collect();
+        assert_eq!(
+            lines,
+            [
+                "fastboot oem edl",
+                "sudo edl r boot_a /bk/boot_a.img --loader=prog.elf",
+                "sudo edl r boot_b /bk/boot_b.img --loader=prog.elf",
+                "sudo edl w boot_a boot.img --loader=prog.elf",
+                "sudo edl w boot_b boot.img --loader=prog.elf",
+            ]
+        );
+        assert!(commands[1].wait_for.is_some());
+        assert!(commands[3].wait_for.is_none());
+        assert_eq!(
+            commands[2].post_step,
+            Some(PostStep::RecordBackup {
+                partition: "boot_b".to_string(),
+                path: "/bk/boot_b.img".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_sequence_without_a_full_write_is_unchanged() {
+        let mut commands =
+            build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+        commands[1].args.truncate(2);
+        let lines: Vec<String> = commands.iter().map(command_line).collect();
+
+        let commands = with_partition_backups(commands, Path::new("/bk"));
+        assert_eq!(commands.iter().map(command_line).collect::<Vec<_>>(), lines);
+    }
+
+    #[test]
+    fn test_record_verify_and_restore() {
+        let root = tempfile::TempDir::new().unwrap();
+        let dir = create_backup_set(root.path(), Some("1234"), "flash_boot", false).unwrap();
+        let dump = dir.join("boot_a.img");
+        fs::write(&dump, b"stock boot").unwrap();
+        let dump = dump.to_str().unwrap();
+
+        let lines = record_partition_backup("boot_a", dump).unwrap();
+        assert!(lines[0].starts_with("Backed up boot_a"), "{lines:?}");
+        let sets = list_backups_in(root.path());
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].manifest.serial.as_deref(), Some("1234"));
+        let backup = &sets[0].manifest.partitions[0];
+        assert_eq!(backup.size_bytes, 10);
+        assert!(verify_backup(backup).is_ok());
+
+        let dump_b = dir.join("boot_b.img");
+        fs::write(&dump_b, b"stock boot b").unwrap();
+        let dump_b = dump_b.to_str().unwrap();
+        record_partition_backup("boot_b", dump_b).unwrap();
+
+        let target = DeviceTarget {
+            serial: Some("1234".to_string()),
+            location: None,
+            edl_serial: Some("0xcafe".to_string()),
+        };
+        let backups = selected_backups(&sets[0].dir, &[]).unwrap();
+        let commands = build_restore_commands(&backups, "prog.elf", &target);
+        assert_eq!(
+            command_line(&commands[0]),
+            format!("sudo edl w boot_a {dump} --loader=prog.elf --serial_number=0xcafe")
+        );
+        assert_eq!(commands.len(), 2);
+        assert!(commands[0].wait_for.is_some());
+        assert!(commands[1].wait_for.is_none());
+        assert_eq!(
+            restore_inputs(&backups, "prog.elf"),
+            [("loader", "prog.elf"), ("boot_a", dump), ("boot_b", dump_b)]
+        );
+        let err = selected_backups(&sets[0].dir, &["dtbo_a".to_string()]).unwrap_err();
+        assert_eq!(err.context()["field"], "partitions");
+
+        // A dump changed on disk is not written back
+        fs::write(dump, b"other boot").unwrap();
+        let err = selected_backups(&sets[0].dir, &[]).unwrap_err();
+        assert!(err.to_string().contains("has changed"), "{err}");
+    }
+
+    #[test]
+    fn test_empty_dump_is_an_error() {
+        let root = tempfile::TempDir::new().unwrap();
+        let dir = create_backup_set(root.path(), None, "flash_boot", false).unwrap();
+        let dump = dir.join("boot_a.img");
+        fs::write(&dump, b"").unwrap();
+        assert!(record_partition_backup("boot_a", dump.to_str().unwrap()).is_err());
+        assert!(list_backups_in(root.path()).is_empty());
+    }
+}
@@ -0,0 +1,622 @@
+//! Headless front end: the same command builders and device detection as the
+//! GUI, driven from a terminal (CI rigs, SSH sessions).
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use geekflash_lib::audit::history_dir_in;
+use geekflash_lib::avb_signer::build_sign_avb_commands;
+use geekflash_lib::backup::{
+    backups_dir_in, build_restore_commands, create_backup_set, list_backups_in, restore_inputs,
+    selected_backups, with_partition_backups,
+};
+use geekflash_lib::config::{
+    config_file_in, default_app_data_dir, get_config_value, load_config_from, save_config_to,
+    set_config_value,
+};
+use geekflash_lib::device::{current_target, probe_device_status};
+use geekflash_lib::events::StdoutSink;
+use geekflash_lib::executor::{run_operation_with, OperationRegistry, OperationRequest};
+use geekflash_lib::flasher::{
+    build_boot_write_commands, build_flash_partition_commands, check_image_fits, connect_target,
+    fit_partitions, selected_partitions,
+};
+use geekflash_lib::gpt::{
+    generated_backup_pair, generated_gpt_dir_in, gpt_diff, load_gpt, write_generated_backup,
+    SLOT_PARTITION,
+};
+use geekflash_lib::gpt_fixer::{
+    build_fix_gpt_commands, prepare_backup_dir, GptPlacement, GptWriteOptions, StagedGpt,
+    DEFAULT_GPT_LUN,
+};
+use geekflash_lib::models::{
+    AppConfig, DeviceTarget, FlashTransport, GeekFlashError, GptRole, SlotSelector,
+};
+use geekflash_lib::runner::SystemRunner;
+
+const NO_DATA_DIR: &str = "cannot locate the app data directory";
+
+const USAGE: &str = "\
+Usage: geekflash-cli [--config <path>] <command> [options]
+
+Commands:
+  status [--json]                       Show connected devices
+  flash-boot   [--boot <img>] [--loader <elf>] [--serial <s>] [--edl-serial <s>]
+               [--slot <slot>] [--dry-run]
+  restore-boot [--boot <img>] [--loader <elf>] [--serial <s>] [--edl-serial <s>]
+               [--slot <slot>] [--dry-run]
+               <slot> is a (default), b, both or active
+  flash-partition --partition <name> --image <img> [--gpt <bin>] [--slot <slot>]
+               [--transport edl|fastboot] [--loader <elf>] [--serial <s>]
+               [--edl-serial <s>] [--dry-run]
+                                        Write any partition; without --slot,
+                                        <name> is used as given
+  fix-gpt      [--main <bin>] [--backup <bin>] [--loader <elf>] [--serial <s>] [--lun <n>]
+               [--no-check-device] [--dry-run]
+  gen-gpt-backup [--main <bin>] [--disk-sectors <n>]
+                                        Build a backup GPT for a primary GPT
+  diff-gpt <old> <new> [--role primary|backup] [--json]
+                                        Compare two GPT images
+  backups [--json]                      List partition backups taken before writes
+  restore-backup <dir> [--partition <name>]... [--loader <elf>] [--serial <s>]
+               [--edl-serial <s>] [--dry-run]
+                                        Write a backup back (all partitions by default)
+  sign-avb     [--boot <img>] [--avbtool <path>] [--key <pem>] [--dry-run]
+  config get [<key>]                    Print the config, or one dotted key
+  config set <key> <value>              Change one dotted key
+
+File options default to the paths saved in the GUI's config. Without a
+backup GPT, fix-gpt generates one from the primary. --serial is the
+fastboot serial; once the tablet is in EDL mode it is found again on the
+same USB port, and --edl-serial (the chip serial) is passed to edl.";
+
+/// Parsed `--name value` options and bare flags, in order of appearance.
+struct Options {
+    values: Vec<(String, String)>,
+    flags: Vec<String>,
+    positional: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        const FLAGS: [&str; 3] = ["--dry-run", "--json", "--no-check-device"];
+        let mut opts = Options {
+            values: Vec::new(),
+            flags: Vec::new(),
+            positional: Vec::new(),
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if FLAGS.contains(&arg.as_str()) {
+                opts.flags.push(arg.clone());
+            } else if let Some(name) = arg.strip_prefix("--") {
+                let value = match name.split_once('=') {
+                    Some((name, value)) => (name.to_string(), value.to_string()),
+                    None => {
+                        let value = iter.next().ok_or(format!("--{name} needs a value"))?;
+                        (name.to_string(), value.clone())
+                    }
+                };
+                opts.values.push(value);
+            } else {
+                opts.positional.push(arg.clone());
+            }
+        }
+        Ok(opts)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    /// `--name`, else the config default, else an error naming both.
+    fn path(&self, name: &str, default: &Option<String>) -> Result<String, String> {
+        self.value(name)
+            .map(str::to_string)
+            .or_else(|| default.clone())
+            .ok_or(format!("--{name} not given and not set in config"))
+    }
+
+    /// `--slot a|b|both|active`, if given.
+    fn slot(&self) -> Result<Option<SlotSelector>, String> {
+        match self.value("slot") {
+            None => Ok(None),
+            Some("a") => Ok(Some(SlotSelector::A)),
+            Some("b") => Ok(Some(SlotSelector::B)),
+            Some("both") => Ok(Some(SlotSelector::Both)),
+            Some("active") => Ok(Some(SlotSelector::Active)),
+            Some(other) => Err(format!("--slot: unknown slot \"{other}\"")),
+        }
+    }
+
+    fn reject_unknown(&self, known: &[&str]) -> Result<(), String> {
+        match self
+            .values
+            .iter()
+            .find(|(n, _)| !known.contains(&n.as_str()))
+        {
+            Some((name, _)) => Err(format!("unknown option --{name}")),
+            None => Ok(()),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(args).await {
+        Ok(code) => code,
+        Err(CliError::Usage(msg)) => {
+            eprintln!("error: {msg}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(CliError::Failed(e)) => {
+            eprintln!("error [{}]: {e}", e.code());
+            eprintln!("hint: {}", e.recovery());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+enum CliError {
+    Usage(String),
+    Failed(GeekFlashError),
+}
+
+impl From<String> for CliError {
+    fn from(msg: String) -> Self {
+        CliError::Usage(msg)
+    }
+}
+
+impl From<GeekFlashError> for CliError {
+    fn from(e: GeekFlashError) -> Self {
+        CliError::Failed(e)
+    }
+}
+
+async fn run(mut args: Vec<String>) -> Result<ExitCode, CliError> {
+    let mut config_path = None;
+    if args.first().map(String::as_str) == Some("--config") {
+        if args.len() < 2 {
+            return Err("--config needs a path".to_string().into());
+        }
+        config_path = Some(PathBuf::from(args[1].clone()));
+        args.drain(..2);
+    }
+    let data_dir = default_app_data_dir();
+    let config_path = match (config_path, &data_dir) {
+        (Some(p), _) => p,
+        (None, Some(dir)) => config_file_in(dir),
+        (None, None) => return Err(NO_DATA_DIR.to_string().into()),
+    };
+    let config = load_config_from(&config_path);
+    let history = data_dir.as_deref().map(history_dir_in);
+    let backups = data_dir.as_deref().map(backups_dir_in);
+    let generated = data_dir.as_deref().map(generated_gpt_dir_in);
+
+    let Some((command, rest)) = args.split_first() else {
+        println!("{USAGE}");
+        return Ok(ExitCode::SUCCESS);
+    };
+    let opts = Options::parse(rest)?;
+
+    match command.as_str() {
+        "status" => status(opts.flag("--json")),
+        "flash-boot" | "restore-boot" => {
+            opts.reject_unknown(&["boot", "loader", "serial", "edl-serial", "slot"])?;
+            let default_boot = if command == "flash-boot" {
+                &config.patched_boot_img
+            } else {
+                &config.stock_boot_img
+            };
+            let boot = opts.path("boot", default_boot)?;
+            let loader = opts.path("loader", &config.firehose_loader)?;
+            let serial = opts.value("serial");
+            let dry_run = opts.flag("--dry-run");
+            let partitions = selected_partitions(
+                &StdoutSink::default(),
+                &SystemRunner,
+                SLOT_PARTITION,
+                opts.slot()?.unwrap_or_default(),
+                serial,
+                config.gpt_main_bin.as_deref(),
+                dry_run,
+            )
+            .await?;
+            let target = current_target(serial, opts.value("edl-serial"), dry_run)?;
+            let commands = build_boot_write_commands(&boot, &loader, &partitions, &target);
+            let kind = command.replace('-', "_");
+            let backup_dir = create_backup_set(
+                backups.as_deref().ok_or(NO_DATA_DIR.to_string())?,
+                serial,
+                &kind,
+                dry_run,
+            )?;
+            let request = OperationRequest {
+                kind: &kind,
+                inputs: vec![("boot_img", &boot), ("loader", &loader)],
+                serial,
+                commands: with_partition_backups(commands, &backup_dir),
+                dry_run,
+            };
+            run_operation(history, request).await
+        }
+        "flash-partition" => {
+            opts.reject_unknown(&[
+                "partition",
+                "image",
+                "gpt",
+                "slot",
+                "transport",
+                "loader",
+                "serial",
+                "edl-serial",
+            ])?;
+            let partition = opts
+                .value("partition")
+                .ok_or("--partition not given".to_string())?;
+            let image = opts
+                .value("image")
+                .ok_or("--image not given".to_string())?
+                .to_string();
+            let gpt = opts.path("gpt", &config.gpt_main_bin)?;
+            let serial = opts.value("serial");
+            let transport = match opts.value("transport") {
+                None => None,
+                Some("edl") => Some(FlashTransport::Edl),
+                Some("fastboot") => Some(FlashTransport::Fastboot),
+                Some(other) => {
+                    return Err(format!("--transport: unknown transport \"{other}\"").into())
+                }
+            };
+
+            let dry_run = opts.flag("--dry-run");
+            let table = load_gpt("gpt", &gpt, GptRole::Primary)?;
+            let partitions = match opts.slot()? {
+                Some(selector) => {
+                    selected_partitions(
+                        &StdoutSink::default(),
+                        &SystemRunner,
+                        partition,
+                        selector,
+                        serial,
+                        Some(&gpt),
+                        dry_run,
+                    )
+                    .await?
+                }
+                None => vec![partition.to_string()],
+            };
+            check_image_fits(
+                &table,
+                &gpt,
+                &fit_partitions(partition, &partitions),
+                &image,
+            )?;
+            let (transport, target) = connect_target(
+                &StdoutSink::default(),
+                transport,
+                serial,
+                opts.value("edl-serial"),
+                dry_run,
+            )?;
+            let mut inputs = vec![("image", image.as_str())];
+            let loader = match transport {
+                FlashTransport::Edl => opts.path("loader", &config.firehose_loader)?,
+                FlashTransport::Fastboot => String::new(),
+            };
+            if transport == FlashTransport::Edl {
+                inputs.push(("loader", &loader));
+            }
+            let mut commands =
+                build_flash_partition_commands(&image, &partitions, transport, &loader, &target);
+            if transport == FlashTransport::Edl {
+                let backup_dir = create_backup_set(
+                    backups.as_deref().ok_or(NO_DATA_DIR.to_string())?,
+                    serial,
+                    "flash_partition",
+                    dry_run,
+                )?;
+                commands = with_partition_backups(commands, &backup_dir);
+            }
+            let request = OperationRequest {
+                kind: "flash_partition",
+                inputs,
+                serial,
+                commands,
+                dry_run,
+            };
+            run_operation(history, request).await
+        }
+        "backups" => {
+            let root = backups.as_deref().ok_or(NO_DATA_DIR.to_string())?;
+            let sets = list_backups_in(root);
+            if opts.flag("--json") {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&sets).unwrap_or_default()
+                );
+                return Ok(ExitCode::SUCCESS);
+            }
+            if sets.is_empty() {
+                println!("No partition backups");
+            }
+            for set in &sets {
+                let partitions: Vec<&str> = set
+                    .manifest
+                    .partitions
+                    .iter()
+                    .map(|b| b.partition.as_str())
+                    .collect();
+                println!(
+                    "{}  {:<16} serial={}  {}\n  {}",
+                    set.manifest.created_at,
+                    set.manifest.operation,
+                    set.manifest.serial.as_deref().unwrap_or("-"),
+                    partitions.join(","),
+                    set.dir
+                );
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        "restore-backup" => {
+            opts.reject_unknown(&["partition", "loader", "serial", "edl-serial"])?;
+            let [dir] = opts.positional.as_slice() else {
+                return Err("restore-backup needs a backup directory".to_string().into());
+            };
+            let partitions: Vec<String> = opts
+                .values
+                .iter()
+                .filter(|(n, _)| n == "partition")
+                .map(|(_, v)| v.clone())
+                .collect();
+            let loader = opts.path("loader", &config.firehose_loader)?;
+            let serial = match opts.value("serial") {
+                Some(serial) => Some(serial.to_string()),
+                None => list_backups_in(backups.as_deref().ok_or(NO_DATA_DIR.to_string())?)
+                    .into_iter()
+                    .find(|set| &set.dir == dir)
+                    .and_then(|set| set.manifest.serial),
+            };
+            let target = DeviceTarget {
+                serial: serial.clone(),
+                location: None,
+                edl_serial: opts.value("edl-serial").map(str::to_string),
+            };
+            let serial = serial.as_deref();
+            let dry_run = opts.flag("--dry-run");
+            let dumps = selected_backups(dir, &partitions)?;
+            let commands = build_restore_commands(&dumps, &loader, &target);
+            let backup_dir = create_backup_set(
+                backups.as_deref().ok_or(NO_DATA_DIR.to_string())?,
+                serial,
+                "restore_backup",
+                dry_run,
+            )?;
+            let request = OperationRequest {
+                kind: "restore_backup",
+                inputs: restore_inputs(&dumps, &loader),
+                serial,
+                commands: with_partition_backups(commands, &backup_dir),
+                dry_run,
+            };
+            run_operation(history, request).await
+        }
+        "fix-gpt" => {
+            opts.reject_unknown(&["main", "backup", "loader", "serial", "lun"])?;
+            let main = opts.path("main", &config.gpt_main_bin)?;
+            let backup = opts
+                .value("backup")
+                .map(str::to_string)
+                .or_else(|| config.gpt_backup_bin.clone());
+            let loader = opts.path("loader", &config.firehose_loader)?;
+            let serial = opts.value("serial");
+            let lun = match opts.value("lun") {
+                Some(v) => v
+                    .parse()
+                    .map_err(|_| format!("--lun: \"{v}\" is not a number"))?,
+                None => DEFAULT_GPT_LUN,
+            };
+            let dry_run = opts.flag("--dry-run");
+            let pair = match backup {
+                Some(backup) => StagedGpt::from_files(&main, &backup)?,
+                None => {
+                    let generated = generated.as_deref().ok_or(NO_DATA_DIR.to_string())?;
+                    let pair = StagedGpt::generated(
+                        generated,
+                        generated_backup_pair(&main, None)?,
+                        dry_run,
+                    )?;
+                    if pair.saved {
+                        eprintln!("Generated backup GPT {}", pair.gpt_backup);
+                    } else {
+                        eprintln!("Would generate backup GPT {}", pair.gpt_backup);
+                    }
+                    pair
+                }
+            };
+            let placement = GptPlacement::from_tables(&pair.main, &pair.backup, lun);
+            let backups = backups
+                .as_deref()
+                .ok_or("cannot locate the backups directory".to_string())?;
+            let options = GptWriteOptions {
+                check_device: !opts.flag("--no-check-device"),
+                backup_dir: Some(prepare_backup_dir(backups, serial, lun, dry_run)?),
+            };
+            let commands = build_fix_gpt_commands(
+                &pair.gpt_main,
+                &pair.gpt_backup,
+                &loader,
+                &placement,
+                &options,
+                serial,
+            );
+            let request = OperationRequest {
+                kind: "fix_gpt_slot",
+                inputs: pair.inputs(&main, &loader),
+                serial,
+                commands,
+                dry_run,
+            };
+            run_operation(history, request).await
+        }
+        "gen-gpt-backup" => {
+            opts.reject_unknown(&["main", "disk-sectors"])?;
+            let main = opts.path("main", &config.gpt_main_bin)?;
+            let disk_sectors = match opts.value("disk-sectors") {
+                Some(v) => Some(
+                    v.parse()
+                        .map_err(|_| format!("--disk-sectors: \"{v}\" is not a number"))?,
+                ),
+                None => None,
+            };
+            let generated = generated.as_deref().ok_or(NO_DATA_DIR.to_string())?;
+            let (main, backup) = write_generated_backup(generated, &main, disk_sectors)?;
+            println!("gpt_main   {main}\ngpt_backup {backup}");
+            Ok(ExitCode::SUCCESS)
+        }
+        "diff-gpt" => {
+            opts.reject_unknown(&["role"])?;
+            let [old, new] = opts.positional.as_slice() else {
+                return Err("diff-gpt needs two GPT files".to_string().into());
+            };
+            let role = match opts.value("role") {
+                None | Some("primary") => GptRole::Primary,
+                Some("backup") => GptRole::Backup,
+                Some(other) => return Err(format!("--role: unknown role \"{other}\"").into()),
+            };
+            let diff = gpt_diff(
+                old,
+                &load_gpt("old", old, role)?,
+                new,
+                &load_gpt("new", new, role)?,
+            );
+            if opts.flag("--json") {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&diff).unwrap_or_default()
+                );
+            } else if diff.lines.is_empty() {
+                println!("No differences");
+            } else {
+                println!("{}", diff.lines.join("\n"));
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        "sign-avb" => {
+            opts.reject_unknown(&["boot", "avbtool", "key"])?;
+            let boot = opts.path("boot", &config.patched_boot_img)?;
+            let avbtool = opts.path("avbtool", &config.avbtool_path)?;
+            let key = opts.path("key", &config.avb_key_path)?;
+            let commands = build_sign_avb_commands(&boot, &avbtool, &key, &config.avb_params);
+            let request = OperationRequest {
+                kind: "sign_avb",
+                inputs: vec![("boot_img", &boot), ("avbtool", &avbtool), ("key", &key)],
+                serial: None,
+                commands,
+                dry_run: opts.flag("--dry-run"),
+            };
+            run_operation(history, request).await
+        }
+        "config" => config_command(&config, &config_path, &opts.positional),
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        other => Err(format!("unknown command \"{other}\"").into()),
+    }
+}
+
+fn status(json: bool) -> Result<ExitCode, CliError> {
+    let status = probe_device_status()?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&status).unwrap_or_default()
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if status.devices.is_empty() {
+        println!("No devices connected");
+    }
+    for d in &status.devices {
+        println!(
+            "{:<18} {:04x}:{:04x}  serial={}  location={}",
+            format!("{:?}", d.mode),
+            d.vendor_id,
+            d.product_id,
+            d.serial.as_deref().unwrap_or("-"),
+            d.location
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn config_command(
+    config: &AppConfig,
+    path: &std::path::Path,
+    args: &[String],
+) -> Result<ExitCode, CliError> {
+    match args {
+        [get] if get == "get" => {
+            let json = serde_json::to_string_pretty(config).unwrap_or_default();
+            println!("{json}");
+        }
+        [get, key] if get == "get" => {
+            let value = get_config_value(config, key)?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&value).unwrap_or_default()
+            );
+        }
+        [set, key, value] if set == "set" => {
+            let updated = set_config_value(config, key, value)?;
+            save_config_to(path, &updated)?;
+            println!("{key} updated in {}", path.display());
+        }
+        _ => {
+            return Err("usage: config get [<key>] | config set <key> <value>"
+                .to_string()
+                .into())
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Run an operation with terminal output, recording it in the same history
+/// as the GUI. Ctrl-C cancels it the way the GUI's cancel button does.
+async fn run_operation(
+    history: Option<PathBuf>,
+    request: OperationRequest<'_>,
+) -> Result<ExitCode, CliError> {
+    let dry_run = request.dry_run;
+    let kind = request.kind.to_string();
+    let registry = OperationRegistry::default();
+    let sink = StdoutSink::default();
+
+    let run = run_operation_with(&sink, &SystemRunner, &registry, history, request);
+    tokio::pin!(run);
+    let result = tokio::select! {
+        r = &mut run => r,
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("Cancelling...");
+            registry.cancel_all();
+            run.await
+        }
+    }?;
+
+    if !dry_run {
+        eprintln!("{kind} completed in {}ms", result.duration_ms);
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -1,33 +1,80 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+#[cfg(feature = "gui")]
 use tauri::{AppHandle, Manager};
 
-use crate::models::AppConfig;
+use crate::models::{AppConfig, GeekFlashError};
 
 const CONFIG_FILE_NAME: &str = "config.json";
 
-/// Get the config file path: `$APP_DATA_DIR/config.json`
-fn config_path(app: &AppHandle) -> Result<PathBuf, String> {
-    let dir = app
-        .path()
+/// Bundle identifier from `tauri.conf.json`; names the app data directory.
+pub const APP_IDENTIFIER: &str = "com.geekflash.app";
+
+/// Resolve `$APP_DATA_DIR`, where config and history are stored.
+#[cfg(feature = "gui")]
+pub fn app_data_dir(app: &AppHandle) -> Result<PathBuf, GeekFlashError> {
+    app.path()
         .app_data_dir()
-        .map_err(|e| format!("Failed to resolve app data dir: {e}"))?;
-    Ok(dir.join(CONFIG_FILE_NAME))
+        .map_err(|e| GeekFlashError::io("resolve app data dir", e))
+}
+
+/// `$APP_DATA_DIR` resolved without a running app (for the CLI), the same
+/// way Tauri does: the platform data dir joined with [`APP_IDENTIFIER`].
+pub fn default_app_data_dir() -> Option<PathBuf> {
+    let env_dir = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+    let home = env_dir("HOME").map(PathBuf::from);
+
+    let data_dir = if cfg!(target_os = "macos") {
+        home.map(|h| h.join("Library/Application Support"))
+    } else if cfg!(windows) {
+        env_dir("APPDATA").map(PathBuf::from)
+    } else {
+        env_dir("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|h| h.join(".local/share")))
+    };
+    data_dir.map(|d| d.join(APP_IDENTIFIER))
+}
+
+/// The config file inside an app data directory.
+pub fn config_file_in(dir: &Path) -> PathBuf {
+    dir.join(CONFIG_FILE_NAME)
+}
+
+/// Get the config file path: `$APP_DATA_DIR/config.json`
+#[cfg(feature = "gui")]
+fn config_path(app: &AppHandle) -> Result<PathBuf, GeekFlashError> {
+    Ok(config_file_in(&app_data_dir(app)?))
+}
+
+/// Map a filesystem error, keeping permission problems distinct.
+pub fn fs_error(context: &str, e: std::io::Error) -> GeekFlashError {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        GeekFlashError::PermissionDenied {
+            context: context.to_string(),
+        }
+    } else {
+        GeekFlashError::io(context, e)
+    }
 }
 
 /// Load config from disk. Returns `AppConfig::default()` on any error
 /// (missing file, corrupted JSON, IO error, etc.).
+#[cfg(feature = "gui")]
 pub fn load_config(app: &AppHandle) -> AppConfig {
-    let path = match config_path(app) {
-        Ok(p) => p,
+    match config_path(app) {
+        Ok(path) => load_config_from(&path),
         Err(e) => {
             eprintln!("[config] Warning: {e}, using default config");
-            return AppConfig::default();
+            AppConfig::default()
         }
-    };
+    }
+}
 
-    let data = match fs::read_to_string(&path) {
+/// [`load_config`] from an explicit path.
+pub fn load_config_from(path: &Path) -> AppConfig {
+    let data = match fs::read_to_string(path) {
         Ok(d) => d,
         Err(e) => {
             if e.kind() != std::io::ErrorKind::NotFound {
@@ -53,30 +100,79 @@ pub fn load_config(app: &AppHandle) -> AppConfig {
 }
 
 /// Save config to disk as JSON. Creates the parent directory if needed.
-pub fn save_config(app: &AppHandle, config: &AppConfig) -> Result<(), String> {
-    let path = config_path(app)?;
+#[cfg(feature = "gui")]
+pub fn save_config(app: &AppHandle, config: &AppConfig) -> Result<(), GeekFlashError> {
+    save_config_to(&config_path(app)?, config)
+}
 
+/// [`save_config`] to an explicit path.
+pub fn save_config_to(path: &Path, config: &AppConfig) -> Result<(), GeekFlashError> {
     if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)
-            .map_err(|e| format!("Failed to create config directory: {e}"))?;
+        fs::create_dir_all(parent).map_err(|e| fs_error("create config directory", e))?;
     }
 
-    let json =
-        serde_json::to_string_pretty(config).map_err(|e| format!("Failed to serialize config: {e}"))?;
+    let json = serde_json::to_string_pretty(config)
+        .map_err(|e| GeekFlashError::io("serialize config", e))?;
 
-    fs::write(&path, json).map_err(|e| format!("Failed to write config file: {e}"))?;
+    fs::write(path, json).map_err(|e| fs_error("write config file", e))?;
 
     Ok(())
 }
 
+/// Look up a config value by dotted key, e.g. `avb_params.partition_size`.
+pub fn get_config_value(
+    config: &AppConfig,
+    key: &str,
+) -> Result<serde_json::Value, GeekFlashError> {
+    let root =
+        serde_json::to_value(config).map_err(|e| GeekFlashError::io("serialize config", e))?;
+    key.split('.')
+        .try_fold(&root, |value, part| value.get(part))
+        .cloned()
+        .ok_or_else(|| {
+            GeekFlashError::invalid_input("key", format!("unknown config key \"{key}\""))
+        })
+}
+
+/// Return `config` with the value at dotted `key` replaced.
+///
+/// `raw` is parsed as JSON (`123`, `null`, `[...]`) if that fits the field,
+/// otherwise it is taken as a plain string.
+pub fn set_config_value(
+    config: &AppConfig,
+    key: &str,
+    raw: &str,
+) -> Result<AppConfig, GeekFlashError> {
+    let root =
+        serde_json::to_value(config).map_err(|e| GeekFlashError::io("serialize config", e))?;
+    let with_value = |value: serde_json::Value| {
+        let mut root = root.clone();
+        let slot = key
+            .split('.')
+            .try_fold(&mut root, |v, part| v.get_mut(part))
+            .ok_or_else(|| {
+                GeekFlashError::invalid_input("key", format!("unknown config key \"{key}\""))
+            })?;
+        *slot = value;
+        serde_json::from_value(root).map_err(|e| GeekFlashError::invalid_input(key, e.to_string()))
+    };
+
+    match serde_json::from_str(raw) {
+        Ok(parsed) => with_value(parsed).or_else(|_| with_value(raw.into())),
+        Err(_) => with_value(raw.into()),
+    }
+}
+
 // === Tauri Commands ===
 
+#[cfg(feature = "gui")]
 #[tauri::command]
-pub async fn cmd_load_config(app: AppHandle) -> Result<AppConfig, String> {
+pub async fn cmd_load_config(app: AppHandle) -> Result<AppConfig, GeekFlashError> {
     Ok(load_config(&app))
 }
 
+#[cfg(feature = "gui")]
 #[tauri::command]
-pub async fn cmd_save_config(app: AppHandle, config: AppConfig) -> Result<(), String> {
+pub async fn cmd_save_config(app: AppHandle, config: AppConfig) -> Result<(), GeekFlashError> {
     save_config(&app, &config)
 }
@@ -1,94 +1,560 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "gui")]
 use tauri::{AppHandle, Emitter};
 
-use crate::models::DeviceStatus;
+use crate::hotplug::{HotplugWatcher, Wake};
+use crate::models::{DeviceInfo, DeviceMode, DeviceStatus, DeviceTarget, GeekFlashError};
+
+/// Root of the Linux sysfs USB device tree.
+#[cfg(target_os = "linux")]
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+const QUALCOMM_VID: u16 = 0x05c6;
+const GOOGLE_VID: u16 = 0x18d1;
 
-/// Parse system_profiler output to detect device mode.
+/// Exact VID/PID → mode table.
 ///
-/// - Looks for "Vendor ID: 0x05c6" AND "Product ID: 0x9008" → "edl"
-/// - Looks for "fastboot" (case-insensitive) in device names → "fastboot"
-/// - Neither → "disconnected"
-pub fn parse_device_status(output: &str) -> DeviceStatus {
-    let timestamp = SystemTime::now()
+/// Qualcomm ABL enumerates bootloader fastboot as 18d1:d00d, while AOSP
+/// recovery uses 18d1:4ee0 for fastbootd, which is what lets us tell them apart.
+const MODE_TABLE: &[(u16, u16, DeviceMode)] = &[
+    (QUALCOMM_VID, 0x9008, DeviceMode::Edl),
+    (
+        QUALCOMM_VID,
+        0x900e,
+        DeviceMode::Diag { product_id: 0x900e },
+    ),
+    (
+        QUALCOMM_VID,
+        0x901d,
+        DeviceMode::Diag { product_id: 0x901d },
+    ),
+    (GOOGLE_VID, 0xd00d, DeviceMode::Fastboot),
+    (GOOGLE_VID, 0x4ee0, DeviceMode::Fastbootd),
+    (GOOGLE_VID, 0xd001, DeviceMode::Recovery),
+    (GOOGLE_VID, 0x4ee2, DeviceMode::Adb),
+    (GOOGLE_VID, 0x4ee4, DeviceMode::Adb),
+    (GOOGLE_VID, 0x4ee6, DeviceMode::Adb),
+    (GOOGLE_VID, 0x4ee7, DeviceMode::Adb),
+];
+
+/// A raw USB device as enumerated by the host OS, before mode detection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+    pub product: Option<String>,
+    /// Bus location, e.g. `1-1.2` (`<busnum>-<devpath>`).
+    pub location: String,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_millis() as u64;
+        .as_millis() as u64
+}
 
-    let mode = detect_mode(output);
+/// Parse system_profiler output into the list of connected devices.
+pub fn parse_device_status(output: &str) -> DeviceStatus {
+    status_from_devices(&parse_system_profiler(output))
+}
 
-    DeviceStatus { mode, timestamp }
+/// Build a device status from an enumerated device list.
+///
+/// Only devices in a recognised mode are reported; the top-level `mode`
+/// summarises the bench using [`mode_priority`].
+pub fn status_from_devices(devices: &[UsbDevice]) -> DeviceStatus {
+    let devices: Vec<DeviceInfo> = devices
+        .iter()
+        .filter_map(|d| {
+            let mode = detect_mode(d.vendor_id, d.product_id)?;
+            Some(DeviceInfo {
+                vendor_id: d.vendor_id,
+                product_id: d.product_id,
+                serial: d.serial.clone(),
+                product: d.product.clone(),
+                location: d.location.clone(),
+                mode,
+            })
+        })
+        .collect();
+
+    let mode = devices
+        .iter()
+        .map(|d| d.mode)
+        .min_by_key(mode_priority)
+        .unwrap_or(DeviceMode::Disconnected);
+
+    DeviceStatus {
+        mode,
+        devices,
+        timestamp: now_millis(),
+    }
 }
 
-fn detect_mode(output: &str) -> String {
-    // Check for EDL device: VID 05c6 + PID 9008
-    let has_qualcomm_vid = output
-        .lines()
-        .any(|line| {
-            let lower = line.to_lowercase();
-            lower.contains("vendor id:") && lower.contains("0x05c6")
-        });
+/// Classify a device by exact VID/PID match.
+///
+/// Unlisted Qualcomm PIDs are still reported as `QualcommUnknown`; anything
+/// else is not one of ours and yields `None`.
+pub fn detect_mode(vendor_id: u16, product_id: u16) -> Option<DeviceMode> {
+    MODE_TABLE
+        .iter()
+        .find(|(vid, pid, _)| *vid == vendor_id && *pid == product_id)
+        .map(|(_, _, mode)| *mode)
+        .or_else(|| {
+            (vendor_id == QUALCOMM_VID).then_some(DeviceMode::QualcommUnknown { product_id })
+        })
+}
 
-    let has_edl_pid = output
-        .lines()
-        .any(|line| {
-            let lower = line.to_lowercase();
-            lower.contains("product id:") && lower.contains("0x9008")
-        });
+/// Lower is more relevant for flashing when summarising several devices.
+fn mode_priority(mode: &DeviceMode) -> u8 {
+    match mode {
+        DeviceMode::Edl => 0,
+        DeviceMode::Fastboot => 1,
+        DeviceMode::Fastbootd => 2,
+        DeviceMode::Recovery => 3,
+        DeviceMode::Adb => 4,
+        DeviceMode::Diag { .. } => 5,
+        DeviceMode::QualcommUnknown { .. } => 6,
+        DeviceMode::Disconnected => 7,
+    }
+}
+
+/// Parse a `0x05c6  (Qualcomm Incorporated)` style value into its number.
+fn parse_profiler_hex(value: &str) -> Option<u32> {
+    let token = value.split_whitespace().next()?;
+    let digits = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))?;
+    u32::from_str_radix(digits, 16).ok()
+}
 
-    if has_qualcomm_vid && has_edl_pid {
-        return "edl".to_string();
+/// Split `system_profiler SPUSBDataType` output into individual devices.
+///
+/// Each device is a `Name:` header followed by indented `Key: value` lines.
+/// Blocks without both a vendor and product ID (buses, controllers) are dropped.
+pub fn parse_system_profiler(output: &str) -> Vec<UsbDevice> {
+    #[derive(Default)]
+    struct Block {
+        name: Option<String>,
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        serial: Option<String>,
+        location: Option<String>,
     }
 
-    // Check for fastboot device (case-insensitive in device names)
-    let lower_output = output.to_lowercase();
-    if lower_output.contains("fastboot") {
-        return "fastboot".to_string();
+    fn finish(block: Block, devices: &mut Vec<UsbDevice>) {
+        if let (Some(vendor_id), Some(product_id)) = (block.vendor_id, block.product_id) {
+            devices.push(UsbDevice {
+                vendor_id,
+                product_id,
+                serial: block.serial,
+                product: block.name,
+                location: block.location.unwrap_or_default(),
+            });
+        }
     }
 
-    "disconnected".to_string()
+    let mut devices = Vec::new();
+    let mut block = Block::default();
+
+    for line in output.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match line.split_once(": ") {
+            Some((key, value)) => {
+                let value = value.trim();
+                match key.to_lowercase().as_str() {
+                    "vendor id" => block.vendor_id = parse_profiler_hex(value).map(|v| v as u16),
+                    "product id" => block.product_id = parse_profiler_hex(value).map(|v| v as u16),
+                    "serial number" => block.serial = Some(value.to_string()),
+                    "location id" => {
+                        block.location = parse_profiler_hex(value).map(|v| format!("{v:#010x}"))
+                    }
+                    _ => {}
+                }
+            }
+            None if line.ends_with(':') => {
+                // New device header: flush the previous block
+                finish(std::mem::take(&mut block), &mut devices);
+                block.name = Some(line.trim_end_matches(':').to_string());
+            }
+            None => {}
+        }
+    }
+    finish(block, &mut devices);
+
+    devices
+}
+
+/// Read a single sysfs attribute, trimmed. Missing or empty attributes → `None`.
+fn read_sysfs_attr(dir: &Path, name: &str) -> Option<String> {
+    let value = fs::read_to_string(dir.join(name)).ok()?;
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn read_sysfs_hex(dir: &Path, name: &str) -> Option<u16> {
+    u16::from_str_radix(&read_sysfs_attr(dir, name)?, 16).ok()
+}
+
+/// Enumerate USB devices from a sysfs tree laid out like `/sys/bus/usb/devices`.
+///
+/// Interface entries (`1-1:1.0`) carry no `idVendor` and are skipped.
+/// The result is sorted by bus location so repeated scans compare equal.
+pub fn scan_sysfs_usb(root: &Path) -> Vec<UsbDevice> {
+    let entries = match fs::read_dir(root) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut devices: Vec<UsbDevice> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let dir = entry.path();
+            let vendor_id = read_sysfs_hex(&dir, "idVendor")?;
+            let product_id = read_sysfs_hex(&dir, "idProduct")?;
+
+            let location = match (
+                read_sysfs_attr(&dir, "busnum"),
+                read_sysfs_attr(&dir, "devpath"),
+            ) {
+                (Some(bus), Some(path)) => format!("{bus}-{path}"),
+                _ => entry.file_name().to_string_lossy().to_string(),
+            };
+
+            Some(UsbDevice {
+                vendor_id,
+                product_id,
+                serial: read_sysfs_attr(&dir, "serial"),
+                product: read_sysfs_attr(&dir, "product"),
+                location,
+            })
+        })
+        .collect();
+
+    devices.sort_by(|a, b| a.location.cmp(&b.location));
+    devices
 }
 
-fn run_system_profiler() -> Result<String, String> {
+#[cfg(not(target_os = "linux"))]
+fn run_system_profiler() -> Result<String, GeekFlashError> {
     let output = std::process::Command::new("system_profiler")
         .arg("SPUSBDataType")
         .output()
-        .map_err(|e| format!("Failed to run system_profiler: {}", e))?;
+        .map_err(|e| GeekFlashError::spawn("system_profiler", e))?;
 
     Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
-/// Get current device status (Tauri command).
-#[tauri::command]
-pub async fn get_device_status() -> Result<DeviceStatus, String> {
+/// Probe the host for connected devices using the platform backend:
+/// sysfs on Linux, `system_profiler` elsewhere (macOS).
+#[cfg(target_os = "linux")]
+pub fn probe_device_status() -> Result<DeviceStatus, GeekFlashError> {
+    let devices = scan_sysfs_usb(Path::new(SYSFS_USB_DEVICES));
+    Ok(status_from_devices(&devices))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn probe_device_status() -> Result<DeviceStatus, GeekFlashError> {
     let output = run_system_profiler()?;
     Ok(parse_device_status(&output))
 }
 
-/// Start background polling task (called during app setup).
-/// Polls every 3 seconds and emits "device-status" event only when status changes.
-pub fn start_device_polling(app: AppHandle) {
-    std::thread::spawn(move || {
-        let mut last_mode = String::new();
+/// Get current device status (Tauri command).
+///
+/// `devices` lists every connected unit with its serial and USB location,
+/// so the frontend can pick a target when several are plugged in.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_device_status() -> Result<DeviceStatus, GeekFlashError> {
+    probe_device_status()
+}
 
-        loop {
-            if let Ok(output) = run_system_profiler() {
-                let status = parse_device_status(&output);
+/// Fallback poll interval, used when no hotplug event arrives.
+#[cfg(feature = "gui")]
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
 
-                if status.mode != last_mode {
-                    last_mode = status.mode.clone();
-                    app.emit("device-status", &status).ok();
-                }
-            }
+/// Handle to the background device monitor thread.
+///
+/// Managed as Tauri state so the app can stop it cleanly on exit.
+pub struct DeviceMonitor {
+    stop: Arc<AtomicBool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
 
-            std::thread::sleep(std::time::Duration::from_secs(3));
+impl DeviceMonitor {
+    /// Signal the monitor thread to stop and wait for it to exit.
+    pub fn shutdown(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let handle = self.handle.lock().ok().and_then(|mut h| h.take());
+        if let Some(handle) = handle {
+            handle.join().ok();
         }
+    }
+}
+
+/// Start the background device monitor (called during app setup).
+///
+/// Re-probes as soon as a USB hotplug event arrives, and every 3 seconds as a
+/// fallback. Emits "device-status" only when the set of connected devices (or
+/// any device's mode) changes.
+#[cfg(feature = "gui")]
+pub fn start_device_monitor(app: AppHandle) -> DeviceMonitor {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+
+    let handle = std::thread::spawn(move || {
+        let watcher = HotplugWatcher::new();
+        run_monitor(
+            watcher,
+            &thread_stop,
+            POLL_INTERVAL,
+            probe_device_status,
+            |status| {
+                app.emit("device-status", status).ok();
+            },
+        );
     });
+
+    DeviceMonitor {
+        stop,
+        handle: Mutex::new(Some(handle)),
+    }
+}
+
+/// Monitor loop: probe, emit on change, then wait for the next hotplug event
+/// or poll tick. Returns once `stop` is set.
+pub fn run_monitor(
+    mut watcher: HotplugWatcher,
+    stop: &AtomicBool,
+    poll_interval: Duration,
+    probe: impl Fn() -> Result<DeviceStatus, GeekFlashError>,
+    mut emit: impl FnMut(&DeviceStatus),
+) {
+    let mut last_devices: Option<Vec<DeviceInfo>> = None;
+
+    loop {
+        if let Ok(status) = probe() {
+            if last_devices.as_ref() != Some(&status.devices) {
+                last_devices = Some(status.devices.clone());
+                emit(&status);
+            }
+        }
+
+        if watcher.wait(poll_interval, stop) == Wake::Stopped {
+            break;
+        }
+    }
+}
+
+/// How often [`wait_for_mode`] re-probes while waiting.
+const WAIT_PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Find a device in `mode`, optionally with a specific serial.
+pub fn find_device<'a>(
+    status: &'a DeviceStatus,
+    mode: DeviceMode,
+    serial: Option<&str>,
+) -> Option<&'a DeviceInfo> {
+    find_device_at(status, mode, serial, None)
+}
+
+/// [`find_device`], optionally also on a specific USB port.
+pub fn find_device_at<'a>(
+    status: &'a DeviceStatus,
+    mode: DeviceMode,
+    serial: Option<&str>,
+    location: Option<&str>,
+) -> Option<&'a DeviceInfo> {
+    status.devices.iter().find(|d| {
+        d.mode == mode
+            && serial.is_none_or(|s| d.serial.as_deref() == Some(s))
+            && location.is_none_or(|l| d.location == l)
+    })
+}
+
+/// Pin a sequence to the unit reporting `serial` (in any mode), remembering
+/// the USB port it is on so it can be found again once it re-enumerates in
+/// EDL mode without that serial.
+pub fn locate_target(
+    status: &DeviceStatus,
+    serial: Option<&str>,
+    edl_serial: Option<&str>,
+) -> Result<DeviceTarget, GeekFlashError> {
+    let location = match serial {
+        Some(serial) => {
+            let device = status
+                .devices
+                .iter()
+                .find(|d| d.serial.as_deref() == Some(serial))
+                .ok_or_else(|| {
+                    GeekFlashError::invalid_input(
+                        "serial",
+                        format!("no connected device reports serial {serial}"),
+                    )
+                })?;
+            Some(device.location.clone())
+        }
+        None => None,
+    };
+    Ok(DeviceTarget {
+        serial: serial.map(str::to_string),
+        location,
+        edl_serial: edl_serial.map(str::to_string),
+    })
+}
+
+/// [`locate_target`] against the devices connected now. Without `serial`,
+/// or for a dry run, nothing is probed and EDL steps accept a device on any
+/// port.
+pub fn current_target(
+    serial: Option<&str>,
+    edl_serial: Option<&str>,
+    dry_run: bool,
+) -> Result<DeviceTarget, GeekFlashError> {
+    if serial.is_none() || dry_run {
+        return Ok(DeviceTarget {
+            serial: serial.map(str::to_string),
+            location: None,
+            edl_serial: edl_serial.map(str::to_string),
+        });
+    }
+    locate_target(&probe_device_status()?, serial, edl_serial)
+}
+
+/// Wait until a device in `mode` (with `serial` and on USB port `location`,
+/// if given) is enumerated.
+///
+/// Returns the matching device as soon as it shows up, or
+/// `GeekFlashError::DeviceNotFound` once `timeout` elapses without it.
+pub async fn wait_for_mode(
+    mode: DeviceMode,
+    serial: Option<&str>,
+    location: Option<&str>,
+    timeout: Duration,
+) -> Result<DeviceInfo, GeekFlashError> {
+    wait_for_device(
+        || async {
+            tokio::task::spawn_blocking(probe_device_status)
+                .await
+                .map_err(|e| GeekFlashError::io("probe devices", e))?
+        },
+        mode,
+        serial,
+        location,
+        timeout,
+        WAIT_PROBE_INTERVAL,
+    )
+    .await
+}
+
+/// [`wait_for_mode`] with an injectable probe, for tests.
+pub async fn wait_for_device<F, Fut>(
+    probe: F,
+    mode: DeviceMode,
+    serial: Option<&str>,
+    location: Option<&str>,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<DeviceInfo, GeekFlashError>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<DeviceStatus, GeekFlashError>>,
+{
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        if let Ok(status) = probe().await {
+            if let Some(device) = find_device_at(&status, mode, serial, location) {
+                return Ok(device.clone());
+            }
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            return Err(GeekFlashError::DeviceNotFound {
+                mode,
+                serial: serial.map(str::to_string),
+                timeout_ms: timeout.as_millis() as u64,
+            });
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// `fastboot` arguments selecting a target device, e.g. `-s <serial>`.
+pub fn fastboot_target_args(serial: Option<&str>) -> Vec<String> {
+    match serial {
+        Some(s) => vec!["-s".to_string(), s.to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// `edl` arguments selecting a target device, e.g. `--serial_number=<serial>`.
+pub fn edl_target_args(serial: Option<&str>) -> Vec<String> {
+    match serial {
+        Some(s) => vec![format!("--serial_number={s}")],
+        None => Vec::new(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+
+    /// Create a fake sysfs device directory with the given attribute files.
+    fn write_sysfs_entry(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attr, value) in attrs {
+            fs::write(dir.join(attr), format!("{value}\n")).unwrap();
+        }
+    }
+
+    fn sysfs_fixture_edl() -> TempDir {
+        let root = TempDir::new().unwrap();
+        write_sysfs_entry(
+            root.path(),
+            "usb1",
+            &[
+                ("idVendor", "1d6b"),
+                ("idProduct", "0002"),
+                ("busnum", "1"),
+                ("devpath", "0"),
+            ],
+        );
+        write_sysfs_entry(
+            root.path(),
+            "1-2",
+            &[
+                ("idVendor", "05c6"),
+                ("idProduct", "9008"),
+                ("product", "QUSB__BULK"),
+                ("serial", "12345678"),
+                ("busnum", "1"),
+                ("devpath", "2"),
+            ],
+        );
+        // Interface directory: no idVendor, must be ignored
+        write_sysfs_entry(root.path(), "1-2:1.0", &[("bInterfaceClass", "ff")]);
+        root
+    }
 
     const SAMPLE_EDL_OUTPUT: &str = r#"
 USB:
@@ -120,7 +586,7 @@ USB:
 
         Android Fastboot:
 
-          Product ID: 0x4ee7
+          Product ID: 0xd00d
           Vendor ID: 0x18d1  (Google Inc.)
           Version: 1.00
           Serial Number: ABCDEF123456
@@ -150,43 +616,46 @@ USB:
     #[test]
     fn test_parse_edl_device() {
         let status = parse_device_status(SAMPLE_EDL_OUTPUT);
-        assert_eq!(status.mode, "edl");
+        assert_eq!(status.mode, DeviceMode::Edl);
         assert!(status.timestamp > 0);
     }
 
     #[test]
     fn test_parse_fastboot_device() {
         let status = parse_device_status(SAMPLE_FASTBOOT_OUTPUT);
-        assert_eq!(status.mode, "fastboot");
+        assert_eq!(status.mode, DeviceMode::Fastboot);
     }
 
     #[test]
     fn test_parse_no_device() {
         let status = parse_device_status(SAMPLE_EMPTY_OUTPUT);
-        assert_eq!(status.mode, "disconnected");
+        assert_eq!(status.mode, DeviceMode::Disconnected);
     }
 
     #[test]
     fn test_parse_empty_output() {
         let status = parse_device_status("");
-        assert_eq!(status.mode, "disconnected");
+        assert_eq!(status.mode, DeviceMode::Disconnected);
     }
 
     #[test]
     fn test_edl_requires_both_vid_and_pid() {
-        // Only VID, no PID → not EDL
+        // Qualcomm VID with another PID → not EDL, but still reported
         let only_vid = r#"
           Vendor ID: 0x05c6  (Qualcomm Incorporated)
           Product ID: 0x1234
         "#;
-        assert_eq!(parse_device_status(only_vid).mode, "disconnected");
+        assert_eq!(
+            parse_device_status(only_vid).mode,
+            DeviceMode::QualcommUnknown { product_id: 0x1234 }
+        );
 
         // Only PID, no VID → not EDL
         let only_pid = r#"
           Vendor ID: 0x1234
           Product ID: 0x9008
         "#;
-        assert_eq!(parse_device_status(only_pid).mode, "disconnected");
+        assert_eq!(parse_device_status(only_pid).mode, DeviceMode::Disconnected);
     }
 
     #[test]
@@ -197,6 +666,333 @@ USB:
           Product ID: 0x9008
           Android Fastboot Device
         "#;
-        assert_eq!(parse_device_status(both).mode, "edl");
+        assert_eq!(parse_device_status(both).mode, DeviceMode::Edl);
+    }
+
+    #[test]
+    fn test_sysfs_scan_reads_device_attributes() {
+        let root = sysfs_fixture_edl();
+        let devices = scan_sysfs_usb(root.path());
+
+        assert_eq!(devices.len(), 2);
+        let edl = devices.iter().find(|d| d.product_id == 0x9008).unwrap();
+        assert_eq!(edl.vendor_id, 0x05c6);
+        assert_eq!(edl.serial.as_deref(), Some("12345678"));
+        assert_eq!(edl.product.as_deref(), Some("QUSB__BULK"));
+        assert_eq!(edl.location, "1-2");
+    }
+
+    #[test]
+    fn test_sysfs_edl_device() {
+        let root = sysfs_fixture_edl();
+        let status = status_from_devices(&scan_sysfs_usb(root.path()));
+        assert_eq!(status.mode, DeviceMode::Edl);
+        assert!(status.timestamp > 0);
+    }
+
+    #[test]
+    fn test_sysfs_fastboot_device() {
+        let root = TempDir::new().unwrap();
+        write_sysfs_entry(
+            root.path(),
+            "3-1.4",
+            &[
+                ("idVendor", "18d1"),
+                ("idProduct", "d00d"),
+                ("product", "Android Fastboot"),
+                ("serial", "ABCDEF123456"),
+                ("busnum", "3"),
+                ("devpath", "1.4"),
+            ],
+        );
+        let devices = scan_sysfs_usb(root.path());
+        assert_eq!(devices[0].location, "3-1.4");
+        assert_eq!(status_from_devices(&devices).mode, DeviceMode::Fastboot);
+    }
+
+    #[test]
+    fn test_sysfs_no_device() {
+        let root = TempDir::new().unwrap();
+        write_sysfs_entry(
+            root.path(),
+            "1-1",
+            &[
+                ("idVendor", "046d"),
+                ("idProduct", "c52b"),
+                ("product", "USB Receiver"),
+            ],
+        );
+        let devices = scan_sysfs_usb(root.path());
+        // No busnum/devpath → falls back to the directory name
+        assert_eq!(devices[0].location, "1-1");
+        assert_eq!(status_from_devices(&devices).mode, DeviceMode::Disconnected);
+    }
+
+    #[test]
+    fn test_sysfs_missing_root() {
+        let root = TempDir::new().unwrap();
+        let devices = scan_sysfs_usb(&root.path().join("does-not-exist"));
+        assert!(devices.is_empty());
+        assert_eq!(status_from_devices(&devices).mode, DeviceMode::Disconnected);
+    }
+
+    #[test]
+    fn test_sysfs_edl_requires_both_vid_and_pid() {
+        let root = TempDir::new().unwrap();
+        write_sysfs_entry(
+            root.path(),
+            "1-1",
+            &[("idVendor", "05c6"), ("idProduct", "1234")],
+        );
+        write_sysfs_entry(
+            root.path(),
+            "1-2",
+            &[("idVendor", "1234"), ("idProduct", "9008")],
+        );
+        let status = status_from_devices(&scan_sysfs_usb(root.path()));
+        assert_eq!(status.devices.len(), 1);
+        assert_eq!(
+            status.mode,
+            DeviceMode::QualcommUnknown { product_id: 0x1234 }
+        );
+    }
+
+    #[test]
+    fn test_parse_multiple_devices_with_serials() {
+        let output = format!("{SAMPLE_EDL_OUTPUT}{SAMPLE_FASTBOOT_OUTPUT}{SAMPLE_EMPTY_OUTPUT}");
+        let status = parse_device_status(&output);
+
+        assert_eq!(status.mode, DeviceMode::Edl);
+        assert_eq!(status.devices.len(), 2);
+
+        let edl = &status.devices[0];
+        assert_eq!(edl.mode, DeviceMode::Edl);
+        assert_eq!((edl.vendor_id, edl.product_id), (0x05c6, 0x9008));
+        assert_eq!(edl.serial.as_deref(), Some("12345678"));
+        assert_eq!(edl.location, "0x01100000");
+
+        let fastboot = &status.devices[1];
+        assert_eq!(fastboot.mode, DeviceMode::Fastboot);
+        assert_eq!(fastboot.serial.as_deref(), Some("ABCDEF123456"));
+        assert_eq!(fastboot.product.as_deref(), Some("Android Fastboot"));
+    }
+
+    #[test]
+    fn test_sysfs_multiple_tablets() {
+        let root = sysfs_fixture_edl();
+        write_sysfs_entry(
+            root.path(),
+            "1-3",
+            &[
+                ("idVendor", "05c6"),
+                ("idProduct", "9008"),
+                ("serial", "87654321"),
+                ("busnum", "1"),
+                ("devpath", "3"),
+            ],
+        );
+        let status = status_from_devices(&scan_sysfs_usb(root.path()));
+
+        // Root hub is not a recognised device and is left out
+        let serials: Vec<_> = status
+            .devices
+            .iter()
+            .map(|d| (d.location.as_str(), d.serial.as_deref()))
+            .collect();
+        assert_eq!(
+            serials,
+            vec![("1-2", Some("12345678")), ("1-3", Some("87654321"))]
+        );
+    }
+
+    #[test]
+    fn test_target_args() {
+        assert!(fastboot_target_args(None).is_empty());
+        assert_eq!(fastboot_target_args(Some("ABC")), vec!["-s", "ABC"]);
+        assert!(edl_target_args(None).is_empty());
+        assert_eq!(edl_target_args(Some("123")), vec!["--serial_number=123"]);
+    }
+
+    #[test]
+    fn test_detect_mode_table() {
+        assert_eq!(detect_mode(0x05c6, 0x9008), Some(DeviceMode::Edl));
+        assert_eq!(
+            detect_mode(0x05c6, 0x900e),
+            Some(DeviceMode::Diag { product_id: 0x900e })
+        );
+        assert_eq!(
+            detect_mode(0x05c6, 0x901d),
+            Some(DeviceMode::Diag { product_id: 0x901d })
+        );
+        assert_eq!(detect_mode(0x18d1, 0xd00d), Some(DeviceMode::Fastboot));
+        assert_eq!(detect_mode(0x18d1, 0x4ee0), Some(DeviceMode::Fastbootd));
+        assert_eq!(detect_mode(0x18d1, 0xd001), Some(DeviceMode::Recovery));
+        assert_eq!(detect_mode(0x18d1, 0x4ee7), Some(DeviceMode::Adb));
+        assert_eq!(detect_mode(0x05ac, 0x0340), None);
+    }
+
+    #[test]
+    fn test_fastboot_name_alone_is_not_enough() {
+        // Product names are ignored; only VID/PID decide the mode
+        let output = r#"
+        Android Fastboot:
+          Product ID: 0x0001
+          Vendor ID: 0x1234
+        "#;
+        assert_eq!(parse_device_status(output).mode, DeviceMode::Disconnected);
+    }
+
+    #[test]
+    fn test_summary_prefers_flash_relevant_mode() {
+        let output = r#"
+        Adb Tablet:
+          Product ID: 0x4ee7
+          Vendor ID: 0x18d1
+        Crashed Tablet:
+          Product ID: 0x900e
+          Vendor ID: 0x05c6
+        Fastbootd Tablet:
+          Product ID: 0x4ee0
+          Vendor ID: 0x18d1
+        "#;
+        let status = parse_device_status(output);
+        assert_eq!(status.devices.len(), 3);
+        assert_eq!(status.mode, DeviceMode::Fastbootd);
+    }
+
+    #[test]
+    fn test_mode_serializes_tagged() {
+        let json = serde_json::to_value(DeviceMode::Diag { product_id: 0x900e }).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "kind": "diag", "product_id": 0x900e })
+        );
+        let json = serde_json::to_value(DeviceMode::QualcommUnknown { product_id: 1 }).unwrap();
+        assert_eq!(json["kind"], "qualcomm_unknown");
+    }
+
+    #[test]
+    fn test_mode_operation_gates() {
+        assert!(DeviceMode::Edl.accepts_edl());
+        assert!(!DeviceMode::Fastboot.accepts_edl());
+        assert!(DeviceMode::Fastbootd.accepts_fastboot());
+        assert!(!DeviceMode::Fastbootd.accepts_oem_edl());
+        assert!(DeviceMode::Fastboot.accepts_oem_edl());
+        assert!(!DeviceMode::Adb.accepts_fastboot());
+    }
+
+    #[test]
+    fn test_monitor_emits_only_on_change_and_stops() {
+        let stop = AtomicBool::new(false);
+        let probes = std::cell::Cell::new(0);
+        let mut emitted = Vec::new();
+
+        run_monitor(
+            HotplugWatcher::polling_only(),
+            &stop,
+            Duration::from_millis(1),
+            || {
+                probes.set(probes.get() + 1);
+                // Device appears on the third probe; stop after the sixth
+                if probes.get() >= 6 {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                let output = if probes.get() >= 3 {
+                    SAMPLE_EDL_OUTPUT
+                } else {
+                    ""
+                };
+                Ok(parse_device_status(output))
+            },
+            |status| emitted.push(status.mode),
+        );
+
+        assert_eq!(probes.get(), 6);
+        assert_eq!(emitted, vec![DeviceMode::Disconnected, DeviceMode::Edl]);
+    }
+
+    fn bench_with(
+        output: &'static str,
+    ) -> impl Fn() -> std::future::Ready<Result<DeviceStatus, GeekFlashError>> {
+        move || std::future::ready(Ok(parse_device_status(output)))
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_mode_finds_device() {
+        let device = wait_for_device(
+            bench_with(SAMPLE_EDL_OUTPUT),
+            DeviceMode::Edl,
+            Some("12345678"),
+            None,
+            Duration::from_millis(50),
+            Duration::from_millis(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(device.location, "0x01100000");
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_mode_waits_for_reenumeration() {
+        let probes = std::cell::Cell::new(0);
+        let device = wait_for_device(
+            || {
+                probes.set(probes.get() + 1);
+                // Still in fastboot for the first two probes
+                let output = if probes.get() > 2 {
+                    SAMPLE_EDL_OUTPUT
+                } else {
+                    SAMPLE_FASTBOOT_OUTPUT
+                };
+                std::future::ready(Ok(parse_device_status(output)))
+            },
+            DeviceMode::Edl,
+            None,
+            None,
+            Duration::from_secs(5),
+            Duration::from_millis(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(probes.get(), 3);
+        assert_eq!(device.mode, DeviceMode::Edl);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_mode_times_out() {
+        let err = wait_for_device(
+            bench_with(SAMPLE_FASTBOOT_OUTPUT),
+            DeviceMode::Edl,
+            None,
+            None,
+            Duration::from_millis(20),
+            Duration::from_millis(5),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), "DEVICE_NOT_FOUND");
+        assert!(matches!(
+            err,
+            GeekFlashError::DeviceNotFound {
+                mode: DeviceMode::Edl,
+                serial: None,
+                timeout_ms: 20
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_mode_respects_serial() {
+        let err = wait_for_device(
+            bench_with(SAMPLE_EDL_OUTPUT),
+            DeviceMode::Edl,
+            Some("OTHER"),
+            None,
+            Duration::from_millis(10),
+            Duration::from_millis(5),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("serial OTHER"), "{err}");
     }
 }
@@ -0,0 +1,181 @@
+use std::io::Write;
+use std::sync::Mutex;
+
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, Emitter};
+
+use crate::models::{LogLine, OperationInfo, ProgressEvent, StepFinished, StepStarted};
+
+/// Where the executor reports what a running operation is doing.
+pub trait EventSink: Send + Sync {
+    fn operation_started(&self, info: &OperationInfo);
+    fn log(&self, line: &LogLine);
+    fn progress(&self, event: &ProgressEvent);
+    fn step_started(&self, event: &StepStarted);
+    fn step_finished(&self, event: &StepFinished);
+}
+
+/// Emits every event to the webview.
+#[cfg(feature = "gui")]
+pub struct TauriSink(pub AppHandle);
+
+#[cfg(feature = "gui")]
+impl EventSink for TauriSink {
+    fn operation_started(&self, info: &OperationInfo) {
+        self.0.emit("operation-started", info).ok();
+    }
+
+    fn log(&self, line: &LogLine) {
+        self.0.emit("log-line", line).ok();
+    }
+
+    fn progress(&self, event: &ProgressEvent) {
+        self.0.emit("progress", event).ok();
+    }
+
+    fn step_started(&self, event: &StepStarted) {
+        self.0.emit("step-started", event).ok();
+    }
+
+    fn step_finished(&self, event: &StepFinished) {
+        self.0.emit("step-finished", event).ok();
+    }
+}
+
+/// Prints log lines to stdout/stderr and progress as an in-place percentage.
+#[derive(Default)]
+pub struct StdoutSink {
+    /// Whether a progress line is pending and needs a newline before output.
+    progress_shown: Mutex<bool>,
+}
+
+impl StdoutSink {
+    fn end_progress(&self) {
+        let mut shown = self.progress_shown.lock().unwrap();
+        if *shown {
+            eprintln!();
+            *shown = false;
+        }
+    }
+}
+
+impl EventSink for StdoutSink {
+    fn operation_started(&self, info: &OperationInfo) {
+        eprintln!("Started {} ({})", info.kind, info.operation_id);
+    }
+
+    fn log(&self, line: &LogLine) {
+        self.end_progress();
+        if line.stream == "stderr" {
+            eprintln!("{}", line.content);
+        } else {
+            println!("{}", line.content);
+        }
+    }
+
+    fn progress(&self, event: &ProgressEvent) {
+        eprint!("\r  {:5.1}%", event.percent);
+        std::io::stderr().flush().ok();
+        *self.progress_shown.lock().unwrap() = true;
+    }
+
+    fn step_started(&self, _event: &StepStarted) {}
+
+    fn step_finished(&self, _event: &StepFinished) {
+        self.end_progress();
+    }
+}
+
+/// One event captured by [`RecordingSink`].
+#[derive(Clone, Debug)]
+pub enum SinkEvent {
+    OperationStarted(OperationInfo),
+    Log(LogLine),
+    Progress(ProgressEvent),
+    StepStarted(StepStarted),
+    StepFinished(StepFinished),
+}
+
+/// Keeps every event in memory, for tests.
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<SinkEvent>>,
+}
+
+impl RecordingSink {
+    pub fn events(&self) -> Vec<SinkEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// The content of every log line, in order.
+    pub fn log_lines(&self) -> Vec<String> {
+        self.events()
+            .into_iter()
+            .filter_map(|e| match e {
+                SinkEvent::Log(line) => Some(line.content),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn finished_steps(&self) -> Vec<StepFinished> {
+        self.events()
+            .into_iter()
+            .filter_map(|e| match e {
+                SinkEvent::StepFinished(step) => Some(step),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn push(&self, event: SinkEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+impl EventSink for RecordingSink {
+    fn operation_started(&self, info: &OperationInfo) {
+        self.push(SinkEvent::OperationStarted(info.clone()));
+    }
+
+    fn log(&self, line: &LogLine) {
+        self.push(SinkEvent::Log(line.clone()));
+    }
+
+    fn progress(&self, event: &ProgressEvent) {
+        self.push(SinkEvent::Progress(event.clone()));
+    }
+
+    fn step_started(&self, event: &StepStarted) {
+        self.push(SinkEvent::StepStarted(event.clone()));
+    }
+
+    fn step_finished(&self, event: &StepFinished) {
+        self.push(SinkEvent::StepFinished(event.clone()));
+    }
+}
+
+/// Forwards every event to each sink in turn.
+pub struct Fanout<'a>(pub Vec<&'a dyn EventSink>);
+
+impl EventSink for Fanout<'_> {
+    fn operation_started(&self, info: &OperationInfo) {
+        self.0.iter().for_each(|s| s.operation_started(info));
+    }
+
+    fn log(&self, line: &LogLine) {
+        self.0.iter().for_each(|s| s.log(line));
+    }
+
+    fn progress(&self, event: &ProgressEvent) {
+        self.0.iter().for_each(|s| s.progress(event));
+    }
+
+    fn step_started(&self, event: &StepStarted) {
+        self.0.iter().for_each(|s| s.step_started(event));
+    }
+
+    fn step_finished(&self, event: &StepFinished) {
+        self.0.iter().for_each(|s| s.step_finished(event));
+    }
+}
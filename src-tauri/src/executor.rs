@@ -1,161 +1,669 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::Instant;
 
-use tauri::{AppHandle, Emitter};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, State};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::watch;
 use tokio::time::{timeout, Duration};
 
-use crate::models::{CommandResult, CommandSpec, LogLine};
+#[cfg(feature = "gui")]
+use crate::audit::history_dir;
+use crate::audit::{hash_inputs, AuditRecorder};
+use crate::backup::record_partition_backup;
+#[cfg(feature = "gui")]
+use crate::events::TauriSink;
+use crate::events::{EventSink, Fanout};
+use crate::gpt::{compare_gpt_dump, parse_storage_info};
+use crate::models::{
+    CommandOutcome, CommandResult, CommandSpec, GeekFlashError, LogLine, OperationInfo,
+    OperationRecord, OutputMatchers, PostStep, ProgressEvent, StepFinished, StepStarted,
+};
+use crate::plan::{build_plan, check_inputs, command_line, first_problem};
+use crate::progress::ProgressParser;
+use crate::runner::CommandRunner;
+#[cfg(any(feature = "gui", test))]
+use crate::runner::SystemRunner;
 
-/// Execute a single system command asynchronously.
-///
-/// Streams stdout/stderr line-by-line to the frontend via `log-line` events.
-/// Supports sudo prefix and a 30-second timeout.
-pub async fn execute_command(
-    app: &AppHandle,
-    program: &str,
-    args: &[&str],
-    sudo: bool,
-) -> Result<CommandResult, String> {
-    let start = Instant::now();
-
-    let mut cmd = if sudo {
-        let mut c = Command::new("sudo");
-        c.arg(program);
-        c.args(args);
-        c
-    } else {
-        let mut c = Command::new(program);
-        c.args(args);
-        c
+pub(crate) fn emit_log(
+    sink: &dyn EventSink,
+    step_index: Option<usize>,
+    stream: &str,
+    content: String,
+) {
+    let log = LogLine {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        stream: stream.to_string(),
+        content,
+        step_index,
     };
+    sink.log(&log);
+}
 
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::piped());
+/// Call `f` for every line of `reader`, treating `\r` as a line break too so
+/// that in-place progress bar redraws arrive one by one. Empty lines are
+/// skipped and invalid UTF-8 is replaced rather than ending the stream.
+async fn for_each_line<R: AsyncRead + Unpin>(reader: R, mut f: impl FnMut(String)) {
+    let mut reader = BufReader::new(reader);
+    let mut pending = Vec::new();
 
-    let mut child = cmd.spawn().map_err(|e| format!("Failed to spawn command: {e}"))?;
+    loop {
+        let buf = match reader.fill_buf().await {
+            Ok(buf) if !buf.is_empty() => buf,
+            _ => break,
+        };
+        for &b in buf {
+            if b == b'\n' || b == b'\r' {
+                if !pending.is_empty() {
+                    f(String::from_utf8_lossy(&pending).into_owned());
+                    pending.clear();
+                }
+            } else {
+                pending.push(b);
+            }
+        }
+        let n = buf.len();
+        reader.consume(n);
+    }
+
+    if !pending.is_empty() {
+        f(String::from_utf8_lossy(&pending).into_owned());
+    }
+}
 
-    let stdout = child.stdout.take().ok_or("Failed to capture stdout")?;
-    let stderr = child.stderr.take().ok_or("Failed to capture stderr")?;
+/// Forward one output stream of a step: progress readings become `progress`
+/// events, everything else (and non-transient progress lines) goes to the log.
+/// Returns the logged lines so they can be checked against output matchers.
+async fn stream_output<R: AsyncRead + Unpin>(
+    sink: &dyn EventSink,
+    reader: R,
+    stream: &'static str,
+    operation_id: &str,
+    step_index: usize,
+) -> Vec<String> {
+    let mut parser = ProgressParser::new();
+    let mut last_percent: Option<f32> = None;
+    let mut captured = Vec::new();
 
-    let app_out = app.clone();
-    let app_err = app.clone();
+    for_each_line(reader, |line| {
+        let Some(update) = parser.feed(&line) else {
+            captured.push(line.clone());
+            emit_log(sink, Some(step_index), stream, line);
+            return;
+        };
 
-    let stdout_task = tokio::spawn(async move {
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            let log = LogLine {
-                timestamp: chrono::Utc::now().to_rfc3339(),
-                stream: "stdout".to_string(),
-                content: line,
+        // edl redraws its bar far more often than the UI needs
+        let changed = last_percent.is_none_or(|p| (update.percent - p).abs() >= 0.5);
+        if changed || update.percent >= 100.0 {
+            last_percent = Some(update.percent);
+            let event = ProgressEvent {
+                operation_id: operation_id.to_string(),
+                step_index,
+                bytes_done: update.bytes_done,
+                bytes_total: update.bytes_total,
+                percent: update.percent,
             };
-            app_out.emit("log-line", &log).ok();
+            sink.progress(&event);
         }
-    });
+        if !update.transient {
+            captured.push(line.clone());
+            emit_log(sink, Some(step_index), stream, line);
+        }
+    })
+    .await;
 
-    let stderr_task = tokio::spawn(async move {
-        let reader = BufReader::new(stderr);
-        let mut lines = reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            let log = LogLine {
-                timestamp: chrono::Utc::now().to_rfc3339(),
-                stream: "stderr".to_string(),
-                content: line,
-            };
-            app_err.emit("log-line", &log).ok();
+    captured
+}
+
+/// Check captured output against a step's matchers.
+///
+/// Returns the reason the step should be considered failed, if any: a
+/// failure pattern that appeared, a required success pattern that did not,
+/// or a reported LUN geometry that differs from the expected one.
+/// Matching is case-insensitive on substrings.
+pub fn check_output(matchers: &OutputMatchers, lines: &[String]) -> Option<String> {
+    let lowered: Vec<String> = lines.iter().map(|l| l.to_lowercase()).collect();
+
+    for pattern in &matchers.failure {
+        let needle = pattern.to_lowercase();
+        if let Some(i) = lowered.iter().position(|l| l.contains(&needle)) {
+            return Some(format!(
+                "output matched failure pattern \"{pattern}\": {}",
+                lines[i].trim()
+            ));
+        }
+    }
+
+    for pattern in &matchers.success {
+        let needle = pattern.to_lowercase();
+        if !lowered.iter().any(|l| l.contains(&needle)) {
+            return Some(format!("required output \"{pattern}\" never appeared"));
+        }
+    }
+
+    if let Some(expected) = &matchers.geometry {
+        match parse_storage_info(lines) {
+            None => return Some("the device did not report its LUN geometry".to_string()),
+            Some(actual) if actual != *expected => {
+                return Some(format!(
+                    "the device LUN has {} sectors of {} bytes, but the GPT files are for {} sectors of {} bytes",
+                    actual.total_sectors, actual.sector_size, expected.total_sectors, expected.sector_size
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+
+    None
+}
+
+/// Cancellation signal handed to a running sequence.
+#[derive(Clone)]
+pub struct CancelToken(watch::Receiver<bool>);
+
+impl CancelToken {
+    /// A token that is never cancelled.
+    pub fn never() -> Self {
+        let (_tx, rx) = watch::channel(false);
+        CancelToken(rx)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolve once cancellation is requested; pends forever otherwise.
+    pub async fn cancelled(&self) {
+        let mut rx = self.0.clone();
+        if rx.wait_for(|c| *c).await.is_err() {
+            // Sender gone without cancelling: nothing can cancel us any more
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Running operations, keyed by operation ID (managed as Tauri state).
+#[derive(Default)]
+pub struct OperationRegistry {
+    running: Mutex<HashMap<String, watch::Sender<bool>>>,
+    next_id: AtomicU64,
+    /// Recorder of the most recently started operation, for `export_log`.
+    last_session: Mutex<Option<AuditRecorder>>,
+}
+
+impl OperationRegistry {
+    /// Register a new operation and return its ID and cancel token.
+    pub fn begin(&self, kind: &str) -> (String, CancelToken) {
+        let n = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let id = format!("{kind}-{}-{n}", chrono::Utc::now().format("%Y%m%d%H%M%S"));
+        let (tx, rx) = watch::channel(false);
+        self.running.lock().unwrap().insert(id.clone(), tx);
+        (id, CancelToken(rx))
+    }
+
+    /// Request cancellation. Returns `false` if no such operation is running.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.running.lock().unwrap().get(id) {
+            Some(tx) => {
+                tx.send_replace(true);
+                true
+            }
+            None => false,
         }
+    }
+
+    pub fn finish(&self, id: &str) {
+        self.running.lock().unwrap().remove(id);
+    }
+
+    /// Cancel every running operation; returns how many were signalled.
+    pub fn cancel_all(&self) -> usize {
+        let running = self.running.lock().unwrap();
+        for tx in running.values() {
+            tx.send_replace(true);
+        }
+        running.len()
+    }
+
+    /// Block until no operation is running or `limit` elapses; returns
+    /// whether they all finished. On exit this gives cancelled operations
+    /// time to kill their processes.
+    pub fn wait_idle(&self, limit: Duration) -> bool {
+        let deadline = Instant::now() + limit;
+        while !self.running.lock().unwrap().is_empty() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+
+    pub fn track_session(&self, audit: &AuditRecorder) {
+        *self.last_session.lock().unwrap() = Some(audit.clone());
+    }
+
+    /// The latest operation's record, including a still-running one's log.
+    pub fn last_session(&self) -> Option<OperationRecord> {
+        self.last_session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|a| a.snapshot())
+    }
+}
+
+/// An operation as requested by one of the Tauri commands.
+pub struct OperationRequest<'a> {
+    /// e.g. "flash_boot", "fix_gpt_slot"
+    pub kind: &'a str,
+    /// `(label, path)` input files, checked before anything runs.
+    pub inputs: Vec<(&'a str, &'a str)>,
+    /// Target device serial, for the audit log.
+    pub serial: Option<&'a str>,
+    pub commands: Vec<CommandSpec>,
+    /// Only return the plan; spawn nothing.
+    pub dry_run: bool,
+}
+
+/// Run an operation for the GUI: events go to the webview and the run is
+/// recorded under `$APP_DATA_DIR/history`.
+#[cfg(feature = "gui")]
+pub async fn run_operation(
+    app: &AppHandle,
+    registry: &OperationRegistry,
+    request: OperationRequest<'_>,
+) -> Result<CommandResult, GeekFlashError> {
+    let history = history_dir(app)
+        .inspect_err(|e| eprintln!("[audit] Warning: {e}, history will not be saved"))
+        .ok();
+    let sink = TauriSink(app.clone());
+    run_operation_with(&sink, &SystemRunner, registry, history, request).await
+}
+
+/// Run a command sequence as a named, cancellable operation, spawning its
+/// commands through `runner`.
+///
+/// With `dry_run`, returns the input checks and rendered plan and spawns
+/// nothing. Otherwise the inputs are hashed, the whole run is recorded in
+/// the audit history under `history` (if given), and `operation_started` is
+/// sent with the operation ID before the first command, so the caller can
+/// pass it to `cancel_operation`.
+pub async fn run_operation_with(
+    sink: &dyn EventSink,
+    runner: &dyn CommandRunner,
+    registry: &OperationRegistry,
+    history: Option<PathBuf>,
+    request: OperationRequest<'_>,
+) -> Result<CommandResult, GeekFlashError> {
+    let kind = request.kind;
+    let plan = build_plan(kind, check_inputs(&request.inputs), &request.commands);
+    if request.dry_run {
+        emit_log(sink, None, "stdout", plan.script.clone());
+        return Ok(CommandResult::dry_run(plan));
+    }
+    if let Some(err) = first_problem(&plan.file_checks) {
+        emit_log(sink, None, "stderr", err.to_string());
+        return Err(err);
+    }
+
+    let owned_inputs: Vec<(String, String)> = request
+        .inputs
+        .iter()
+        .map(|(label, path)| (label.to_string(), path.to_string()))
+        .collect();
+    let inputs = tokio::task::spawn_blocking(move || hash_inputs(&owned_inputs))
+        .await
+        .map_err(|e| GeekFlashError::io("hash input files", e))?;
+
+    let (operation_id, cancel) = registry.begin(kind);
+    let audit = AuditRecorder::start(history, &operation_id, kind, request.serial, inputs, plan);
+    registry.track_session(&audit);
+    let sink = Fanout(vec![sink, &audit]);
+    sink.operation_started(&OperationInfo {
+        operation_id: operation_id.clone(),
+        kind: kind.to_string(),
     });
 
-    // Wait for the child with a 30-second timeout
-    let result = timeout(Duration::from_secs(30), async {
-        // Wait for stream readers to finish first
-        let _ = stdout_task.await;
-        let _ = stderr_task.await;
-        child.wait().await
-    })
-    .await;
+    let result = execute_sequence(&sink, runner, &operation_id, request.commands, &cancel).await;
+    registry.finish(&operation_id);
+    audit.finish(&result);
+    result
+}
+
+/// Cancel a running operation (Tauri command).
+///
+/// Kills the current command's whole process group, so tools started under
+/// `sudo` are stopped too. Returns `false` if the operation already finished.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn cancel_operation(
+    registry: State<'_, OperationRegistry>,
+    operation_id: String,
+) -> Result<bool, GeekFlashError> {
+    Ok(registry.cancel(&operation_id))
+}
+
+/// Execute a single command through `runner`.
+///
+/// Streams stdout/stderr line-by-line to `sink` as log lines, and recognised
+/// edl/fastboot progress output as progress events tagged with
+/// `operation_id` and `step_index`. Supports sudo prefix and a per-command
+/// timeout (`CommandSpec.timeout_ms`, or the default for its `StepKind`).
+/// If `cancel` fires, the process (group) is killed and a `Cancelled` result
+/// is returned.
+pub async fn execute_command(
+    sink: &dyn EventSink,
+    runner: &dyn CommandRunner,
+    spec: &CommandSpec,
+    operation_id: &str,
+    step_index: usize,
+    cancel: &CancelToken,
+) -> Result<CommandResult, GeekFlashError> {
+    let start = Instant::now();
+
+    let spawned_program = if spec.sudo { "sudo" } else { &spec.program };
+    let process = runner
+        .spawn(spec)
+        .map_err(|e| GeekFlashError::spawn(spawned_program, e))?;
+    let (stdout, stderr, mut child) = (process.stdout, process.stderr, process.handle);
+
+    // Wait for the child with the step's timeout, unless cancelled first
+    let timeout_ms = spec.effective_timeout_ms();
+    let result = tokio::select! {
+        r = timeout(Duration::from_millis(timeout_ms), async {
+            // Drain both streams to EOF first
+            let (mut output, errors) = tokio::join!(
+                stream_output(sink, stdout, "stdout", operation_id, step_index),
+                stream_output(sink, stderr, "stderr", operation_id, step_index),
+            );
+            output.extend(errors);
+            child.wait().await.map(|exit_code| (exit_code, output))
+        }) => Some(r),
+        _ = cancel.cancelled() => None,
+    };
+    let step = Some(step_index);
 
     let duration_ms = start.elapsed().as_millis() as u64;
 
     match result {
-        Ok(Ok(status)) => {
-            let exit_code = status.code().unwrap_or(-1);
-            let cmd_result = CommandResult {
+        Some(Ok(Ok((exit_code, output)))) => {
+            let mut cmd_result = CommandResult {
                 exit_code,
                 duration_ms,
+                outcome: CommandOutcome::Exited,
+                message: None,
+                plan: None,
             };
 
             // Emit completion summary
-            let summary = LogLine {
-                timestamp: chrono::Utc::now().to_rfc3339(),
-                stream: "stdout".to_string(),
-                content: format!(
-                    "Command exited with code {exit_code} (took {duration_ms}ms)"
-                ),
-            };
-            app.emit("log-line", &summary).ok();
+            emit_log(
+                sink,
+                step,
+                "stdout",
+                format!("Command exited with code {exit_code} (took {duration_ms}ms)"),
+            );
+
+            if exit_code != 0 && spec.sudo && sudo_wants_password(&output) {
+                return Err(GeekFlashError::SudoPasswordRequired {
+                    program: spec.program.clone(),
+                });
+            }
+
+            // Some edl versions exit 0 after failing; trust the output instead
+            if exit_code == 0 {
+                if let Some(reason) = check_output(&spec.matchers, &output) {
+                    emit_log(
+                        sink,
+                        step,
+                        "stderr",
+                        format!("Step failed despite exit code 0: {reason}"),
+                    );
+                    cmd_result.outcome = CommandOutcome::OutputMismatch;
+                    cmd_result.message = Some(reason);
+                }
+            }
 
             Ok(cmd_result)
         }
-        Ok(Err(e)) => Err(format!("Command failed: {e}")),
-        Err(_) => {
+        Some(Ok(Err(e))) => Err(GeekFlashError::io(format!("wait for {}", spec.program), e)),
+        Some(Err(_)) => {
             // Timeout — kill the child process
-            child.kill().await.ok();
-            let summary = LogLine {
-                timestamp: chrono::Utc::now().to_rfc3339(),
-                stream: "stderr".to_string(),
-                content: "Command timed out after 30 seconds".to_string(),
-            };
-            app.emit("log-line", &summary).ok();
-            Err("Command timed out after 30 seconds".to_string())
+            child.kill().await;
+            let result = CommandResult::timed_out(spec.kind, timeout_ms, duration_ms);
+            if let Some(message) = &result.message {
+                emit_log(sink, step, "stderr", message.clone());
+            }
+            Ok(result)
+        }
+        None => {
+            child.kill().await;
+            emit_log(
+                sink,
+                step,
+                "stderr",
+                format!("Command cancelled by user (after {duration_ms}ms)"),
+            );
+            Ok(CommandResult::cancelled(duration_ms))
+        }
+    }
+}
+
+/// Run a short read-only query (e.g. `fastboot getvar`) outside any
+/// operation and return its exit code and output, stdout first.
+pub async fn capture_command(
+    runner: &dyn CommandRunner,
+    spec: &CommandSpec,
+) -> Result<(i32, Vec<String>), GeekFlashError> {
+    let spawned_program = if spec.sudo { "sudo" } else { &spec.program };
+    let process = runner
+        .spawn(spec)
+        .map_err(|e| GeekFlashError::spawn(spawned_program, e))?;
+    let (stdout, stderr, mut child) = (process.stdout, process.stderr, process.handle);
+
+    let timeout_ms = spec.effective_timeout_ms();
+    let result = timeout(Duration::from_millis(timeout_ms), async {
+        let (mut output, mut errors) = (Vec::new(), Vec::new());
+        tokio::join!(
+            for_each_line(stdout, |l| output.push(l)),
+            for_each_line(stderr, |l| errors.push(l)),
+        );
+        output.extend(errors);
+        child.wait().await.map(|exit_code| (exit_code, output))
+    })
+    .await;
+    match result {
+        Ok(Ok(r)) => Ok(r),
+        Ok(Err(e)) => Err(GeekFlashError::io(format!("wait for {}", spec.program), e)),
+        Err(_) => {
+            child.kill().await;
+            Err(GeekFlashError::Timeout {
+                program: spec.program.clone(),
+                timeout_ms,
+                during_write: false,
+            })
+        }
+    }
+}
+
+/// Whether `sudo -n` refused to run a command because it needs a password.
+fn sudo_wants_password(output: &[String]) -> bool {
+    output
+        .iter()
+        .any(|l| l.starts_with("sudo:") && l.contains("password is required"))
+}
+
+/// The error for a step that ran but did not succeed, if any.
+pub fn step_error(spec: &CommandSpec, result: &CommandResult) -> Option<GeekFlashError> {
+    let program = spec.program.clone();
+    match result.outcome {
+        CommandOutcome::Exited | CommandOutcome::DryRun if result.exit_code == 0 => None,
+        CommandOutcome::Exited | CommandOutcome::DryRun => Some(GeekFlashError::NonZeroExit {
+            program,
+            exit_code: result.exit_code,
+        }),
+        CommandOutcome::Cancelled => Some(GeekFlashError::Cancelled),
+        CommandOutcome::TimedOut | CommandOutcome::WriteTimedOut => Some(GeekFlashError::Timeout {
+            program,
+            timeout_ms: spec.effective_timeout_ms(),
+            during_write: result.outcome == CommandOutcome::WriteTimedOut,
+        }),
+        CommandOutcome::OutputMismatch => Some(GeekFlashError::OutputMismatch {
+            program,
+            reason: result.message.clone().unwrap_or_default(),
+        }),
+    }
+}
+
+/// Run a step's [`PostStep`], logging what it reports.
+fn run_post_step(
+    sink: &dyn EventSink,
+    step_index: usize,
+    post_step: &PostStep,
+) -> Result<(), GeekFlashError> {
+    let step = Some(step_index);
+    let lines = match post_step {
+        PostStep::CompareGpt {
+            dump,
+            role,
+            incoming,
+        } => compare_gpt_dump(dump, *role, incoming),
+        PostStep::RecordBackup { partition, path } => record_partition_backup(partition, path),
+    };
+    match lines {
+        Ok(lines) => {
+            for line in lines {
+                emit_log(sink, step, "stdout", line);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            emit_log(sink, step, "stderr", e.to_string());
+            Err(e)
         }
     }
 }
 
 /// Execute a sequence of commands in order.
 ///
-/// Stops immediately if any command returns a non-zero exit code.
-/// Supports per-command delays via `CommandSpec.delay_before_ms`.
+/// Stops at the first failing command (or post-step) and returns it as a
+/// `GeekFlashError` (non-zero exit, timeout, output mismatch), or `Cancelled` if the
+/// sequence is cancelled (delays and device waits are cancellable too).
+/// Supports per-command delays via `CommandSpec.delay_before_ms`, and
+/// device waits via `CommandSpec.wait_for` (fails if the device never shows up).
 pub async fn execute_sequence(
-    app: &AppHandle,
+    sink: &dyn EventSink,
+    runner: &dyn CommandRunner,
+    operation_id: &str,
     commands: Vec<CommandSpec>,
-) -> Result<CommandResult, String> {
+    cancel: &CancelToken,
+) -> Result<CommandResult, GeekFlashError> {
     let mut last_result = CommandResult {
         exit_code: 0,
         duration_ms: 0,
+        outcome: CommandOutcome::Exited,
+        message: None,
+        plan: None,
+    };
+
+    let cancelled = |step| {
+        emit_log(
+            sink,
+            step,
+            "stderr",
+            "Operation cancelled by user".to_string(),
+        );
+        Err(GeekFlashError::Cancelled)
     };
 
     for (i, spec) in commands.iter().enumerate() {
+        let step = Some(i);
+        if cancel.is_cancelled() {
+            return cancelled(step);
+        }
+
+        let cmd_display = command_line(spec);
+        sink.step_started(&StepStarted {
+            operation_id: operation_id.to_string(),
+            step_index: i,
+            total_steps: commands.len(),
+            command_line: cmd_display.clone(),
+        });
+
         // Apply delay before this command if specified
         if spec.delay_before_ms > 0 {
-            let delay_log = LogLine {
-                timestamp: chrono::Utc::now().to_rfc3339(),
-                stream: "stdout".to_string(),
-                content: format!("Waiting {}ms before next command...", spec.delay_before_ms),
+            emit_log(
+                sink,
+                step,
+                "stdout",
+                format!("Waiting {}ms before next command...", spec.delay_before_ms),
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(spec.delay_before_ms)) => {}
+                _ = cancel.cancelled() => return cancelled(step),
+            }
+        }
+
+        // Block until the device re-enumerates in the expected mode
+        if let Some(wait) = &spec.wait_for {
+            let mut target = match &wait.serial {
+                Some(serial) => format!("{:?} device {serial}", wait.mode),
+                None => format!("{:?} device", wait.mode),
+            };
+            if let Some(location) = &wait.location {
+                target.push_str(&format!(" on USB port {location}"));
+            }
+            emit_log(
+                sink,
+                step,
+                "stdout",
+                format!("Waiting up to {}ms for {target}...", wait.timeout_ms),
+            );
+
+            let waited = tokio::select! {
+                r = runner.wait_for_device(wait) => r,
+                _ = cancel.cancelled() => return cancelled(step),
             };
-            app.emit("log-line", &delay_log).ok();
-            tokio::time::sleep(Duration::from_millis(spec.delay_before_ms)).await;
+            match waited {
+                Ok(device) => {
+                    emit_log(
+                        sink,
+                        step,
+                        "stdout",
+                        format!("Found {target} at {}", device.location),
+                    );
+                }
+                Err(e) => {
+                    emit_log(sink, step, "stderr", e.to_string());
+                    return Err(e);
+                }
+            }
         }
 
         // Log which command is about to run
-        let cmd_display = if spec.sudo {
-            format!("sudo {} {}", spec.program, spec.args.join(" "))
-        } else {
-            format!("{} {}", spec.program, spec.args.join(" "))
-        };
-        let start_log = LogLine {
-            timestamp: chrono::Utc::now().to_rfc3339(),
-            stream: "stdout".to_string(),
-            content: format!("[{}/{}] Running: {}", i + 1, commands.len(), cmd_display),
-        };
-        app.emit("log-line", &start_log).ok();
+        emit_log(
+            sink,
+            step,
+            "stdout",
+            format!("[{}/{}] Running: {cmd_display}", i + 1, commands.len()),
+        );
 
-        let args_refs: Vec<&str> = spec.args.iter().map(|s| s.as_str()).collect();
-        let result = execute_command(app, &spec.program, &args_refs, spec.sudo).await?;
+        let result = execute_command(sink, runner, spec, operation_id, i, cancel).await?;
+        sink.step_finished(&StepFinished {
+            operation_id: operation_id.to_string(),
+            step_index: i,
+            command_line: cmd_display,
+            result: result.clone(),
+        });
 
-        if result.exit_code != 0 {
-            return Ok(result);
+        if let Some(err) = step_error(spec, &result) {
+            return Err(err);
+        }
+        if let Some(post_step) = &spec.post_step {
+            run_post_step(sink, i, post_step)?;
         }
 
         last_result = result;
@@ -163,3 +671,493 @@ pub async fn execute_sequence(
 
     Ok(last_result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{RecordingSink, SinkEvent};
+    use crate::models::StepKind;
+
+    #[test]
+    fn test_registry_cancel_running_operation() {
+        let registry = OperationRegistry::default();
+        let (id, token) = registry.begin("flash_boot");
+        assert!(id.starts_with("flash_boot-"));
+        assert!(!token.is_cancelled());
+
+        assert!(registry.cancel(&id));
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_registry_ids_are_unique_and_finished_ops_cannot_be_cancelled() {
+        let registry = OperationRegistry::default();
+        let (a, _) = registry.begin("fix_gpt_slot");
+        let (b, _) = registry.begin("fix_gpt_slot");
+        assert_ne!(a, b);
+
+        registry.finish(&a);
+        assert!(!registry.cancel(&a));
+        assert!(!registry.cancel("no-such-op"));
+        assert!(registry.cancel(&b));
+    }
+
+    #[test]
+    fn test_wait_idle_waits_for_running_operations() {
+        let registry = std::sync::Arc::new(OperationRegistry::default());
+        let (id, _) = registry.begin("flash_boot");
+        assert!(!registry.wait_idle(Duration::from_millis(10)));
+
+        let finisher = std::thread::spawn({
+            let registry = registry.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(20));
+                registry.finish(&id);
+            }
+        });
+        assert!(registry.wait_idle(Duration::from_secs(5)));
+        finisher.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_resolves_after_cancel() {
+        let registry = OperationRegistry::default();
+        let (id, token) = registry.begin("sign_avb");
+
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        registry.cancel(&id);
+        timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("cancelled() should resolve")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_never_token_stays_pending() {
+        let token = CancelToken::never();
+        assert!(!token.is_cancelled());
+        let r = timeout(Duration::from_millis(20), token.cancelled()).await;
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_timeout_defaults_per_step_kind() {
+        let mut spec = CommandSpec {
+            program: "edl".to_string(),
+            args: vec![],
+            sudo: true,
+            delay_before_ms: 0,
+            wait_for: None,
+            kind: StepKind::EdlWrite,
+            timeout_ms: None,
+            matchers: OutputMatchers::default(),
+            post_step: None,
+        };
+        assert_eq!(spec.effective_timeout_ms(), 600_000);
+
+        spec.kind = StepKind::EdlRead;
+        assert_eq!(spec.effective_timeout_ms(), 900_000);
+        spec.kind = StepKind::Sign;
+        assert_eq!(spec.effective_timeout_ms(), 300_000);
+        spec.kind = StepKind::Generic;
+        assert_eq!(spec.effective_timeout_ms(), 30_000);
+
+        spec.timeout_ms = Some(1234);
+        assert_eq!(spec.effective_timeout_ms(), 1234);
+    }
+
+    #[test]
+    fn test_write_timeout_is_reported_as_inconsistent_state() {
+        let write = CommandResult::timed_out(StepKind::EdlWrite, 600_000, 600_010);
+        assert_eq!(write.outcome, CommandOutcome::WriteTimedOut);
+        assert!(write.message.unwrap().contains("inconsistent state"));
+
+        let read = CommandResult::timed_out(StepKind::EdlRead, 900_000, 900_010);
+        assert_eq!(read.outcome, CommandOutcome::TimedOut);
+        assert_eq!(read.exit_code, -1);
+    }
+
+    #[tokio::test]
+    async fn test_for_each_line_splits_carriage_returns() {
+        let output: &[u8] =
+            b"Loading\nProgress: |--| 10.0% Write\rProgress: |##| 100.0% Write\r\nDone\r\n\ntail";
+        let mut lines = Vec::new();
+        for_each_line(output, |l| lines.push(l)).await;
+        assert_eq!(
+            lines,
+            vec![
+                "Loading",
+                "Progress: |--| 10.0% Write",
+                "Progress: |##| 100.0% Write",
+                "Done",
+                "tail",
+            ]
+        );
+    }
+
+    fn lines(output: &[&str]) -> Vec<String> {
+        output.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_check_output_failure_pattern() {
+        let matchers = OutputMatchers::edl_write();
+        let output = lines(&[
+            "main - Trying with no loader given ...",
+            "main - Couldn't find device",
+        ]);
+        let reason = check_output(&matchers, &output).unwrap();
+        assert!(reason.contains("Couldn't find device"), "{reason}");
+    }
+
+    #[test]
+    fn test_check_output_firehose_nak() {
+        let matchers = OutputMatchers::edl_write();
+        let output = lines(&[r#"firehose - <response value="NAK" rawmode="false" />"#]);
+        assert!(check_output(&matchers, &output).is_some());
+    }
+
+    #[test]
+    fn test_check_output_missing_success_pattern() {
+        let matchers = OutputMatchers::edl_write();
+        let output = lines(&["firehose - Target detected", "Progress: |###| 100.0% Write"]);
+        let reason = check_output(&matchers, &output).unwrap();
+        assert!(reason.contains("never appeared"), "{reason}");
+    }
+
+    #[test]
+    fn test_check_output_success() {
+        let matchers = OutputMatchers::edl_write();
+        let output = lines(&["Wrote boot.img to sector 262144."]);
+        assert_eq!(check_output(&matchers, &output), None);
+
+        // No matchers → nothing to check
+        assert_eq!(check_output(&OutputMatchers::default(), &[]), None);
+    }
+
+    #[test]
+    fn test_check_output_fastboot_failure() {
+        let output = lines(&[
+            "FAILED (remote: 'unknown command')",
+            "fastboot: error: Command failed",
+        ]);
+        assert!(check_output(&OutputMatchers::fastboot(), &output).is_some());
+    }
+
+    #[test]
+    fn test_step_error_maps_outcomes() {
+        let spec = CommandSpec {
+            program: "edl".to_string(),
+            args: vec![],
+            sudo: true,
+            delay_before_ms: 0,
+            wait_for: None,
+            kind: StepKind::EdlWrite,
+            timeout_ms: None,
+            matchers: OutputMatchers::edl_write(),
+            post_step: None,
+        };
+        let ok = CommandResult {
+            exit_code: 0,
+            duration_ms: 10,
+            outcome: CommandOutcome::Exited,
+            message: None,
+            plan: None,
+        };
+        assert_eq!(step_error(&spec, &ok), None);
+
+        let failed = CommandResult {
+            exit_code: 2,
+            ..ok.clone()
+        };
+        assert_eq!(
+            step_error(&spec, &failed),
+            Some(GeekFlashError::NonZeroExit {
+                program: "edl".to_string(),
+                exit_code: 2
+            })
+        );
+
+        let timed_out = CommandResult::timed_out(StepKind::EdlWrite, 600_000, 600_010);
+        assert_eq!(
+            step_error(&spec, &timed_out),
+            Some(GeekFlashError::Timeout {
+                program: "edl".to_string(),
+                timeout_ms: 600_000,
+                during_write: true
+            })
+        );
+
+        assert_eq!(
+            step_error(&spec, &CommandResult::cancelled(5)),
+            Some(GeekFlashError::Cancelled)
+        );
+    }
+
+    /// A step running `script` under `sh`, standing in for edl/fastboot.
+    #[cfg(unix)]
+    fn sh(script: &str) -> CommandSpec {
+        CommandSpec {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            sudo: false,
+            delay_before_ms: 0,
+            wait_for: None,
+            kind: StepKind::Generic,
+            timeout_ms: None,
+            matchers: OutputMatchers::default(),
+            post_step: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sequence_runs_every_step_and_reports_events() {
+        let sink = RecordingSink::default();
+        let commands = vec![sh("echo first"), sh("echo second >&2")];
+
+        let result = execute_sequence(
+            &sink,
+            &SystemRunner,
+            "op-1",
+            commands,
+            &CancelToken::never(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.exit_code, 0);
+
+        let steps = sink.finished_steps();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[1].command_line, "sh -c 'echo second >&2'");
+
+        let logs: Vec<LogLine> = sink
+            .events()
+            .into_iter()
+            .filter_map(|e| match e {
+                SinkEvent::Log(line) => Some(line),
+                _ => None,
+            })
+            .collect();
+        let first = logs.iter().find(|l| l.content == "first").unwrap();
+        assert_eq!(
+            (first.stream.as_str(), first.step_index),
+            ("stdout", Some(0))
+        );
+        let second = logs.iter().find(|l| l.content == "second").unwrap();
+        assert_eq!(
+            (second.stream.as_str(), second.step_index),
+            ("stderr", Some(1))
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sequence_stops_at_non_zero_exit() {
+        let sink = RecordingSink::default();
+        let commands = vec![sh("exit 3"), sh("echo unreachable")];
+
+        let err = execute_sequence(
+            &sink,
+            &SystemRunner,
+            "op-1",
+            commands,
+            &CancelToken::never(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err,
+            GeekFlashError::NonZeroExit {
+                program: "sh".to_string(),
+                exit_code: 3
+            }
+        );
+        assert_eq!(sink.finished_steps().len(), 1);
+        assert!(!sink.log_lines().contains(&"unreachable".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sequence_fails_on_output_mismatch() {
+        let sink = RecordingSink::default();
+        let mut step = sh("echo 'Error: sahara handshake failed'");
+        step.matchers = OutputMatchers::edl_write();
+
+        let err = execute_sequence(
+            &sink,
+            &SystemRunner,
+            "op-1",
+            vec![step],
+            &CancelToken::never(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code(), "OUTPUT_MISMATCH");
+        let steps = sink.finished_steps();
+        assert_eq!(steps[0].result.exit_code, 0);
+        assert_eq!(steps[0].result.outcome, CommandOutcome::OutputMismatch);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sequence_times_out() {
+        let sink = RecordingSink::default();
+        let mut step = sh("sleep 5");
+        step.kind = StepKind::EdlWrite;
+        step.timeout_ms = Some(200);
+
+        let started = Instant::now();
+        let err = execute_sequence(
+            &sink,
+            &SystemRunner,
+            "op-1",
+            vec![step],
+            &CancelToken::never(),
+        )
+        .await
+        .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(
+            err,
+            GeekFlashError::Timeout {
+                program: "sh".to_string(),
+                timeout_ms: 200,
+                during_write: true
+            }
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sequence_cancel_kills_running_step() {
+        let registry = OperationRegistry::default();
+        let (id, token) = registry.begin("flash_boot");
+        let sink = RecordingSink::default();
+
+        let run = execute_sequence(
+            &sink,
+            &SystemRunner,
+            &id,
+            vec![sh("sleep 5"), sh("echo no")],
+            &token,
+        );
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            registry.cancel(&id);
+        };
+        let (result, _) = tokio::join!(run, cancel);
+
+        assert_eq!(result.unwrap_err(), GeekFlashError::Cancelled);
+        let steps = sink.finished_steps();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].result.outcome, CommandOutcome::Cancelled);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_cancel_kills_a_tool_in_its_own_session() {
+        // Like `edl` under sudo with a pty: outside the step's process group,
+        // and ignoring SIGTERM
+        let dir = tempfile::TempDir::new().unwrap();
+        let pid_file = dir.path().join("tool.pid");
+        let script = format!(
+            "setsid sh -c 'trap \"\" TERM; echo $$ > {}; exec sleep 30' & wait",
+            pid_file.display()
+        );
+        let registry = OperationRegistry::default();
+        let (id, token) = registry.begin("flash_boot");
+        let sink = RecordingSink::default();
+
+        let run = execute_sequence(&sink, &SystemRunner, &id, vec![sh(&script)], &token);
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            registry.cancel(&id);
+        };
+        let (result, _) = tokio::join!(run, cancel);
+        assert_eq!(result.unwrap_err(), GeekFlashError::Cancelled);
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // Reaped, or a zombie left for init
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(
+            stat.map_or(true, |s| s.contains(") Z ")),
+            "tool {} is still running",
+            pid.trim()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_progress_lines_become_progress_events() {
+        let sink = RecordingSink::default();
+        let step = sh(
+            r"printf 'Progress: |#---| 25.0%% Write (Sector 0x100 of 0x400)\rProgress: |####| 100.0%% Write (Sector 0x400 of 0x400)\nWrote boot.img\n'",
+        );
+
+        execute_sequence(
+            &sink,
+            &SystemRunner,
+            "op-1",
+            vec![step],
+            &CancelToken::never(),
+        )
+        .await
+        .unwrap();
+        let percents: Vec<f32> = sink
+            .events()
+            .into_iter()
+            .filter_map(|e| match e {
+                SinkEvent::Progress(p) => Some(p.percent),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(percents, vec![25.0, 100.0]);
+        assert!(sink.log_lines().contains(&"Wrote boot.img".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_operation_records_history_and_honours_dry_run() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let input = dir.path().join("boot.img");
+        std::fs::write(&input, b"abc").unwrap();
+        let input = input.to_str().unwrap();
+        let registry = OperationRegistry::default();
+        let sink = RecordingSink::default();
+        let request = |dry_run| OperationRequest {
+            kind: "flash_boot",
+            inputs: vec![("boot_img", input)],
+            serial: Some("1234"),
+            commands: vec![sh("echo flashed")],
+            dry_run,
+        };
+
+        let planned = run_operation_with(&sink, &SystemRunner, &registry, None, request(true))
+            .await
+            .unwrap();
+        assert_eq!(planned.outcome, CommandOutcome::DryRun);
+        assert!(sink.finished_steps().is_empty());
+
+        let history = dir.path().join("history");
+        run_operation_with(
+            &sink,
+            &SystemRunner,
+            &registry,
+            Some(history.clone()),
+            request(false),
+        )
+        .await
+        .unwrap();
+        let records = crate::audit::list_records(&history);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].success);
+        let record = registry.last_session().unwrap();
+        assert_eq!(record.steps.len(), 1);
+        assert!(record.log.iter().any(|l| l.content == "flashed"));
+        assert!(record.inputs[0].sha256.is_some());
+    }
+}
@@ -1,51 +1,896 @@
-use tauri::AppHandle;
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, State};
 
-use crate::executor::execute_sequence;
-use crate::models::{CommandResult, CommandSpec};
+#[cfg(feature = "gui")]
+use crate::backup::{backups_dir, create_backup_set, with_partition_backups};
+use crate::device::{
+    current_target, edl_target_args, fastboot_target_args, find_device, locate_target,
+    probe_device_status,
+};
+use crate::events::EventSink;
+#[cfg(feature = "gui")]
+use crate::events::TauriSink;
+use crate::executor::{capture_command, emit_log};
+#[cfg(feature = "gui")]
+use crate::executor::{run_operation, OperationRegistry, OperationRequest};
+use crate::gpt::{active_slot, load_gpt, SLOT_PARTITION};
+#[cfg(feature = "gui")]
+use crate::models::CommandResult;
+use crate::models::{
+    CommandSpec, DeviceMode, DeviceStatus, DeviceTarget, DeviceWait, FlashTransport,
+    GeekFlashError, GptRole, GptTable, OutputMatchers, Slot, SlotSelector, StepKind,
+};
+use crate::plan::{check_file, first_problem};
+use crate::runner::CommandRunner;
+#[cfg(feature = "gui")]
+use crate::runner::SystemRunner;
 
-/// Build the command sequence for flashing boot_a partition via EDL.
+/// How long to wait for the 9008 device to show up after `fastboot oem edl`.
+const EDL_ENUMERATION_TIMEOUT_MS: u64 = 30_000;
+
+/// `fastboot flash` of a large image over USB 2.0.
+const FASTBOOT_FLASH_TIMEOUT_MS: u64 = 10 * 60_000;
+
+/// `fastboot getvar` answers at once from a device in fastboot mode; without
+/// one it waits forever.
+const GETVAR_TIMEOUT_MS: u64 = 10_000;
+
+/// Stands in for the `_a`/`_b` suffix of `active` in a dry run, which must
+/// not ask the device.
+pub const UNRESOLVED_SLOT_SUFFIX: &str = "_<active>";
+
+/// Build the command sequence for flashing the boot partition of each of
+/// `slots` via EDL.
 ///
 /// Sequence:
 /// 1. `fastboot oem edl` — switch device into EDL mode
-/// 2. (2s delay) `sudo edl w boot_a <boot_img> --loader=<loader>` — write boot image
-pub fn build_flash_boot_commands(boot_img: &str, loader: &str) -> Vec<CommandSpec> {
-    vec![
-        CommandSpec {
-            program: "fastboot".to_string(),
-            args: vec!["oem".to_string(), "edl".to_string()],
-            sudo: false,
-            delay_before_ms: 0,
-        },
-        CommandSpec {
+/// 2. (wait for the 9008 device, up to 30s) `sudo edl w boot_<slot> <boot_img> --loader=<loader>`
+///    — write boot image, once per slot
+///
+/// Every step is pinned to `target` so the right tablet is written on a
+/// crowded bench: `fastboot -s <serial>`, then the 9008 device on the same
+/// USB port and `edl --serial_number=<edl_serial>`.
+pub fn build_flash_boot_commands(
+    boot_img: &str,
+    loader: &str,
+    slots: &[Slot],
+    target: &DeviceTarget,
+) -> Vec<CommandSpec> {
+    let partitions = slot_partitions(SLOT_PARTITION, Some(slots));
+    build_boot_write_commands(boot_img, loader, &partitions, target)
+}
+
+/// [`build_flash_boot_commands`] for boot partitions already named, e.g.
+/// from [`selected_partitions`].
+pub fn build_boot_write_commands(
+    boot_img: &str,
+    loader: &str,
+    partitions: &[String],
+    target: &DeviceTarget,
+) -> Vec<CommandSpec> {
+    let mut fastboot_args = fastboot_target_args(target.serial.as_deref());
+    fastboot_args.extend(["oem".to_string(), "edl".to_string()]);
+
+    let mut commands = vec![CommandSpec {
+        program: "fastboot".to_string(),
+        args: fastboot_args,
+        sudo: false,
+        delay_before_ms: 0,
+        wait_for: None,
+        kind: StepKind::Generic,
+        timeout_ms: None,
+        matchers: OutputMatchers::fastboot(),
+        post_step: None,
+    }];
+    for (i, partition) in partitions.iter().enumerate() {
+        let mut edl_args = vec![
+            "w".to_string(),
+            partition.clone(),
+            boot_img.to_string(),
+            format!("--loader={}", loader),
+        ];
+        edl_args.extend(edl_target_args(target.edl_serial.as_deref()));
+
+        commands.push(CommandSpec {
             program: "edl".to_string(),
-            args: vec![
-                "w".to_string(),
-                "boot_a".to_string(),
-                boot_img.to_string(),
-                format!("--loader={}", loader),
-            ],
+            args: edl_args,
             sudo: true,
-            delay_before_ms: 2000,
+            delay_before_ms: 0,
+            // Only the first write has to wait for the mode switch; the
+            // fastboot serial is gone once the device is in 9008 mode
+            wait_for: (i == 0).then(|| DeviceWait {
+                mode: DeviceMode::Edl,
+                serial: None,
+                location: target.location.clone(),
+                timeout_ms: EDL_ENUMERATION_TIMEOUT_MS,
+            }),
+            kind: StepKind::EdlWrite,
+            timeout_ms: None,
+            matchers: OutputMatchers::edl_write(),
+            post_step: None,
+        });
+    }
+    commands
+}
+
+/// The partitions to write: `partition` itself, or its copy in each of
+/// `slots` (`init_boot` → `init_boot_a`).
+pub fn slot_partitions(partition: &str, slots: Option<&[Slot]>) -> Vec<String> {
+    match slots {
+        Some(slots) => slots
+            .iter()
+            .map(|slot| format!("{partition}{}", slot.suffix()))
+            .collect(),
+        None => vec![partition.to_string()],
+    }
+}
+
+/// The partitions `image` must fit: `partitions`, or both slots of
+/// `partition` while the active one is unresolved.
+pub fn fit_partitions(partition: &str, partitions: &[String]) -> Vec<String> {
+    if partitions
+        .iter()
+        .any(|p| p.ends_with(UNRESOLVED_SLOT_SUFFIX))
+    {
+        slot_partitions(partition, Some(&[Slot::A, Slot::B]))
+    } else {
+        partitions.to_vec()
+    }
+}
+
+/// Refuse to write `image` to a partition that `table` (loaded from `gpt`)
+/// does not list, or that is smaller than the image.
+pub fn check_image_fits(
+    table: &GptTable,
+    gpt: &str,
+    partitions: &[String],
+    image: &str,
+) -> Result<(), GeekFlashError> {
+    let check = check_file("image", image);
+    if let Some(err) = first_problem(std::slice::from_ref(&check)) {
+        return Err(err);
+    }
+    let image_bytes = check.size_bytes.unwrap_or_default();
+    for name in partitions {
+        let partition = table
+            .partitions
+            .iter()
+            .find(|p| &p.name == name)
+            .ok_or_else(|| {
+                GeekFlashError::invalid_input("partition", format!("{name} is not in {gpt}"))
+            })?;
+        if image_bytes > partition.size_bytes {
+            return Err(GeekFlashError::invalid_input(
+                "image",
+                format!(
+                    "{image} is {image_bytes} bytes but {name} holds only {} bytes",
+                    partition.size_bytes
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `requested`, or else fastboot if a device (with `serial`, if given) is
+/// in bootloader fastboot, else EDL.
+pub fn choose_transport(
+    requested: Option<FlashTransport>,
+    status: &DeviceStatus,
+    serial: Option<&str>,
+) -> FlashTransport {
+    match requested {
+        Some(transport) => transport,
+        None if find_device(status, DeviceMode::Fastboot, serial).is_some() => {
+            FlashTransport::Fastboot
+        }
+        None => FlashTransport::Edl,
+    }
+}
+
+/// Build the command sequence writing `image` to each of `partitions`:
+///
+/// - EDL: `sudo edl w <partition> <image> --loader=<loader>` per partition,
+///   after waiting for the 9008 device
+/// - fastboot: `fastboot flash <partition> <image>` per partition, after
+///   waiting for the bootloader
+///
+/// `loader` is only used for EDL. Every step is pinned to `target`, as in
+/// [`build_flash_boot_commands`].
+pub fn build_flash_partition_commands(
+    image: &str,
+    partitions: &[String],
+    transport: FlashTransport,
+    loader: &str,
+    target: &DeviceTarget,
+) -> Vec<CommandSpec> {
+    let wait_for = |i: usize, mode| {
+        (i == 0).then(|| DeviceWait {
+            mode,
+            serial: match mode {
+                DeviceMode::Fastboot => target.serial.clone(),
+                _ => None,
+            },
+            location: target.location.clone(),
+            timeout_ms: EDL_ENUMERATION_TIMEOUT_MS,
+        })
+    };
+    partitions
+        .iter()
+        .enumerate()
+        .map(|(i, partition)| match transport {
+            FlashTransport::Edl => {
+                let mut args = vec![
+                    "w".to_string(),
+                    partition.clone(),
+                    image.to_string(),
+                    format!("--loader={}", loader),
+                ];
+                args.extend(edl_target_args(target.edl_serial.as_deref()));
+                CommandSpec {
+                    program: "edl".to_string(),
+                    args,
+                    sudo: true,
+                    delay_before_ms: 0,
+                    wait_for: wait_for(i, DeviceMode::Edl),
+                    kind: StepKind::EdlWrite,
+                    timeout_ms: None,
+                    matchers: OutputMatchers::edl_write(),
+                    post_step: None,
+                }
+            }
+            FlashTransport::Fastboot => {
+                let mut args = fastboot_target_args(target.serial.as_deref());
+                args.extend(["flash".to_string(), partition.clone(), image.to_string()]);
+                CommandSpec {
+                    program: "fastboot".to_string(),
+                    args,
+                    sudo: false,
+                    delay_before_ms: 0,
+                    wait_for: wait_for(i, DeviceMode::Fastboot),
+                    kind: StepKind::Generic,
+                    timeout_ms: Some(FASTBOOT_FLASH_TIMEOUT_MS),
+                    matchers: OutputMatchers::fastboot(),
+                    post_step: None,
+                }
+            }
+        })
+        .collect()
+}
+
+/// The slot in `fastboot getvar current-slot` output (`current-slot: b`).
+pub fn parse_current_slot(lines: &[String]) -> Option<Slot> {
+    lines.iter().find_map(|line| {
+        let value = line.trim().strip_prefix("current-slot:")?.trim();
+        match value.trim_start_matches('_') {
+            "a" => Some(Slot::A),
+            "b" => Some(Slot::B),
+            _ => None,
+        }
+    })
+}
+
+/// Ask the device in fastboot mode which slot it booted from.
+async fn current_slot(
+    runner: &dyn CommandRunner,
+    serial: Option<&str>,
+) -> Result<Slot, GeekFlashError> {
+    let mut args = fastboot_target_args(serial);
+    args.extend(["getvar".to_string(), "current-slot".to_string()]);
+    let spec = CommandSpec {
+        program: "fastboot".to_string(),
+        args,
+        sudo: false,
+        delay_before_ms: 0,
+        wait_for: None,
+        kind: StepKind::Generic,
+        timeout_ms: Some(GETVAR_TIMEOUT_MS),
+        matchers: OutputMatchers::fastboot(),
+        post_step: None,
+    };
+    let (exit_code, lines) = capture_command(runner, &spec).await?;
+    match parse_current_slot(&lines) {
+        Some(slot) if exit_code == 0 => Ok(slot),
+        _ => Err(GeekFlashError::OutputMismatch {
+            program: spec.program,
+            reason: format!(
+                "getvar current-slot did not report a slot: {}",
+                lines.join(" | ")
+            ),
+        }),
+    }
+}
+
+fn log_active_slot(sink: &dyn EventSink, slot: Slot, source: &str) {
+    let partition = format!("{SLOT_PARTITION}{}", slot.suffix());
+    emit_log(
+        sink,
+        None,
+        "stdout",
+        format!("Active slot resolves to {partition} (from {source})"),
+    );
+}
+
+/// The boot slots `selector` stands for. `Active` asks the device with
+/// `fastboot getvar current-slot`, falling back to the slot flags in the
+/// primary GPT at `gpt_main`; where the answer came from is logged.
+pub async fn resolve_slots(
+    sink: &dyn EventSink,
+    runner: &dyn CommandRunner,
+    selector: SlotSelector,
+    serial: Option<&str>,
+    gpt_main: Option<&str>,
+) -> Result<Vec<Slot>, GeekFlashError> {
+    let slot = match selector {
+        SlotSelector::A => return Ok(vec![Slot::A]),
+        SlotSelector::B => return Ok(vec![Slot::B]),
+        SlotSelector::Both => return Ok(vec![Slot::A, Slot::B]),
+        SlotSelector::Active => match current_slot(runner, serial).await {
+            Ok(slot) => {
+                log_active_slot(sink, slot, "fastboot getvar current-slot");
+                slot
+            }
+            Err(e) => {
+                emit_log(
+                    sink,
+                    None,
+                    "stderr",
+                    format!("Could not ask the device: {e}"),
+                );
+                let Some(gpt_main) = gpt_main else {
+                    return Err(e);
+                };
+                let slot = active_slot(&load_gpt("gpt_main", gpt_main, GptRole::Primary)?)
+                    .ok_or_else(|| {
+                        GeekFlashError::invalid_input(
+                            "gpt_main",
+                            format!("{gpt_main}: the boot_a/boot_b flags do not single out a slot"),
+                        )
+                    })?;
+                log_active_slot(sink, slot, &format!("slot flags in {gpt_main}"));
+                slot
+            }
         },
-    ]
+    };
+    Ok(vec![slot])
+}
+
+/// The copies of `partition` that `selector` writes, e.g. `boot_a`. A dry
+/// run does not ask the device, so there `active` is left unresolved and
+/// planned as `<partition>_<active>`.
+pub async fn selected_partitions(
+    sink: &dyn EventSink,
+    runner: &dyn CommandRunner,
+    partition: &str,
+    selector: SlotSelector,
+    serial: Option<&str>,
+    gpt_main: Option<&str>,
+    dry_run: bool,
+) -> Result<Vec<String>, GeekFlashError> {
+    if dry_run && selector == SlotSelector::Active {
+        emit_log(
+            sink,
+            None,
+            "stdout",
+            "Slot: active (resolved at run time)".to_string(),
+        );
+        return Ok(vec![format!("{partition}{UNRESOLVED_SLOT_SUFFIX}")]);
+    }
+    let slots = resolve_slots(sink, runner, selector, serial, gpt_main).await?;
+    Ok(slot_partitions(partition, Some(&slots)))
 }
 
+/// [`choose_transport`] and [`locate_target`] against the devices connected
+/// now. A dry run probes nothing and plans `requested`, or EDL.
+pub fn connect_target(
+    sink: &dyn EventSink,
+    requested: Option<FlashTransport>,
+    serial: Option<&str>,
+    edl_serial: Option<&str>,
+    dry_run: bool,
+) -> Result<(FlashTransport, DeviceTarget), GeekFlashError> {
+    if dry_run {
+        if requested.is_none() {
+            emit_log(
+                sink,
+                None,
+                "stdout",
+                "Transport: edl (fastboot if the device is in bootloader at run time)".to_string(),
+            );
+        }
+        let target = current_target(serial, edl_serial, true)?;
+        return Ok((requested.unwrap_or(FlashTransport::Edl), target));
+    }
+    let status = probe_device_status()?;
+    Ok((
+        choose_transport(requested, &status, serial),
+        locate_target(&status, serial, edl_serial)?,
+    ))
+}
+
+/// Shared by [`flash_boot`] and [`restore_boot`], which differ only in the
+/// image and the operation name.
+#[cfg(feature = "gui")]
+#[allow(clippy::too_many_arguments)]
+async fn write_boot(
+    app: AppHandle,
+    registry: State<'_, OperationRegistry>,
+    kind: &str,
+    boot_img: String,
+    loader: String,
+    serial: Option<String>,
+    edl_serial: Option<String>,
+    slot: Option<SlotSelector>,
+    gpt_main: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    let dry_run = dry_run.unwrap_or(false);
+    let partitions = selected_partitions(
+        &TauriSink(app.clone()),
+        &SystemRunner,
+        SLOT_PARTITION,
+        slot.unwrap_or_default(),
+        serial.as_deref(),
+        gpt_main.as_deref(),
+        dry_run,
+    )
+    .await?;
+    let target = current_target(serial.as_deref(), edl_serial.as_deref(), dry_run)?;
+    let backup_dir = create_backup_set(&backups_dir(&app)?, serial.as_deref(), kind, dry_run)?;
+    let commands = build_boot_write_commands(&boot_img, &loader, &partitions, &target);
+    let request = OperationRequest {
+        kind,
+        inputs: vec![("boot_img", &boot_img), ("loader", &loader)],
+        serial: serial.as_deref(),
+        commands: with_partition_backups(commands, &backup_dir),
+        dry_run,
+    };
+    run_operation(&app, &registry, request).await
+}
+
+/// `slot` defaults to `a`; `gpt_main` is only read to resolve `active`
+/// when the device cannot be asked. The partitions are backed up before they are
+/// written. `serial` is the fastboot serial; `edl_serial`, if given, is
+/// passed to `edl` as the chip serial.
+#[cfg(feature = "gui")]
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn flash_boot(
     app: AppHandle,
+    registry: State<'_, OperationRegistry>,
     boot_img: String,
     loader: String,
-) -> Result<CommandResult, String> {
-    let commands = build_flash_boot_commands(&boot_img, &loader);
-    execute_sequence(&app, commands).await
+    serial: Option<String>,
+    edl_serial: Option<String>,
+    slot: Option<SlotSelector>,
+    gpt_main: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    write_boot(
+        app,
+        registry,
+        "flash_boot",
+        boot_img,
+        loader,
+        serial,
+        edl_serial,
+        slot,
+        gpt_main,
+        dry_run,
+    )
+    .await
 }
 
+/// Takes the same slot options as [`flash_boot`].
+#[cfg(feature = "gui")]
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn restore_boot(
     app: AppHandle,
+    registry: State<'_, OperationRegistry>,
     boot_img: String,
     loader: String,
-) -> Result<CommandResult, String> {
-    let commands = build_flash_boot_commands(&boot_img, &loader);
-    execute_sequence(&app, commands).await
+    serial: Option<String>,
+    edl_serial: Option<String>,
+    slot: Option<SlotSelector>,
+    gpt_main: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    write_boot(
+        app,
+        registry,
+        "restore_boot",
+        boot_img,
+        loader,
+        serial,
+        edl_serial,
+        slot,
+        gpt_main,
+        dry_run,
+    )
+    .await
+}
+
+/// Write `image` to `partition`, or with `slot` to its `_a`/`_b` copies.
+///
+/// The image must fit every target partition as listed in `gpt`, the primary
+/// GPT of the LUN holding it. Without `transport`, fastboot is used if the
+/// device is in bootloader fastboot, EDL otherwise; EDL needs `loader`.
+/// Over EDL the current contents are backed up first (see
+/// [`with_partition_backups`]).
+#[cfg(feature = "gui")]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn flash_partition(
+    app: AppHandle,
+    registry: State<'_, OperationRegistry>,
+    partition: String,
+    image: String,
+    gpt: String,
+    slot: Option<SlotSelector>,
+    transport: Option<FlashTransport>,
+    loader: Option<String>,
+    serial: Option<String>,
+    edl_serial: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    let dry_run = dry_run.unwrap_or(false);
+    let sink = TauriSink(app.clone());
+    let table = load_gpt("gpt", &gpt, GptRole::Primary)?;
+    let partitions = match slot {
+        Some(selector) => {
+            selected_partitions(
+                &sink,
+                &SystemRunner,
+                &partition,
+                selector,
+                serial.as_deref(),
+                Some(&gpt),
+                dry_run,
+            )
+            .await?
+        }
+        None => vec![partition.clone()],
+    };
+    check_image_fits(
+        &table,
+        &gpt,
+        &fit_partitions(&partition, &partitions),
+        &image,
+    )?;
+
+    let (transport, target) = connect_target(
+        &sink,
+        transport,
+        serial.as_deref(),
+        edl_serial.as_deref(),
+        dry_run,
+    )?;
+    let mut inputs = vec![("image", image.as_str())];
+    if transport == FlashTransport::Edl {
+        let loader = loader.as_deref().ok_or_else(|| {
+            GeekFlashError::invalid_input("loader", "a Firehose loader is needed to write over EDL")
+        })?;
+        inputs.push(("loader", loader));
+    }
+    let mut commands = build_flash_partition_commands(
+        &image,
+        &partitions,
+        transport,
+        loader.as_deref().unwrap_or_default(),
+        &target,
+    );
+    // fastboot cannot read partitions back
+    if transport == FlashTransport::Edl {
+        let backup_dir = create_backup_set(
+            &backups_dir(&app)?,
+            serial.as_deref(),
+            "flash_partition",
+            dry_run,
+        )?;
+        commands = with_partition_backups(commands, &backup_dir);
+    }
+    let request = OperationRequest {
+        kind: "flash_partition",
+        inputs,
+        serial: serial.as_deref(),
+        commands,
+        dry_run,
+    };
+    run_operation(&app, &registry, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RecordingSink;
+    use crate::gpt::parse_gpt;
+    use crate::gpt::tests::{build_image, Y700_PARTS};
+    use crate::models::DeviceInfo;
+    use crate::plan::command_line;
+    use crate::runner::{ScriptedCommand, ScriptedRunner};
+
+    #[test]
+    fn test_one_write_per_slot() {
+        let commands = build_flash_boot_commands(
+            "boot.img",
+            "prog.elf",
+            &[Slot::A, Slot::B],
+            &DeviceTarget::default(),
+        );
+        let lines: Vec<String> = commands.iter().map(command_line).collect();
+        assert_eq!(
+            lines,
+            [
+                "fastboot oem edl",
+                "sudo edl w boot_a boot.img --loader=prog.elf",
+                "sudo edl w boot_b boot.img --loader=prog.elf",
+            ]
+        );
+        assert!(commands[1].wait_for.is_some());
+        assert!(commands[2].wait_for.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_edl_device_is_found_by_port_not_fastboot_serial() {
+        let target = DeviceTarget {
+            serial: Some("FB1234".to_string()),
+            location: Some("1-1.2".to_string()),
+            edl_serial: None,
+        };
+        let commands = build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &target);
+        assert_eq!(command_line(&commands[0]), "fastboot -s FB1234 oem edl");
+        assert_eq!(
+            command_line(&commands[1]),
+            "sudo edl w boot_a boot.img --loader=prog.elf"
+        );
+
+        // Two tablets in 9008 mode, neither reporting a serial
+        let edl = |location: &str| DeviceInfo {
+            vendor_id: 0x05c6,
+            product_id: 0x9008,
+            serial: None,
+            product: Some("QUSB__BULK".to_string()),
+            location: location.to_string(),
+            mode: DeviceMode::Edl,
+        };
+        let status = DeviceStatus {
+            mode: DeviceMode::Edl,
+            devices: vec![edl("1-1.1"), edl("1-1.2")],
+            timestamp: 0,
+        };
+        let wait = commands[1].wait_for.as_ref().unwrap();
+        assert_eq!(wait.serial, None);
+        let device = crate::device::wait_for_device(
+            || std::future::ready(Ok(status.clone())),
+            wait.mode,
+            wait.serial.as_deref(),
+            wait.location.as_deref(),
+            std::time::Duration::from_millis(10),
+            std::time::Duration::from_millis(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(device.location, "1-1.2");
+
+        assert_eq!(
+            locate_target(&status, Some("FB1234"), None)
+                .unwrap_err()
+                .code(),
+            "INVALID_INPUT"
+        );
+    }
+
+    #[test]
+    fn test_flash_partition_commands() {
+        let partitions = slot_partitions("init_boot", Some(&[Slot::A, Slot::B]));
+        assert_eq!(partitions, ["init_boot_a", "init_boot_b"]);
+        assert_eq!(
+            slot_partitions("vbmeta_system_a", None),
+            ["vbmeta_system_a"]
+        );
+
+        let edl = build_flash_partition_commands(
+            "init_boot.img",
+            &partitions,
+            FlashTransport::Edl,
+            "prog.elf",
+            &DeviceTarget::default(),
+        );
+        let lines: Vec<String> = edl.iter().map(command_line).collect();
+        assert_eq!(
+            lines,
+            [
+                "sudo edl w init_boot_a init_boot.img --loader=prog.elf",
+                "sudo edl w init_boot_b init_boot.img --loader=prog.elf",
+            ]
+        );
+        assert_eq!(edl[0].wait_for.as_ref().unwrap().mode, DeviceMode::Edl);
+
+        let fastboot = build_flash_partition_commands(
+            "dtbo.img",
+            &["dtbo_a".to_string()],
+            FlashTransport::Fastboot,
+            "",
+            &DeviceTarget {
+                serial: Some("1234".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            command_line(&fastboot[0]),
+            "fastboot -s 1234 flash dtbo_a dtbo.img"
+        );
+        assert_eq!(
+            fastboot[0].wait_for.as_ref().unwrap().mode,
+            DeviceMode::Fastboot
+        );
+    }
+
+    #[test]
+    fn test_image_must_fit_the_partition() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let table = parse_gpt(
+            &build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+            GptRole::Primary,
+        )
+        .unwrap();
+        let image = dir.path().join("boot.img");
+        let file = std::fs::File::create(&image).unwrap();
+        let image = image.to_str().unwrap();
+        let boot_a = ["boot_a".to_string()];
+
+        // boot_a is 8192 sectors of 4096 bytes
+        file.set_len(8192 * 4096).unwrap();
+        assert!(check_image_fits(&table, "gpt.bin", &boot_a, image).is_ok());
+        file.set_len(8192 * 4096 + 1).unwrap();
+        let err = check_image_fits(&table, "gpt.bin", &boot_a, image).unwrap_err();
+        assert_eq!(err.context()["field"], "image");
+
+        let err = check_image_fits(&table, "gpt.bin", &["dtbo_a".to_string()], image).unwrap_err();
+        assert!(
+            err.to_string().contains("dtbo_a is not in gpt.bin"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_choose_transport() {
+        let status = |mode| DeviceStatus {
+            mode,
+            devices: vec![DeviceInfo {
+                vendor_id: 0x18d1,
+                product_id: 0xd00d,
+                serial: Some("1234".to_string()),
+                product: None,
+                location: "1-1".to_string(),
+                mode,
+            }],
+            timestamp: 0,
+        };
+        let fastboot = status(DeviceMode::Fastboot);
+        assert_eq!(
+            choose_transport(None, &fastboot, None),
+            FlashTransport::Fastboot
+        );
+        assert_eq!(
+            choose_transport(None, &fastboot, Some("5678")),
+            FlashTransport::Edl
+        );
+        let edl = status(DeviceMode::Edl);
+        assert_eq!(choose_transport(None, &edl, None), FlashTransport::Edl);
+        assert_eq!(
+            choose_transport(Some(FlashTransport::Edl), &fastboot, None),
+            FlashTransport::Edl
+        );
+    }
+
+    #[test]
+    fn test_parse_current_slot() {
+        let lines = |s: &str| s.lines().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            parse_current_slot(&lines("current-slot: b\nFinished. Total time: 0.001s")),
+            Some(Slot::B)
+        );
+        assert_eq!(
+            parse_current_slot(&lines("current-slot: _a")),
+            Some(Slot::A)
+        );
+        assert_eq!(
+            parse_current_slot(&lines("current-slot: \nFinished.")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_active_slot_from_fastboot() {
+        let runner = ScriptedRunner::new(vec![ScriptedCommand {
+            args: Some(vec![
+                "-s".to_string(),
+                "1234".to_string(),
+                "getvar".to_string(),
+                "current-slot".to_string(),
+            ]),
+            stderr: "current-slot: b\nFinished. Total time: 0.001s\n".to_string(),
+            ..ScriptedCommand::new("fastboot")
+        }]);
+        let sink = RecordingSink::default();
+        let slots = resolve_slots(&sink, &runner, SlotSelector::Active, Some("1234"), None)
+            .await
+            .unwrap();
+        assert_eq!(slots, [Slot::B]);
+        assert_eq!(
+            sink.log_lines(),
+            ["Active slot resolves to boot_b (from fastboot getvar current-slot)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_leaves_active_slot_unresolved() {
+        let runner = ScriptedRunner::new(Vec::new());
+        let sink = RecordingSink::default();
+        let partitions = selected_partitions(
+            &sink,
+            &runner,
+            "init_boot",
+            SlotSelector::Active,
+            Some("1234"),
+            None,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(partitions, ["init_boot_<active>"]);
+        assert!(runner.calls().is_empty());
+        assert_eq!(sink.log_lines(), ["Slot: active (resolved at run time)"]);
+        assert_eq!(
+            fit_partitions("init_boot", &partitions),
+            ["init_boot_a", "init_boot_b"]
+        );
+
+        let commands = build_boot_write_commands(
+            "boot.img",
+            "prog.elf",
+            &[format!("boot{UNRESOLVED_SLOT_SUFFIX}")],
+            &DeviceTarget::default(),
+        );
+        assert_eq!(
+            command_line(&commands[1]),
+            "sudo edl w 'boot_<active>' boot.img --loader=prog.elf"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_active_slot_falls_back_to_gpt() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let gpt_main = dir.path().join("gpt_main.bin");
+        std::fs::write(
+            &gpt_main,
+            build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        )
+        .unwrap();
+        let gpt_main = gpt_main.to_str().unwrap();
+        let unknown = || ScriptedCommand {
+            exit_code: 1,
+            stderr: "getvar:current-slot FAILED (remote: 'unknown command')\n".to_string(),
+            ..ScriptedCommand::new("fastboot")
+        };
+
+        let runner = ScriptedRunner::new(vec![unknown()]);
+        let sink = RecordingSink::default();
+        let slots = resolve_slots(&sink, &runner, SlotSelector::Active, None, Some(gpt_main))
+            .await
+            .unwrap();
+        assert_eq!(slots, [Slot::A]);
+        assert_eq!(
+            sink.log_lines()[1],
+            format!("Active slot resolves to boot_a (from slot flags in {gpt_main})")
+        );
+
+        let runner = ScriptedRunner::new(vec![unknown()]);
+        let err = resolve_slots(&sink, &runner, SlotSelector::Active, None, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "OUTPUT_MISMATCH");
+    }
 }
@@ -0,0 +1,1477 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "gui")]
+use tauri::AppHandle;
+
+#[cfg(feature = "gui")]
+use crate::config::app_data_dir;
+use crate::config::fs_error;
+#[cfg(feature = "gui")]
+use crate::models::GeneratedGpt;
+use crate::models::{
+    GeekFlashError, GptChange, GptDiff, GptHeader, GptPartition, GptRole, GptTable, LunGeometry,
+    Slot, SlotAttributes, SlotEdit,
+};
+use crate::plan::{check_file, first_problem};
+
+pub const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Logical sector sizes a GPT image may use (eMMC/SD, UFS).
+pub const SECTOR_SIZES: [usize; 2] = [512, 4096];
+
+/// Smallest header the spec allows (fields up to the entry array CRC).
+const MIN_HEADER_SIZE: u32 = 92;
+
+/// Anything bigger than this is not a GPT dump (a full LUN image, say).
+const MAX_IMAGE_BYTES: u64 = 1 << 20;
+
+/// Generated GPT images, under `$APP_DATA_DIR`.
+const GENERATED_DIR_NAME: &str = "gpt";
+
+/// Partition whose A/B flags the bootloader reads to pick a slot.
+pub const SLOT_PARTITION: &str = "boot";
+
+const SLOT_PRIORITY_SHIFT: u32 = 48;
+const SLOT_ACTIVE: u64 = 1 << 50;
+const SLOT_RETRY_SHIFT: u32 = 51;
+const SLOT_SUCCESSFUL: u64 = 1 << 54;
+const SLOT_UNBOOTABLE: u64 = 1 << 55;
+pub const MAX_SLOT_PRIORITY: u8 = 3;
+pub const MAX_RETRY_COUNT: u8 = 7;
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Format a 16-byte on-disk GUID (first three fields little-endian) the way
+/// `sgdisk` and `edl printgpt` show it.
+pub fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        le_u32(bytes, 0),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        bytes[10..16]
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<String>()
+    )
+}
+
+/// A UTF-16LE partition name, up to its first NUL.
+fn decode_name(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&u| u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Find the header for `role`: its sector size and sector index in `data`.
+///
+/// A primary image starts with the protective MBR, so the header is sector 1;
+/// a backup image ends with its header.
+fn locate_header(data: &[u8], role: GptRole) -> Option<(usize, usize)> {
+    SECTOR_SIZES.iter().find_map(|&ss| {
+        let sector = match role {
+            GptRole::Primary if data.len() >= 2 * ss => 1,
+            GptRole::Backup if data.len() >= ss && data.len().is_multiple_of(ss) => {
+                data.len() / ss - 1
+            }
+            _ => return None,
+        };
+        data[sector * ss..]
+            .starts_with(GPT_SIGNATURE)
+            .then_some((ss, sector))
+    })
+}
+
+fn parse_header(h: &[u8]) -> GptHeader {
+    GptHeader {
+        revision: le_u32(h, 8),
+        header_size: le_u32(h, 12),
+        header_crc32: le_u32(h, 16),
+        my_lba: le_u64(h, 24),
+        alternate_lba: le_u64(h, 32),
+        first_usable_lba: le_u64(h, 40),
+        last_usable_lba: le_u64(h, 48),
+        disk_guid: format_guid(&h[56..72]),
+        partition_entry_lba: le_u64(h, 72),
+        num_partition_entries: le_u32(h, 80),
+        partition_entry_size: le_u32(h, 84),
+        partition_entry_array_crc32: le_u32(h, 88),
+    }
+}
+
+/// CRC32 of a header's first `header_size` bytes with its CRC field zeroed.
+pub fn header_crc32(h: &[u8], header_size: usize) -> u32 {
+    let mut copy = h[..header_size].to_vec();
+    copy[16..20].fill(0);
+    crc32fast::hash(&copy)
+}
+
+/// Sectors the partition entry array of `header` occupies.
+pub fn entry_array_sectors(header: &GptHeader, sector_size: usize) -> u64 {
+    let bytes = header.num_partition_entries as u64 * header.partition_entry_size as u64;
+    bytes.div_ceil(sector_size as u64)
+}
+
+pub fn decode_slot_attributes(attributes: u64) -> SlotAttributes {
+    SlotAttributes {
+        priority: ((attributes >> SLOT_PRIORITY_SHIFT) & 0x3) as u8,
+        active: attributes & SLOT_ACTIVE != 0,
+        retry_count: ((attributes >> SLOT_RETRY_SHIFT) & 0x7) as u8,
+        successful: attributes & SLOT_SUCCESSFUL != 0,
+        unbootable: attributes & SLOT_UNBOOTABLE != 0,
+    }
+}
+
+/// `attributes` with its A/B bits replaced by `slot`; other bits are kept.
+pub fn encode_slot_attributes(attributes: u64, slot: &SlotAttributes) -> u64 {
+    let flag = |set: bool, bit: u64| if set { bit } else { 0 };
+    (attributes & !(0xFF << SLOT_PRIORITY_SHIFT))
+        | (slot.priority.min(MAX_SLOT_PRIORITY) as u64) << SLOT_PRIORITY_SHIFT
+        | flag(slot.active, SLOT_ACTIVE)
+        | (slot.retry_count.min(MAX_RETRY_COUNT) as u64) << SLOT_RETRY_SHIFT
+        | flag(slot.successful, SLOT_SUCCESSFUL)
+        | flag(slot.unbootable, SLOT_UNBOOTABLE)
+}
+
+fn is_slot_partition(name: &str) -> bool {
+    name.ends_with(Slot::A.suffix()) || name.ends_with(Slot::B.suffix())
+}
+
+/// Where the header and the partition entry array sit in an image file.
+struct ImageLayout {
+    sector_size: usize,
+    header_offset: usize,
+    entries_offset: usize,
+    entries_len: usize,
+}
+
+fn image_layout(data: &[u8], role: GptRole) -> Result<(ImageLayout, GptHeader), String> {
+    let (ss, sector) = locate_header(data, role).ok_or_else(|| {
+        let place = match role {
+            GptRole::Primary => "sector 1",
+            GptRole::Backup => "the last sector",
+        };
+        format!("no \"EFI PART\" signature in {place} for 512- or 4096-byte sectors")
+    })?;
+    let header = parse_header(&data[sector * ss..(sector + 1) * ss]);
+
+    if header.header_size < MIN_HEADER_SIZE || header.header_size as usize > ss {
+        return Err(format!("invalid header size {}", header.header_size));
+    }
+    if header.partition_entry_size < 128 || !header.partition_entry_size.is_multiple_of(8) {
+        return Err(format!(
+            "invalid partition entry size {}",
+            header.partition_entry_size
+        ));
+    }
+
+    // LBA of the file's first sector, to find the entries relative to the header
+    let base_lba = header
+        .my_lba
+        .checked_sub(sector as u64)
+        .ok_or_else(|| format!("header LBA {} does not fit the file layout", header.my_lba))?;
+    let entries_len = header.num_partition_entries as usize * header.partition_entry_size as usize;
+    let entries_offset = header
+        .partition_entry_lba
+        .checked_sub(base_lba)
+        .and_then(|rel| usize::try_from(rel).ok()?.checked_mul(ss))
+        .filter(|&start| {
+            start
+                .checked_add(entries_len)
+                .is_some_and(|end| end <= data.len())
+        })
+        .ok_or_else(|| {
+            format!(
+                "partition entries at LBA {} lie outside the file",
+                header.partition_entry_lba
+            )
+        })?;
+
+    let layout = ImageLayout {
+        sector_size: ss,
+        header_offset: sector * ss,
+        entries_offset,
+        entries_len,
+    };
+    Ok((layout, header))
+}
+
+/// Parse a primary or backup GPT image (as dumped by `edl rs`).
+///
+/// Fails only if no header or entry array can be found; checksum and LBA
+/// consistency failures are listed in `GptTable::problems` instead, so a
+/// damaged table can still be inspected.
+pub fn parse_gpt(data: &[u8], role: GptRole) -> Result<GptTable, String> {
+    let (layout, header) = image_layout(data, role)?;
+    let ss = layout.sector_size;
+    let h = &data[layout.header_offset..layout.header_offset + ss];
+    let entries = &data[layout.entries_offset..layout.entries_offset + layout.entries_len];
+
+    let mut problems = Vec::new();
+    let crc = header_crc32(h, header.header_size as usize);
+    if crc != header.header_crc32 {
+        problems.push(format!(
+            "header CRC32 is {:08x} but should be {crc:08x}",
+            header.header_crc32
+        ));
+    }
+    let crc = crc32fast::hash(entries);
+    if crc != header.partition_entry_array_crc32 {
+        problems.push(format!(
+            "partition entry array CRC32 is {:08x} but should be {crc:08x}",
+            header.partition_entry_array_crc32
+        ));
+    }
+
+    let entry_end = header
+        .partition_entry_lba
+        .saturating_add(entry_array_sectors(&header, ss));
+    match role {
+        GptRole::Primary => {
+            if header.my_lba != 1 {
+                problems.push(format!("primary header claims LBA {}", header.my_lba));
+            }
+            if header.alternate_lba <= header.my_lba {
+                problems.push(format!(
+                    "backup header LBA {} is not after the primary header",
+                    header.alternate_lba
+                ));
+            }
+            if header.partition_entry_lba < 2 {
+                problems.push("partition entries overlap the primary header".to_string());
+            }
+        }
+        GptRole::Backup => {
+            if header.alternate_lba != 1 {
+                problems.push(format!(
+                    "backup header points to LBA {} for the primary header",
+                    header.alternate_lba
+                ));
+            }
+            if header.my_lba <= header.alternate_lba {
+                problems.push(format!("backup header claims LBA {}", header.my_lba));
+            }
+            if entry_end != header.my_lba {
+                problems.push(format!(
+                    "partition entries end at LBA {entry_end}, not just before the backup header"
+                ));
+            }
+        }
+    }
+    if header.first_usable_lba > header.last_usable_lba {
+        problems.push(format!(
+            "usable range {}..={} is empty",
+            header.first_usable_lba, header.last_usable_lba
+        ));
+    }
+
+    let partitions: Vec<GptPartition> = entries
+        .chunks_exact(header.partition_entry_size as usize)
+        .enumerate()
+        .filter(|(_, e)| e[..16].iter().any(|&b| b != 0))
+        .map(|(i, e)| {
+            let first_lba = le_u64(e, 32);
+            let last_lba = le_u64(e, 40);
+            let name = decode_name(&e[56..128]);
+            let attributes = le_u64(e, 48);
+            GptPartition {
+                index: i as u32,
+                type_guid: format_guid(&e[..16]),
+                unique_guid: format_guid(&e[16..32]),
+                slot: is_slot_partition(&name).then(|| decode_slot_attributes(attributes)),
+                name,
+                first_lba,
+                last_lba,
+                size_bytes: last_lba
+                    .saturating_add(1)
+                    .saturating_sub(first_lba)
+                    .saturating_mul(ss as u64),
+                attributes,
+            }
+        })
+        .collect();
+
+    let mut by_start: Vec<&GptPartition> = partitions.iter().collect();
+    by_start.sort_by_key(|p| p.first_lba);
+    for p in &by_start {
+        if p.first_lba > p.last_lba
+            || p.first_lba < header.first_usable_lba
+            || p.last_lba > header.last_usable_lba
+        {
+            problems.push(format!(
+                "partition {} ({}) spans LBA {}..={}, outside the usable range",
+                p.index, p.name, p.first_lba, p.last_lba
+            ));
+        }
+    }
+    for pair in by_start.windows(2) {
+        if pair[1].first_lba <= pair[0].last_lba {
+            problems.push(format!(
+                "partitions {} and {} overlap",
+                pair[0].name, pair[1].name
+            ));
+        }
+    }
+
+    Ok(GptTable {
+        role,
+        sector_size: ss as u32,
+        header,
+        partitions,
+        problems,
+    })
+}
+
+/// Recompute the entry array CRC stored in the header, then the header CRC.
+fn refresh_crcs(image: &mut [u8], layout: &ImageLayout, header_size: usize) {
+    let entries_crc = crc32fast::hash(&image[layout.entries_offset..][..layout.entries_len]);
+    let h = &mut image[layout.header_offset..][..layout.sector_size];
+    h[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = header_crc32(h, header_size);
+    h[16..20].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// The slot the bootloader would pick from the `boot_a`/`boot_b` flags: the
+/// one marked active, else the bootable one with the higher priority.
+/// `None` if the table does not single one out.
+pub fn active_slot(table: &GptTable) -> Option<Slot> {
+    let flags = |slot: Slot| {
+        let name = format!("{SLOT_PARTITION}{}", slot.suffix());
+        table.partitions.iter().find(|p| p.name == name)?.slot
+    };
+    let (a, b) = (flags(Slot::A)?, flags(Slot::B)?);
+    match (a.active, b.active) {
+        (true, false) => return Some(Slot::A),
+        (false, true) => return Some(Slot::B),
+        _ => {}
+    }
+    let rank = |f: SlotAttributes| if f.unbootable { 0 } else { f.priority + 1 };
+    match rank(a).cmp(&rank(b)) {
+        std::cmp::Ordering::Greater => Some(Slot::A),
+        std::cmp::Ordering::Less => Some(Slot::B),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+/// Apply `edit` to the `boot_a`/`boot_b` entries of a valid primary image
+/// and return the image with both CRCs updated.
+///
+/// `SetActive` gives the target slot the active flag, top priority and full
+/// retries and clears its unbootable flag; the other slot loses its active
+/// flag and drops below the target's priority.
+pub fn apply_slot_edit(primary: &[u8], edit: SlotEdit) -> Result<Vec<u8>, String> {
+    let table = parse_gpt(primary, GptRole::Primary)?;
+    if !table.problems.is_empty() {
+        return Err(table.problems.join("; "));
+    }
+    let (layout, header) = image_layout(primary, GptRole::Primary)?;
+
+    let mut image = primary.to_vec();
+    for slot in [Slot::A, Slot::B] {
+        let name = format!("{SLOT_PARTITION}{}", slot.suffix());
+        let partition = table
+            .partitions
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| format!("no {name} partition in this GPT"))?;
+
+        let mut flags = decode_slot_attributes(partition.attributes);
+        match edit {
+            SlotEdit::SetActive { slot: target } if target == slot => {
+                flags.active = true;
+                flags.priority = MAX_SLOT_PRIORITY;
+                flags.retry_count = MAX_RETRY_COUNT;
+                flags.unbootable = false;
+            }
+            SlotEdit::SetActive { .. } => {
+                flags.active = false;
+                flags.priority = flags.priority.min(MAX_SLOT_PRIORITY - 1);
+            }
+            SlotEdit::ResetRetries { slot: target } if target.is_none_or(|t| t == slot) => {
+                flags.retry_count = MAX_RETRY_COUNT;
+                flags.unbootable = false;
+            }
+            SlotEdit::ResetRetries { .. } => continue,
+        }
+
+        let offset = layout.entries_offset
+            + partition.index as usize * header.partition_entry_size as usize
+            + 48;
+        let attributes = encode_slot_attributes(partition.attributes, &flags);
+        image[offset..offset + 8].copy_from_slice(&attributes.to_le_bytes());
+    }
+
+    refresh_crcs(&mut image, &layout, header.header_size as usize);
+    Ok(image)
+}
+
+/// The backup GPT for a primary image: the same entry array, followed by a
+/// header at the primary's `alternate_lba` pointing back to LBA 1.
+pub fn backup_from_primary(primary: &[u8]) -> Result<Vec<u8>, String> {
+    let (layout, header) = image_layout(primary, GptRole::Primary)?;
+    let ss = layout.sector_size;
+    let entry_sectors = entry_array_sectors(&header, ss);
+    let my_lba = header.alternate_lba;
+    let entry_lba = my_lba
+        .checked_sub(entry_sectors)
+        .filter(|&lba| lba > header.last_usable_lba)
+        .ok_or_else(|| {
+            format!("backup header LBA {my_lba} leaves no room for the partition entries")
+        })?;
+
+    let header_offset = entry_sectors as usize * ss;
+    let mut backup = vec![0u8; header_offset + ss];
+    backup[..layout.entries_len]
+        .copy_from_slice(&primary[layout.entries_offset..][..layout.entries_len]);
+    backup[header_offset..].copy_from_slice(&primary[layout.header_offset..][..ss]);
+    let h = &mut backup[header_offset..];
+    h[24..32].copy_from_slice(&my_lba.to_le_bytes());
+    h[32..40].copy_from_slice(&1u64.to_le_bytes());
+    h[72..80].copy_from_slice(&entry_lba.to_le_bytes());
+
+    let backup_layout = ImageLayout {
+        sector_size: ss,
+        header_offset,
+        entries_offset: 0,
+        entries_len: layout.entries_len,
+    };
+    refresh_crcs(&mut backup, &backup_layout, header.header_size as usize);
+    Ok(backup)
+}
+
+/// Move the backup header of a valid primary image to the last sector of a
+/// `disk_sectors`-sector disk and end the usable range before its entry
+/// array, so [`backup_from_primary`] lays the backup out for that disk.
+pub fn resize_primary(primary: &[u8], disk_sectors: u64) -> Result<Vec<u8>, String> {
+    let table = parse_gpt(primary, GptRole::Primary)?;
+    if !table.problems.is_empty() {
+        return Err(table.problems.join("; "));
+    }
+    let (layout, header) = image_layout(primary, GptRole::Primary)?;
+    let entry_sectors = entry_array_sectors(&header, layout.sector_size);
+    let alternate_lba = disk_sectors.saturating_sub(1);
+    let last_usable_lba = alternate_lba
+        .checked_sub(entry_sectors + 1)
+        .filter(|&lba| lba >= header.first_usable_lba)
+        .ok_or_else(|| format!("a disk of {disk_sectors} sectors is too small for this GPT"))?;
+    if let Some(p) = table
+        .partitions
+        .iter()
+        .find(|p| p.last_lba > last_usable_lba)
+    {
+        return Err(format!(
+            "partition {} ends at LBA {}, past the last usable LBA {last_usable_lba} of a \
+             {disk_sectors}-sector disk",
+            p.name, p.last_lba
+        ));
+    }
+
+    let mut image = primary.to_vec();
+    let h = &mut image[layout.header_offset..][..layout.sector_size];
+    h[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+    h[48..56].copy_from_slice(&last_usable_lba.to_le_bytes());
+    refresh_crcs(&mut image, &layout, header.header_size as usize);
+    Ok(image)
+}
+
+#[cfg(feature = "gui")]
+pub fn generated_gpt_dir(app: &AppHandle) -> Result<PathBuf, GeekFlashError> {
+    Ok(generated_gpt_dir_in(&app_data_dir(app)?))
+}
+
+/// The generated GPT directory inside an app data directory.
+pub fn generated_gpt_dir_in(data_dir: &Path) -> PathBuf {
+    data_dir.join(GENERATED_DIR_NAME)
+}
+
+/// Write a generated primary/backup pair to a new timestamped directory
+/// under `dir`; returns the two file paths. A dry run just returns them.
+pub fn save_generated_pair(
+    dir: &Path,
+    primary: &[u8],
+    backup: &[u8],
+    dry_run: bool,
+) -> Result<(String, String), GeekFlashError> {
+    let dir = dir.join(chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f").to_string());
+    if !dry_run {
+        fs::create_dir_all(&dir).map_err(|e| fs_error("create GPT output directory", e))?;
+    }
+    let mut paths = Vec::new();
+    for (name, data) in [("gpt_main.bin", primary), ("gpt_backup.bin", backup)] {
+        let path = dir.join(name);
+        if !dry_run {
+            fs::write(&path, data).map_err(|e| fs_error("write generated GPT", e))?;
+        }
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    let backup_path = paths.pop().unwrap_or_default();
+    let primary_path = paths.pop().unwrap_or_default();
+    Ok((primary_path, backup_path))
+}
+
+/// Read the primary GPT at `gpt_main`, change it with `edit`, then build the
+/// matching backup; returns both images.
+fn generate_pair(
+    gpt_main: &str,
+    edit: impl FnOnce(&[u8]) -> Result<Vec<u8>, String>,
+) -> Result<(Vec<u8>, Vec<u8>), GeekFlashError> {
+    load_gpt("gpt_main", gpt_main, GptRole::Primary)?;
+    let data = fs::read(gpt_main).map_err(|e| fs_error("read GPT image", e))?;
+    let invalid =
+        |reason| GeekFlashError::invalid_input("gpt_main", format!("{gpt_main}: {reason}"));
+    let primary = edit(&data).map_err(invalid)?;
+    let backup = backup_from_primary(&primary).map_err(invalid)?;
+    Ok((primary, backup))
+}
+
+/// Apply `edit` to the primary GPT at `gpt_main` and regenerate the backup,
+/// without saving them (see [`write_slot_edit`]).
+pub fn slot_edit_pair(
+    gpt_main: &str,
+    edit: SlotEdit,
+) -> Result<(Vec<u8>, Vec<u8>), GeekFlashError> {
+    generate_pair(gpt_main, |data| apply_slot_edit(data, edit))
+}
+
+/// Apply `edit` to the primary GPT at `gpt_main`, regenerate the backup and
+/// save both under `dir`.
+pub fn write_slot_edit(
+    dir: &Path,
+    gpt_main: &str,
+    edit: SlotEdit,
+) -> Result<(String, String), GeekFlashError> {
+    let (primary, backup) = slot_edit_pair(gpt_main, edit)?;
+    save_generated_pair(dir, &primary, &backup, false)
+}
+
+/// Build the backup GPT for the primary at `gpt_main`, without saving the
+/// pair (see [`write_generated_backup`]).
+pub fn generated_backup_pair(
+    gpt_main: &str,
+    disk_sectors: Option<u64>,
+) -> Result<(Vec<u8>, Vec<u8>), GeekFlashError> {
+    generate_pair(gpt_main, |data| match disk_sectors {
+        Some(n) => resize_primary(data, n),
+        None => {
+            let table = parse_gpt(data, GptRole::Primary)?;
+            if table.problems.is_empty() {
+                Ok(data.to_vec())
+            } else {
+                Err(table.problems.join("; "))
+            }
+        }
+    })
+}
+
+/// Build the backup GPT for the primary at `gpt_main` and save the pair
+/// under `dir`. With `disk_sectors`, both are laid out for a disk of that
+/// size (see [`resize_primary`]); otherwise for the size the primary's
+/// header already describes.
+pub fn write_generated_backup(
+    dir: &Path,
+    gpt_main: &str,
+    disk_sectors: Option<u64>,
+) -> Result<(String, String), GeekFlashError> {
+    let (primary, backup) = generated_backup_pair(gpt_main, disk_sectors)?;
+    save_generated_pair(dir, &primary, &backup, false)
+}
+
+/// Read and parse a GPT image file; `label` names the input in errors.
+pub fn load_gpt(label: &str, path: &str, role: GptRole) -> Result<GptTable, GeekFlashError> {
+    let check = check_file(label, path);
+    if let Some(err) = first_problem(std::slice::from_ref(&check)) {
+        return Err(err);
+    }
+    if check.size_bytes.unwrap_or_default() > MAX_IMAGE_BYTES {
+        return Err(GeekFlashError::invalid_input(
+            label,
+            format!("{path}: too large for a GPT image"),
+        ));
+    }
+    let data = fs::read(path).map_err(|e| fs_error("read GPT image", e))?;
+    parse_gpt_image(label, path, &data, role)
+}
+
+/// Parse a GPT image read from (or to be saved as) `path`.
+pub fn parse_gpt_image(
+    label: &str,
+    path: &str,
+    data: &[u8],
+    role: GptRole,
+) -> Result<GptTable, GeekFlashError> {
+    parse_gpt(data, role).map_err(|reason| {
+        GeekFlashError::invalid_input(label, format!("{path}: not a {role:?} GPT: {reason}"))
+    })
+}
+
+/// Ways a primary and backup table do not describe the same disk.
+pub fn pair_problems(main: &GptTable, backup: &GptTable) -> Vec<String> {
+    let (m, b) = (&main.header, &backup.header);
+    let mut problems = Vec::new();
+    if main.sector_size != backup.sector_size {
+        problems.push(format!(
+            "sector sizes differ ({} vs {})",
+            main.sector_size, backup.sector_size
+        ));
+    }
+    if m.alternate_lba != b.my_lba || b.alternate_lba != m.my_lba {
+        problems.push(format!(
+            "headers do not point at each other (primary → LBA {}, backup at LBA {})",
+            m.alternate_lba, b.my_lba
+        ));
+    }
+    if m.disk_guid != b.disk_guid {
+        problems.push(format!(
+            "disk GUIDs differ ({} vs {})",
+            m.disk_guid, b.disk_guid
+        ));
+    }
+    if (m.first_usable_lba, m.last_usable_lba) != (b.first_usable_lba, b.last_usable_lba) {
+        problems.push("usable LBA ranges differ".to_string());
+    }
+    if m.partition_entry_array_crc32 != b.partition_entry_array_crc32
+        || main.partitions != backup.partitions
+    {
+        problems.push("partition entries differ".to_string());
+    }
+    problems
+}
+
+/// Load both GPT images for a repair and refuse any that fail validation.
+pub fn validate_gpt_pair(
+    main_path: &str,
+    backup_path: &str,
+) -> Result<(GptTable, GptTable), GeekFlashError> {
+    let main = load_gpt("gpt_main", main_path, GptRole::Primary)?;
+    let backup = load_gpt("gpt_backup", backup_path, GptRole::Backup)?;
+    check_gpt_pair(main_path, &main, backup_path, &backup)?;
+    Ok((main, backup))
+}
+
+/// Refuse a pair where either table has problems or they do not match.
+pub fn check_gpt_pair(
+    main_path: &str,
+    main: &GptTable,
+    backup_path: &str,
+    backup: &GptTable,
+) -> Result<(), GeekFlashError> {
+    for (label, path, table) in [
+        ("gpt_main", main_path, main),
+        ("gpt_backup", backup_path, backup),
+    ] {
+        if !table.problems.is_empty() {
+            return Err(GeekFlashError::invalid_input(
+                label,
+                format!("{path}: {}", table.problems.join("; ")),
+            ));
+        }
+    }
+    let problems = pair_problems(main, backup);
+    if !problems.is_empty() {
+        return Err(GeekFlashError::invalid_input(
+            "gpt_backup",
+            format!("does not match gpt_main: {}", problems.join("; ")),
+        ));
+    }
+    Ok(())
+}
+
+/// Differences between the partition table `old` (usually the one on the
+/// device) and `new` (the one about to replace it).
+pub fn diff_tables(old: &GptTable, new: &GptTable) -> Vec<GptChange> {
+    let mut changes = Vec::new();
+    if old.sector_size != new.sector_size {
+        changes.push(GptChange::SectorSize {
+            old: old.sector_size,
+            new: new.sector_size,
+        });
+    }
+    if old.header.disk_guid != new.header.disk_guid {
+        changes.push(GptChange::DiskGuid {
+            old: old.header.disk_guid.clone(),
+            new: new.header.disk_guid.clone(),
+        });
+    }
+    for p in &old.partitions {
+        if !new.partitions.iter().any(|n| n.name == p.name) {
+            changes.push(GptChange::Removed {
+                partition: p.clone(),
+            });
+        }
+    }
+    for n in &new.partitions {
+        let Some(p) = old.partitions.iter().find(|p| p.name == n.name) else {
+            changes.push(GptChange::Added {
+                partition: n.clone(),
+            });
+            continue;
+        };
+        let name = n.name.clone();
+        if (p.first_lba, p.last_lba) != (n.first_lba, n.last_lba) {
+            changes.push(GptChange::Resized {
+                name: name.clone(),
+                old_first_lba: p.first_lba,
+                old_last_lba: p.last_lba,
+                new_first_lba: n.first_lba,
+                new_last_lba: n.last_lba,
+            });
+        }
+        if p.type_guid != n.type_guid {
+            changes.push(GptChange::TypeGuid {
+                name: name.clone(),
+                old: p.type_guid.clone(),
+                new: n.type_guid.clone(),
+            });
+        }
+        if p.unique_guid != n.unique_guid {
+            changes.push(GptChange::UniqueGuid {
+                name: name.clone(),
+                old: p.unique_guid.clone(),
+                new: n.unique_guid.clone(),
+            });
+        }
+        if p.attributes != n.attributes {
+            changes.push(GptChange::Attributes {
+                name,
+                old: p.attributes,
+                new: n.attributes,
+                old_slot: p.slot,
+                new_slot: n.slot,
+            });
+        }
+    }
+    changes
+}
+
+/// The slot flags that differ, e.g. `priority 3 → 2, active yes → no`.
+fn describe_slot_change(old: &SlotAttributes, new: &SlotAttributes) -> String {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    let mut parts = Vec::new();
+    if old.priority != new.priority {
+        parts.push(format!("priority {} → {}", old.priority, new.priority));
+    }
+    for (flag, o, n) in [
+        ("active", old.active, new.active),
+        ("successful", old.successful, new.successful),
+        ("unbootable", old.unbootable, new.unbootable),
+    ] {
+        if o != n {
+            parts.push(format!("{flag} {} → {}", yes_no(o), yes_no(n)));
+        }
+    }
+    if old.retry_count != new.retry_count {
+        parts.push(format!("retries {} → {}", old.retry_count, new.retry_count));
+    }
+    parts.join(", ")
+}
+
+/// One line per change: `+` added, `-` removed, `~` changed.
+pub fn describe_change(change: &GptChange) -> String {
+    match change {
+        GptChange::SectorSize { old, new } => format!("~ sector size {old} → {new}"),
+        GptChange::DiskGuid { old, new } => format!("~ disk GUID {old} → {new}"),
+        GptChange::Added { partition: p } => {
+            format!("+ {} (LBA {}..={})", p.name, p.first_lba, p.last_lba)
+        }
+        GptChange::Removed { partition: p } => {
+            format!("- {} (LBA {}..={})", p.name, p.first_lba, p.last_lba)
+        }
+        GptChange::Resized {
+            name,
+            old_first_lba,
+            old_last_lba,
+            new_first_lba,
+            new_last_lba,
+        } => format!(
+            "~ {name}: LBA {old_first_lba}..={old_last_lba} → {new_first_lba}..={new_last_lba}"
+        ),
+        GptChange::TypeGuid { name, old, new } => format!("~ {name}: type GUID {old} → {new}"),
+        GptChange::UniqueGuid { name, old, new } => format!("~ {name}: GUID {old} → {new}"),
+        GptChange::Attributes {
+            name,
+            old,
+            new,
+            old_slot,
+            new_slot,
+        } => {
+            let line = format!("~ {name}: attributes {old:#018x} → {new:#018x}");
+            match (old_slot, new_slot) {
+                (Some(o), Some(n)) if o != n => format!("{line} ({})", describe_slot_change(o, n)),
+                _ => line,
+            }
+        }
+    }
+}
+
+/// [`diff_tables`] with its text rendering; `old_source` and `new_source`
+/// say where each table came from.
+pub fn gpt_diff(old_source: &str, old: &GptTable, new_source: &str, new: &GptTable) -> GptDiff {
+    let changes = diff_tables(old, new);
+    GptDiff {
+        old: old_source.to_string(),
+        new: new_source.to_string(),
+        lines: changes.iter().map(describe_change).collect(),
+        changes,
+    }
+}
+
+/// Check a GPT dump read from the device before a repair and describe how
+/// the image at `incoming` differs from it.
+///
+/// A missing or empty dump is an error, and so is a valid device table laid
+/// out for another disk than `incoming` (sector size, or where the headers
+/// sit): writing it would put the backup table at the wrong LBA. A damaged
+/// table on the device is the usual reason for a repair, so that is only
+/// logged; its raw sectors are kept anyway.
+pub fn compare_gpt_dump(
+    dump: &str,
+    role: GptRole,
+    incoming: &str,
+) -> Result<Vec<String>, GeekFlashError> {
+    let data = fs::read(dump).map_err(|e| fs_error("read GPT backup", e))?;
+    if data.is_empty() {
+        return Err(GeekFlashError::io(
+            "read GPT backup",
+            format!("{dump} is empty"),
+        ));
+    }
+
+    let mut lines = vec![format!(
+        "Saved the device's {role:?} GPT to {dump} ({} bytes)",
+        data.len()
+    )];
+    let current = match parse_gpt(&data, role) {
+        Ok(table) => table,
+        Err(reason) => {
+            lines.push(format!("The device's {role:?} GPT is not valid: {reason}"));
+            return Ok(lines);
+        }
+    };
+    if !current.problems.is_empty() {
+        lines.push(format!(
+            "The device's {role:?} GPT has problems: {}",
+            current.problems.join("; ")
+        ));
+    }
+
+    let label = match role {
+        GptRole::Primary => "gpt_main",
+        GptRole::Backup => "gpt_backup",
+    };
+    let new = load_gpt(label, incoming, role)?;
+    if current.sector_size != new.sector_size {
+        return Err(GeekFlashError::invalid_input(
+            label,
+            format!(
+                "{incoming} uses {}-byte sectors but the device's {role:?} GPT uses {}",
+                new.sector_size, current.sector_size
+            ),
+        ));
+    }
+    let lbas = |t: &GptTable| (t.header.my_lba, t.header.alternate_lba);
+    if current.problems.is_empty() && lbas(&current) != lbas(&new) {
+        return Err(GeekFlashError::invalid_input(
+            label,
+            format!(
+                "{incoming} is built for another disk: its {role:?} header is at LBA {} with \
+                 the other at LBA {}, the device's at LBA {} with the other at LBA {}",
+                new.header.my_lba,
+                new.header.alternate_lba,
+                current.header.my_lba,
+                current.header.alternate_lba
+            ),
+        ));
+    }
+    let diff = gpt_diff("device", &current, incoming, &new);
+    if diff.changes.is_empty() {
+        lines.push(format!("{incoming} has the same partitions as the device"));
+    } else {
+        lines.push(format!("Changes {incoming} makes to the device's table:"));
+        lines.extend(diff.lines.into_iter().map(|l| format!("  {l}")));
+    }
+    Ok(lines)
+}
+
+/// The geometry a GPT pair was built for: the backup header sits in the
+/// LUN's last sector.
+pub fn table_geometry(main: &GptTable) -> LunGeometry {
+    LunGeometry {
+        sector_size: main.sector_size,
+        total_sectors: main.header.alternate_lba + 1,
+    }
+}
+
+/// The number following `key` (quoted either way, then `:`) in `line`.
+fn json_number(line: &str, key: &str) -> Option<u64> {
+    let rest = &line[line.find(key)? + key.len()..];
+    let rest = rest.trim_start_matches(['"', '\'', ':', ' ']);
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// LUN geometry from `edl getstorageinfo` output, which logs the firehose
+/// `storage_info` JSON (`"total_blocks":..., "block_size":...`).
+pub fn parse_storage_info(lines: &[String]) -> Option<LunGeometry> {
+    lines.iter().find_map(|line| {
+        Some(LunGeometry {
+            sector_size: json_number(line, "block_size")?.try_into().ok()?,
+            total_sectors: json_number(line, "total_blocks")?,
+        })
+    })
+}
+
+// === Tauri Commands ===
+
+/// Parse a GPT image and list its partitions and problems.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn inspect_gpt(path: String, role: GptRole) -> Result<GptTable, GeekFlashError> {
+    load_gpt("path", &path, role)
+}
+
+/// Compare two GPT image files of the same role, `old` → `new`.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn diff_gpt(old: String, new: String, role: GptRole) -> Result<GptDiff, GeekFlashError> {
+    let old_table = load_gpt("old", &old, role)?;
+    let new_table = load_gpt("new", &new, role)?;
+    Ok(gpt_diff(&old, &old_table, &new, &new_table))
+}
+
+/// Build a backup GPT for a primary-only dump, saved with a copy of the
+/// primary under `$APP_DATA_DIR/gpt/`.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn generate_backup_gpt(
+    app: AppHandle,
+    gpt_main: String,
+    disk_sectors: Option<u64>,
+) -> Result<GeneratedGpt, GeekFlashError> {
+    let (gpt_main, gpt_backup) =
+        write_generated_backup(&generated_gpt_dir(&app)?, &gpt_main, disk_sectors)?;
+    Ok(GeneratedGpt {
+        gpt_main,
+        gpt_backup,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A partition for [`build_image`]: name, first LBA, last LBA, attributes.
+    pub(crate) type TestPartition<'a> = (&'a str, u64, u64, u64);
+
+    const BASIC_DATA: [u8; 16] = [
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99,
+        0xC7,
+    ];
+
+    /// A valid GPT image as `edl rs` would dump it: a 128-entry array on a
+    /// disk of `disk_sectors` sectors.
+    pub(crate) fn build_image(
+        role: GptRole,
+        ss: usize,
+        disk_sectors: u64,
+        parts: &[TestPartition],
+    ) -> Vec<u8> {
+        let entry_sectors = (128 * 128usize).div_ceil(ss) as u64;
+        let mut entries = vec![0u8; entry_sectors as usize * ss];
+        for (i, (name, first, last, attrs)) in parts.iter().enumerate() {
+            let e = &mut entries[i * 128..(i + 1) * 128];
+            e[..16].copy_from_slice(&BASIC_DATA);
+            e[16] = i as u8 + 1;
+            e[32..40].copy_from_slice(&first.to_le_bytes());
+            e[40..48].copy_from_slice(&last.to_le_bytes());
+            e[48..56].copy_from_slice(&attrs.to_le_bytes());
+            for (j, u) in name.encode_utf16().enumerate() {
+                e[56 + 2 * j..58 + 2 * j].copy_from_slice(&u.to_le_bytes());
+            }
+        }
+
+        let last_lba = disk_sectors - 1;
+        let (my_lba, alternate_lba, entry_lba) = match role {
+            GptRole::Primary => (1, last_lba, 2),
+            GptRole::Backup => (last_lba, 1, last_lba - entry_sectors),
+        };
+        let mut h = vec![0u8; ss];
+        h[..8].copy_from_slice(GPT_SIGNATURE);
+        h[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        h[12..16].copy_from_slice(&92u32.to_le_bytes());
+        h[24..32].copy_from_slice(&my_lba.to_le_bytes());
+        h[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        h[40..48].copy_from_slice(&(2 + entry_sectors).to_le_bytes());
+        h[48..56].copy_from_slice(&(last_lba - 1 - entry_sectors).to_le_bytes());
+        h[56..72].copy_from_slice(&[0x11; 16]);
+        h[72..80].copy_from_slice(&entry_lba.to_le_bytes());
+        h[80..84].copy_from_slice(&128u32.to_le_bytes());
+        h[84..88].copy_from_slice(&128u32.to_le_bytes());
+        h[88..92].copy_from_slice(&crc32fast::hash(&entries[..128 * 128]).to_le_bytes());
+        let crc = header_crc32(&h, 92);
+        h[16..20].copy_from_slice(&crc.to_le_bytes());
+
+        match role {
+            GptRole::Primary => {
+                let mut mbr = vec![0u8; ss];
+                mbr[510] = 0x55;
+                mbr[511] = 0xAA;
+                [mbr, h, entries].concat()
+            }
+            GptRole::Backup => [entries, h].concat(),
+        }
+    }
+
+    pub(crate) const Y700_PARTS: [TestPartition; 3] = [
+        ("boot_a", 64, 8255, 0x003F_0000_0000_0000),
+        ("boot_b", 8256, 16447, 0x003A_0000_0000_0000),
+        ("userdata", 16448, 30000, 0),
+    ];
+
+    #[test]
+    fn test_format_guid() {
+        let esp = [
+            0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E,
+            0xC9, 0x3B,
+        ];
+        assert_eq!(format_guid(&esp), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+    }
+
+    #[test]
+    fn test_parse_primary_for_both_sector_sizes() {
+        for ss in SECTOR_SIZES {
+            let image = build_image(GptRole::Primary, ss, 32768, &Y700_PARTS);
+            let table = parse_gpt(&image, GptRole::Primary).unwrap();
+            assert_eq!(table.sector_size, ss as u32);
+            assert!(table.problems.is_empty(), "{:?}", table.problems);
+            assert_eq!(table.header.my_lba, 1);
+            assert_eq!(table.header.alternate_lba, 32767);
+
+            let names: Vec<&str> = table.partitions.iter().map(|p| p.name.as_str()).collect();
+            assert_eq!(names, ["boot_a", "boot_b", "userdata"]);
+            let boot_a = &table.partitions[0];
+            assert_eq!(boot_a.type_guid, "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+            assert_eq!((boot_a.first_lba, boot_a.last_lba), (64, 8255));
+            assert_eq!(boot_a.size_bytes, 8192 * ss as u64);
+            assert_eq!(boot_a.attributes, 0x003F_0000_0000_0000);
+        }
+    }
+
+    #[test]
+    fn test_parse_backup() {
+        let image = build_image(GptRole::Backup, 4096, 32768, &Y700_PARTS);
+        let table = parse_gpt(&image, GptRole::Backup).unwrap();
+        assert!(table.problems.is_empty(), "{:?}", table.problems);
+        assert_eq!(table.header.my_lba, 32767);
+        assert_eq!(table.header.partition_entry_lba, 32763);
+        assert_eq!(table.partitions.len(), 3);
+    }
+
+    #[test]
+    fn test_wrong_file_or_role_is_rejected() {
+        assert!(parse_gpt(&[0u8; 8192], GptRole::Primary).is_err());
+        assert!(parse_gpt(b"EFI PART", GptRole::Primary).is_err());
+
+        let backup = build_image(GptRole::Backup, 512, 32768, &Y700_PARTS);
+        let err = parse_gpt(&backup, GptRole::Primary).unwrap_err();
+        assert!(err.contains("sector 1"), "{err}");
+        let primary = build_image(GptRole::Primary, 512, 32768, &Y700_PARTS);
+        assert!(parse_gpt(&primary, GptRole::Backup).is_err());
+    }
+
+    #[test]
+    fn test_corruption_is_reported_as_problems() {
+        let mut image = build_image(GptRole::Primary, 512, 32768, &Y700_PARTS);
+        image[1024 + 56] ^= 1; // first byte of boot_a's name
+        let table = parse_gpt(&image, GptRole::Primary).unwrap();
+        assert_eq!(table.problems.len(), 1);
+        assert!(table.problems[0].contains("entry array CRC32"));
+
+        let mut image = build_image(GptRole::Primary, 512, 32768, &Y700_PARTS);
+        image[512 + 32] ^= 1; // alternate_lba
+        let table = parse_gpt(&image, GptRole::Primary).unwrap();
+        assert!(table.problems[0].contains("header CRC32"));
+    }
+
+    #[test]
+    fn test_overlapping_partitions_are_reported() {
+        let parts = [("a", 64, 100, 0), ("b", 90, 200, 0)];
+        let image = build_image(GptRole::Primary, 4096, 32768, &parts);
+        let table = parse_gpt(&image, GptRole::Primary).unwrap();
+        assert_eq!(table.problems, ["partitions a and b overlap"]);
+    }
+
+    #[test]
+    fn test_slot_attributes_round_trip() {
+        let flags = decode_slot_attributes(0x003F_0000_0000_0000);
+        assert_eq!(
+            flags,
+            SlotAttributes {
+                priority: 3,
+                active: true,
+                retry_count: 7,
+                successful: false,
+                unbootable: false
+            }
+        );
+        // A bricked slot: unbootable, no retries left, priority 0
+        let bricked = decode_slot_attributes(0x0080_0000_0000_0001);
+        assert!(bricked.unbootable && !bricked.active);
+        assert_eq!((bricked.priority, bricked.retry_count), (0, 0));
+
+        // Bits outside 48-55 survive re-encoding
+        assert_eq!(
+            encode_slot_attributes(0x1080_0000_0000_0001, &flags),
+            0x103F_0000_0000_0001
+        );
+    }
+
+    #[test]
+    fn test_parse_decodes_slot_flags_of_ab_partitions() {
+        let image = build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS);
+        let table = parse_gpt(&image, GptRole::Primary).unwrap();
+        assert!(table.partitions[0].slot.unwrap().active);
+        let boot_b = table.partitions[1].slot.unwrap();
+        assert!(!boot_b.active);
+        assert_eq!((boot_b.priority, boot_b.retry_count), (2, 7));
+        assert_eq!(table.partitions[2].slot, None);
+    }
+
+    fn slot_flags(image: &[u8]) -> Vec<SlotAttributes> {
+        let table = parse_gpt(image, GptRole::Primary).unwrap();
+        assert!(table.problems.is_empty(), "{:?}", table.problems);
+        table.partitions.iter().filter_map(|p| p.slot).collect()
+    }
+
+    #[test]
+    fn test_set_active_slot() {
+        let parts = [
+            ("boot_a", 64, 8255, 0x003F_0000_0000_0000),
+            ("boot_b", 8256, 16447, 0x0080_0000_0000_0000),
+        ];
+        let image = build_image(GptRole::Primary, 4096, 32768, &parts);
+        let edited = apply_slot_edit(&image, SlotEdit::SetActive { slot: Slot::B }).unwrap();
+
+        let flags = slot_flags(&edited);
+        assert!(!flags[0].active);
+        assert_eq!(flags[0].priority, 2);
+        assert!(flags[1].active && !flags[1].unbootable);
+        assert_eq!((flags[1].priority, flags[1].retry_count), (3, 7));
+    }
+
+    #[test]
+    fn test_reset_retries() {
+        let parts = [
+            ("boot_a", 64, 8255, 0x0080_0000_0000_0000),
+            ("boot_b", 8256, 16447, 0x0080_0000_0000_0000),
+        ];
+        let image = build_image(GptRole::Primary, 512, 32768, &parts);
+
+        let one = apply_slot_edit(
+            &image,
+            SlotEdit::ResetRetries {
+                slot: Some(Slot::A),
+            },
+        )
+        .unwrap();
+        let flags = slot_flags(&one);
+        assert_eq!((flags[0].retry_count, flags[0].unbootable), (7, false));
+        assert_eq!((flags[1].retry_count, flags[1].unbootable), (0, true));
+
+        let both = apply_slot_edit(&image, SlotEdit::ResetRetries { slot: None }).unwrap();
+        assert!(slot_flags(&both)
+            .iter()
+            .all(|f| f.retry_count == 7 && !f.unbootable));
+    }
+
+    #[test]
+    fn test_active_slot() {
+        let table = |a: u64, b: u64| {
+            let parts = [("boot_a", 64, 8255, a), ("boot_b", 8256, 16447, b)];
+            let image = build_image(GptRole::Primary, 4096, 32768, &parts);
+            parse_gpt(&image, GptRole::Primary).unwrap()
+        };
+        let y700 = parse_gpt(
+            &build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+            GptRole::Primary,
+        )
+        .unwrap();
+        assert_eq!(active_slot(&y700), Some(Slot::A));
+        // No active flag: the higher priority wins, unless it is unbootable
+        assert_eq!(
+            active_slot(&table(0x0001 << 48, 0x0002 << 48)),
+            Some(Slot::B)
+        );
+        assert_eq!(
+            active_slot(&table(0x0001 << 48, 0x0082 << 48)),
+            Some(Slot::A)
+        );
+        assert_eq!(active_slot(&table(0, 0)), None);
+        let image = build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS[2..]);
+        assert_eq!(
+            active_slot(&parse_gpt(&image, GptRole::Primary).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_slot_edit_needs_boot_partitions() {
+        let image = build_image(GptRole::Primary, 4096, 32768, &[("userdata", 64, 100, 0)]);
+        let err = apply_slot_edit(&image, SlotEdit::SetActive { slot: Slot::A }).unwrap_err();
+        assert!(err.contains("no boot_a"), "{err}");
+    }
+
+    #[test]
+    fn test_backup_from_primary_matches_real_backup() {
+        for ss in SECTOR_SIZES {
+            let primary = build_image(GptRole::Primary, ss, 32768, &Y700_PARTS);
+            let expected = build_image(GptRole::Backup, ss, 32768, &Y700_PARTS);
+            assert_eq!(backup_from_primary(&primary).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_resize_primary_for_another_disk() {
+        for ss in SECTOR_SIZES {
+            let small = build_image(GptRole::Primary, ss, 32768, &Y700_PARTS);
+            let resized = resize_primary(&small, 65536).unwrap();
+            let table = parse_gpt(&resized, GptRole::Primary).unwrap();
+            assert!(table.problems.is_empty(), "{:?}", table.problems);
+            assert_eq!(table.header.alternate_lba, 65535);
+            assert_eq!(
+                backup_from_primary(&resized).unwrap(),
+                build_image(GptRole::Backup, ss, 65536, &Y700_PARTS)
+            );
+        }
+
+        let image = build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS);
+        let err = resize_primary(&image, 30000).unwrap_err();
+        assert!(
+            err.contains("partition userdata ends at LBA 30000"),
+            "{err}"
+        );
+        let err = resize_primary(&image, 8).unwrap_err();
+        assert!(err.contains("too small"), "{err}");
+    }
+
+    #[test]
+    fn test_write_generated_backup() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let main = dir.path().join("main.bin");
+        fs::write(
+            &main,
+            build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        )
+        .unwrap();
+        let main = main.to_str().unwrap();
+        let out = dir.path().join("out");
+
+        let (primary, backup) = write_generated_backup(&out, main, None).unwrap();
+        let (_, backup) = validate_gpt_pair(&primary, &backup).unwrap();
+        assert_eq!(backup.header.my_lba, 32767);
+        assert_eq!(fs::read(&primary).unwrap(), fs::read(main).unwrap());
+
+        let (primary, backup) = write_generated_backup(&out, main, Some(65536)).unwrap();
+        let (_, backup) = validate_gpt_pair(&primary, &backup).unwrap();
+        assert_eq!(backup.header.my_lba, 65535);
+
+        // A damaged primary is not a template for a backup
+        let mut damaged = fs::read(main).unwrap();
+        damaged[4096 + 16] ^= 1;
+        fs::write(main, damaged).unwrap();
+        let err = write_generated_backup(&out, main, None).unwrap_err();
+        assert!(err.to_string().contains("header CRC32"), "{err}");
+    }
+
+    #[test]
+    fn test_write_slot_edit_produces_a_valid_pair() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let main = dir.path().join("main.bin");
+        fs::write(
+            &main,
+            build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        )
+        .unwrap();
+
+        let (primary, backup) = write_slot_edit(
+            &dir.path().join("out"),
+            main.to_str().unwrap(),
+            SlotEdit::SetActive { slot: Slot::B },
+        )
+        .unwrap();
+        let (main, backup) = validate_gpt_pair(&primary, &backup).unwrap();
+        assert!(main.partitions[1].slot.unwrap().active);
+        assert!(backup.partitions[1].slot.unwrap().active);
+    }
+
+    #[test]
+    fn test_diff_tables() {
+        let old = build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS);
+        let new_parts = [
+            ("boot_a", 64, 8255, 0x003A_0000_0000_0000),
+            ("boot_b", 8256, 16447, 0x003F_0000_0000_0000),
+            ("userdata", 16448, 31000, 0),
+        ];
+        let new = build_image(GptRole::Primary, 4096, 32768, &new_parts);
+        let old = parse_gpt(&old, GptRole::Primary).unwrap();
+        let new = parse_gpt(&new, GptRole::Primary).unwrap();
+
+        assert_eq!(diff_tables(&old, &old), []);
+        let diff = gpt_diff("device", &old, "new.bin", &new);
+        assert_eq!(
+            diff.lines,
+            [
+                "~ boot_a: attributes 0x003f000000000000 → 0x003a000000000000 \
+                 (priority 3 → 2, active yes → no)",
+                "~ boot_b: attributes 0x003a000000000000 → 0x003f000000000000 \
+                 (priority 2 → 3, active no → yes)",
+                "~ userdata: LBA 16448..=30000 → 16448..=31000",
+            ]
+        );
+        let GptChange::Attributes { new_slot, .. } = &diff.changes[1] else {
+            panic!("{:?}", diff.changes[1]);
+        };
+        assert!(new_slot.unwrap().active);
+
+        let fewer = build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS[..2]);
+        let fewer = parse_gpt(&fewer, GptRole::Primary).unwrap();
+        assert_eq!(
+            gpt_diff("a", &old, "b", &fewer).lines,
+            ["- userdata (LBA 16448..=30000)"]
+        );
+        assert_eq!(
+            gpt_diff("a", &fewer, "b", &old).lines,
+            ["+ userdata (LBA 16448..=30000)"]
+        );
+
+        // Another unit's table: different GUIDs throughout
+        let mut other = build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS);
+        other[4096 + 56] = 0x22;
+        other[2 * 4096 + 16] = 0x7F;
+        let (layout, _) = image_layout(&other, GptRole::Primary).unwrap();
+        refresh_crcs(&mut other, &layout, 92);
+        let other = parse_gpt(&other, GptRole::Primary).unwrap();
+        let changes = diff_tables(&old, &other);
+        assert!(
+            matches!(changes[0], GptChange::DiskGuid { .. }),
+            "{changes:?}"
+        );
+        assert!(
+            matches!(&changes[1], GptChange::UniqueGuid { name, .. } if name == "boot_a"),
+            "{changes:?}"
+        );
+    }
+
+    #[test]
+    fn test_compare_gpt_dump() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let incoming = write(
+            "main.bin",
+            &build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        );
+
+        let same = write(
+            "same.bin",
+            &build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        );
+        let lines = compare_gpt_dump(&same, GptRole::Primary, &incoming).unwrap();
+        assert!(lines[1].contains("same partitions"), "{lines:?}");
+
+        // A wiped table is still backed up, just not compared
+        let wiped = write("wiped.bin", &[0u8; 6 * 4096]);
+        let lines = compare_gpt_dump(&wiped, GptRole::Primary, &incoming).unwrap();
+        assert!(lines[1].contains("not valid"), "{lines:?}");
+
+        let missing = dir.path().join("missing.bin");
+        assert!(compare_gpt_dump(missing.to_str().unwrap(), GptRole::Primary, &incoming).is_err());
+
+        // A table for a larger disk would put the backup GPT at the wrong LBA
+        let larger = write(
+            "larger.bin",
+            &build_image(GptRole::Primary, 4096, 65536, &Y700_PARTS),
+        );
+        let err = compare_gpt_dump(&larger, GptRole::Primary, &incoming).unwrap_err();
+        assert_eq!(err.context()["field"], "gpt_main");
+        assert!(err.to_string().contains("LBA 65535"), "{err}");
+
+        let other_sectors = write(
+            "512.bin",
+            &build_image(GptRole::Primary, 512, 262144, &Y700_PARTS),
+        );
+        let err = compare_gpt_dump(&other_sectors, GptRole::Primary, &incoming).unwrap_err();
+        assert!(err.to_string().contains("512"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_storage_info() {
+        let lines = [
+            "firehose - Target detected".to_string(),
+            r#"firehose - INFO: {"storage_info": {"total_blocks":62265344, "block_size":4096, "page_size":4096, "mem_type":"UFS"}}"#.to_string(),
+        ];
+        assert_eq!(
+            parse_storage_info(&lines),
+            Some(LunGeometry {
+                sector_size: 4096,
+                total_sectors: 62265344
+            })
+        );
+        let python_repr = ["{'total_blocks': 32768, 'block_size': 512}".to_string()];
+        assert_eq!(
+            parse_storage_info(&python_repr).unwrap().total_sectors,
+            32768
+        );
+        assert_eq!(parse_storage_info(&lines[..1]), None);
+    }
+
+    #[test]
+    fn test_validate_gpt_pair() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let write = |name: &str, data: Vec<u8>| {
+            let path = dir.path().join(name);
+            fs::write(&path, data).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let main = write(
+            "main.bin",
+            build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        );
+        let backup = write(
+            "backup.bin",
+            build_image(GptRole::Backup, 4096, 32768, &Y700_PARTS),
+        );
+        assert!(validate_gpt_pair(&main, &backup).is_ok());
+
+        // Swapped files
+        let err = validate_gpt_pair(&backup, &main).unwrap_err();
+        assert_eq!(err.context()["field"], "gpt_main");
+
+        // Backup from a bigger disk
+        let other = write(
+            "other.bin",
+            build_image(GptRole::Backup, 4096, 65536, &Y700_PARTS),
+        );
+        let err = validate_gpt_pair(&main, &other).unwrap_err();
+        assert!(
+            err.to_string().contains("do not point at each other"),
+            "{err}"
+        );
+    }
+}
@@ -1,49 +1,582 @@
-use tauri::AppHandle;
+#[cfg(feature = "gui")]
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use crate::executor::execute_sequence;
-use crate::models::{CommandResult, CommandSpec};
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, State};
 
-/// Build the command sequence for GPT slot repair via EDL.
-///
-/// Sequence:
-/// 1. `sudo edl ws 0 <gpt_main> --lun=4 --loader=<loader>` — write main GPT
-/// 2. `sudo edl ws 1048543 <gpt_backup> --lun=4 --loader=<loader>` — write backup GPT
-pub fn build_fix_gpt_commands(gpt_main: &str, gpt_backup: &str, loader: &str) -> Vec<CommandSpec> {
-    vec![
-        CommandSpec {
-            program: "edl".to_string(),
-            args: vec![
-                "ws".to_string(),
-                "0".to_string(),
-                gpt_main.to_string(),
-                "--lun=4".to_string(),
-                format!("--loader={}", loader),
-            ],
-            sudo: true,
-            delay_before_ms: 0,
-        },
+#[cfg(feature = "gui")]
+use crate::backup::backups_dir;
+use crate::backup::{backup_dir_for, create_backup_dir};
+#[cfg(feature = "gui")]
+use crate::config::fs_error;
+use crate::device::edl_target_args;
+#[cfg(feature = "gui")]
+use crate::executor::{run_operation, OperationRegistry, OperationRequest};
+use crate::gpt::{
+    check_gpt_pair, entry_array_sectors, parse_gpt_image, save_generated_pair, table_geometry,
+    validate_gpt_pair,
+};
+#[cfg(feature = "gui")]
+use crate::gpt::{
+    generated_backup_pair, generated_gpt_dir, gpt_diff, load_gpt, parse_gpt, slot_edit_pair,
+};
+#[cfg(feature = "gui")]
+use crate::models::{CommandResult, GptDiff, SlotEdit};
+use crate::models::{
+    CommandSpec, GeekFlashError, GptRole, GptTable, LunGeometry, OutputMatchers, PostStep, StepKind,
+};
+
+/// UFS LUN holding the A/B slot partitions on the Y700.
+pub const DEFAULT_GPT_LUN: u32 = 4;
+
+/// `edl getstorageinfo` uploads the loader first, which can take a while.
+const STORAGE_INFO_TIMEOUT_MS: u64 = 60_000;
+
+/// Where a GPT pair is written, taken from the images' own headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GptPlacement {
+    pub lun: u32,
+    /// Sectors the primary image covers from LBA 0 (MBR, header, entries).
+    pub primary_sectors: u64,
+    /// First sector of the backup image: its partition entry array, which
+    /// the backup header follows.
+    pub backup_lba: u64,
+    pub backup_sectors: u64,
+    /// The LUN size the headers describe.
+    pub geometry: LunGeometry,
+}
+
+impl GptPlacement {
+    /// Placement for a validated pair (see [`validate_gpt_pair`]).
+    pub fn from_tables(main: &GptTable, backup: &GptTable, lun: u32) -> Self {
+        let (_, primary_sectors) = table_extent(main);
+        let (backup_lba, backup_sectors) = table_extent(backup);
+        GptPlacement {
+            lun,
+            primary_sectors,
+            backup_lba,
+            backup_sectors,
+            geometry: table_geometry(main),
+        }
+    }
+}
+
+/// The sectors a GPT image covers on its LUN: first LBA and count.
+pub fn table_extent(table: &GptTable) -> (u64, u64) {
+    let h = &table.header;
+    match table.role {
+        GptRole::Primary => (
+            0,
+            h.partition_entry_lba + entry_array_sectors(h, table.sector_size as usize),
+        ),
+        GptRole::Backup => (h.partition_entry_lba, h.my_lba + 1 - h.partition_entry_lba),
+    }
+}
+
+/// Builds `sudo edl <args> --lun=<lun> --loader=<loader>` steps, pinned to
+/// `serial` if given.
+fn edl_steps<'a>(
+    lun: u32,
+    loader: &'a str,
+    serial: Option<&'a str>,
+) -> impl Fn(Vec<String>, StepKind, Option<u64>, OutputMatchers) -> CommandSpec + 'a {
+    move |mut args, kind, timeout_ms, matchers| {
+        args.push(format!("--lun={lun}"));
+        args.push(format!("--loader={}", loader));
+        args.extend(edl_target_args(serial));
         CommandSpec {
             program: "edl".to_string(),
-            args: vec![
-                "ws".to_string(),
-                "1048543".to_string(),
-                gpt_backup.to_string(),
-                "--lun=4".to_string(),
-                format!("--loader={}", loader),
-            ],
+            args,
             sudo: true,
             delay_before_ms: 0,
-        },
-    ]
+            wait_for: None,
+            kind,
+            timeout_ms,
+            matchers,
+            post_step: None,
+        }
+    }
+}
+
+/// File name for a dump of the device's `role` table.
+fn dump_name(role: GptRole) -> &'static str {
+    match role {
+        GptRole::Primary => "gpt_main.bin",
+        GptRole::Backup => "gpt_backup.bin",
+    }
+}
+
+/// `edl rs` of the `(start, count)` sectors into `dump`, then a log of how
+/// the `role` table in `incoming` differs from what was read.
+fn read_gpt_step(
+    edl: &impl Fn(Vec<String>, StepKind, Option<u64>, OutputMatchers) -> CommandSpec,
+    (start, count): (u64, u64),
+    dump: &Path,
+    role: GptRole,
+    incoming: &str,
+) -> CommandSpec {
+    let dump = dump.to_string_lossy().into_owned();
+    let mut read = edl(
+        vec![
+            "rs".to_string(),
+            start.to_string(),
+            count.to_string(),
+            dump.clone(),
+        ],
+        StepKind::EdlRead,
+        None,
+        OutputMatchers::edl_read(),
+    );
+    read.post_step = Some(PostStep::CompareGpt {
+        dump,
+        role,
+        incoming: incoming.to_string(),
+    });
+    read
+}
+
+/// Extra steps around a GPT write.
+#[derive(Clone, Debug, Default)]
+pub struct GptWriteOptions {
+    /// Check the LUN geometry with `edl getstorageinfo` first.
+    pub check_device: bool,
+    /// Dump the device's current tables into this directory before writing.
+    pub backup_dir: Option<PathBuf>,
+}
+
+/// Build the command sequence for GPT slot repair via EDL.
+///
+/// Sequence:
+/// 1. `sudo edl getstorageinfo --lun=<lun> --loader=<loader>` — only with
+///    `check_device`: fails unless the LUN's sector size and count match
+///    the ones the GPT headers describe
+/// 2. `sudo edl rs 0 <n> <backup_dir>/gpt_main.bin ...` and
+///    `sudo edl rs <backup_lba> <n> <backup_dir>/gpt_backup.bin ...` — only
+///    with `backup_dir`: save the device's current tables and log how the
+///    new ones differ
+/// 3. `sudo edl ws 0 <gpt_main> --lun=<lun> --loader=<loader>` — write main GPT
+/// 4. `sudo edl ws <backup_lba> <gpt_backup> --lun=<lun> --loader=<loader>` —
+///    write backup GPT
+///
+/// With `serial`, each `edl` call is pinned to that unit via `--serial_number=`.
+pub fn build_fix_gpt_commands(
+    gpt_main: &str,
+    gpt_backup: &str,
+    loader: &str,
+    placement: &GptPlacement,
+    options: &GptWriteOptions,
+    serial: Option<&str>,
+) -> Vec<CommandSpec> {
+    let edl = edl_steps(placement.lun, loader, serial);
+
+    let mut commands = Vec::new();
+    if options.check_device {
+        commands.push(edl(
+            vec!["getstorageinfo".to_string()],
+            StepKind::Generic,
+            Some(STORAGE_INFO_TIMEOUT_MS),
+            OutputMatchers::edl_storage_info(placement.geometry),
+        ));
+    }
+    if let Some(dir) = &options.backup_dir {
+        let reads = [
+            ((0, placement.primary_sectors), GptRole::Primary, gpt_main),
+            (
+                (placement.backup_lba, placement.backup_sectors),
+                GptRole::Backup,
+                gpt_backup,
+            ),
+        ];
+        for (extent, role, incoming) in reads {
+            let dump = dir.join(dump_name(role));
+            commands.push(read_gpt_step(&edl, extent, &dump, role, incoming));
+        }
+    }
+    commands.push(edl(
+        vec!["ws".to_string(), "0".to_string(), gpt_main.to_string()],
+        StepKind::EdlWrite,
+        None,
+        OutputMatchers::edl_write(),
+    ));
+    commands.push(edl(
+        vec![
+            "ws".to_string(),
+            placement.backup_lba.to_string(),
+            gpt_backup.to_string(),
+        ],
+        StepKind::EdlWrite,
+        None,
+        OutputMatchers::edl_write(),
+    ));
+    commands
+}
+
+/// A GPT pair about to be written, with its parsed tables.
+pub struct StagedGpt {
+    pub gpt_main: String,
+    pub gpt_backup: String,
+    pub main: GptTable,
+    pub backup: GptTable,
+    /// `false` for a pair generated in a dry run: the paths are where it
+    /// would be saved.
+    pub saved: bool,
+}
+
+impl StagedGpt {
+    /// Validate the pair of files at `gpt_main` and `gpt_backup`.
+    pub fn from_files(gpt_main: &str, gpt_backup: &str) -> Result<Self, GeekFlashError> {
+        let (main, backup) = validate_gpt_pair(gpt_main, gpt_backup)?;
+        Ok(StagedGpt {
+            gpt_main: gpt_main.to_string(),
+            gpt_backup: gpt_backup.to_string(),
+            main,
+            backup,
+            saved: true,
+        })
+    }
+
+    /// Validate a generated `(primary, backup)` pair and save it under
+    /// `dir`; a dry run only names the files.
+    pub fn generated(
+        dir: &Path,
+        (primary, backup): (Vec<u8>, Vec<u8>),
+        dry_run: bool,
+    ) -> Result<Self, GeekFlashError> {
+        let (gpt_main, gpt_backup) = save_generated_pair(dir, &primary, &backup, dry_run)?;
+        let main = parse_gpt_image("gpt_main", &gpt_main, &primary, GptRole::Primary)?;
+        let backup = parse_gpt_image("gpt_backup", &gpt_backup, &backup, GptRole::Backup)?;
+        check_gpt_pair(&gpt_main, &main, &gpt_backup, &backup)?;
+        Ok(StagedGpt {
+            gpt_main,
+            gpt_backup,
+            main,
+            backup,
+            saved: !dry_run,
+        })
+    }
+
+    /// Input files to check and hash: the pair, or the primary `source` it
+    /// is generated from while it is not saved.
+    pub fn inputs<'a>(&'a self, source: &'a str, loader: &'a str) -> Vec<(&'a str, &'a str)> {
+        if self.saved {
+            vec![
+                ("gpt_main", &self.gpt_main),
+                ("gpt_backup", &self.gpt_backup),
+                ("loader", loader),
+            ]
+        } else {
+            vec![("gpt_main", source), ("loader", loader)]
+        }
+    }
+}
+
+/// Where the device's current tables on `lun` are saved before a write.
+///
+/// Created up front, since `edl` runs as root and only writes the files;
+/// a dry run just returns the path.
+pub fn prepare_backup_dir(
+    backups_root: &Path,
+    serial: Option<&str>,
+    lun: u32,
+    dry_run: bool,
+) -> Result<PathBuf, GeekFlashError> {
+    let dir = backup_dir_for(backups_root, serial, &format!("gpt-lun{lun}"));
+    if !dry_run {
+        create_backup_dir(&dir)?;
+    }
+    Ok(dir)
 }
 
+/// Refuses to write GPT images that fail validation (see [`validate_gpt_pair`]).
+/// Without `gpt_backup`, one is generated from `gpt_main` under
+/// `$APP_DATA_DIR/gpt/` (a dry run only shows where). Unless `check_device` is `false`, the LUN's
+/// geometry is checked against the headers, since the backup table's LBA
+/// comes from them. The device's current tables are backed up first.
+#[cfg(feature = "gui")]
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn fix_gpt_slot(
     app: AppHandle,
+    registry: State<'_, OperationRegistry>,
+    gpt_main: String,
+    gpt_backup: Option<String>,
+    loader: String,
+    serial: Option<String>,
+    lun: Option<u32>,
+    check_device: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    let dry_run = dry_run.unwrap_or(false);
+    let pair = match gpt_backup {
+        Some(gpt_backup) => StagedGpt::from_files(&gpt_main, &gpt_backup)?,
+        None => StagedGpt::generated(
+            &generated_gpt_dir(&app)?,
+            generated_backup_pair(&gpt_main, None)?,
+            dry_run,
+        )?,
+    };
+    let placement =
+        GptPlacement::from_tables(&pair.main, &pair.backup, lun.unwrap_or(DEFAULT_GPT_LUN));
+    let options = GptWriteOptions {
+        check_device: check_device.unwrap_or(true),
+        backup_dir: Some(prepare_backup_dir(
+            &backups_dir(&app)?,
+            serial.as_deref(),
+            placement.lun,
+            dry_run,
+        )?),
+    };
+    let commands = build_fix_gpt_commands(
+        &pair.gpt_main,
+        &pair.gpt_backup,
+        &loader,
+        &placement,
+        &options,
+        serial.as_deref(),
+    );
+    let request = OperationRequest {
+        kind: "fix_gpt_slot",
+        inputs: pair.inputs(&gpt_main, &loader),
+        serial: serial.as_deref(),
+        commands,
+        dry_run,
+    };
+    run_operation(&app, &registry, request).await
+}
+
+/// Read the `role` table off the device on `lun` and compare the GPT image
+/// at `gpt` against it. The dump is kept with the other device backups.
+#[cfg(feature = "gui")]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn diff_gpt_with_device(
+    app: AppHandle,
+    registry: State<'_, OperationRegistry>,
+    gpt: String,
+    role: GptRole,
+    loader: String,
+    serial: Option<String>,
+    lun: Option<u32>,
+) -> Result<GptDiff, GeekFlashError> {
+    let table = load_gpt("gpt", &gpt, role)?;
+    let lun = lun.unwrap_or(DEFAULT_GPT_LUN);
+    let dump = prepare_backup_dir(&backups_dir(&app)?, serial.as_deref(), lun, false)?
+        .join(dump_name(role));
+    let edl = edl_steps(lun, &loader, serial.as_deref());
+    let commands = vec![read_gpt_step(&edl, table_extent(&table), &dump, role, &gpt)];
+    let request = OperationRequest {
+        kind: "diff_gpt_with_device",
+        inputs: vec![("gpt", &gpt), ("loader", &loader)],
+        serial: serial.as_deref(),
+        commands,
+        dry_run: false,
+    };
+    run_operation(&app, &registry, request).await?;
+
+    let data = fs::read(&dump).map_err(|e| fs_error("read GPT backup", e))?;
+    let device = parse_gpt(&data, role).map_err(|reason| {
+        GeekFlashError::invalid_input(
+            "device",
+            format!("the device's {role:?} GPT is not valid: {reason}"),
+        )
+    })?;
+    Ok(gpt_diff("device", &device, &gpt, &table))
+}
+
+/// Change the A/B flags in the primary GPT at `gpt_main`, then write it
+/// and a regenerated backup GPT the same way [`fix_gpt_slot`] does.
+///
+/// The edited pair is kept under `$APP_DATA_DIR/gpt/` for reference; a dry
+/// run only shows where.
+#[cfg(feature = "gui")]
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn edit_gpt_slots(
+    app: AppHandle,
+    registry: State<'_, OperationRegistry>,
     gpt_main: String,
-    gpt_backup: String,
+    edit: SlotEdit,
     loader: String,
-) -> Result<CommandResult, String> {
-    let commands = build_fix_gpt_commands(&gpt_main, &gpt_backup, &loader);
-    execute_sequence(&app, commands).await
+    serial: Option<String>,
+    lun: Option<u32>,
+    check_device: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<CommandResult, GeekFlashError> {
+    let dry_run = dry_run.unwrap_or(false);
+    let pair = StagedGpt::generated(
+        &generated_gpt_dir(&app)?,
+        slot_edit_pair(&gpt_main, edit)?,
+        dry_run,
+    )?;
+    let placement =
+        GptPlacement::from_tables(&pair.main, &pair.backup, lun.unwrap_or(DEFAULT_GPT_LUN));
+    let options = GptWriteOptions {
+        check_device: check_device.unwrap_or(true),
+        backup_dir: Some(prepare_backup_dir(
+            &backups_dir(&app)?,
+            serial.as_deref(),
+            placement.lun,
+            dry_run,
+        )?),
+    };
+    let commands = build_fix_gpt_commands(
+        &pair.gpt_main,
+        &pair.gpt_backup,
+        &loader,
+        &placement,
+        &options,
+        serial.as_deref(),
+    );
+    let request = OperationRequest {
+        kind: "edit_gpt_slots",
+        inputs: pair.inputs(&gpt_main, &loader),
+        serial: serial.as_deref(),
+        commands,
+        dry_run,
+    };
+    run_operation(&app, &registry, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::gpt::tests::{build_image, Y700_PARTS};
+    use crate::gpt::{generated_backup_pair, parse_gpt, slot_edit_pair};
+    use crate::models::SlotEdit;
+    use crate::plan::command_line;
+
+    #[test]
+    fn test_backup_lba_comes_from_the_headers() {
+        for (ss, disk_sectors, backup_lba) in [(4096, 32768, 32763), (512, 262144, 262111)] {
+            let main = build_image(GptRole::Primary, ss, disk_sectors, &Y700_PARTS);
+            let backup = build_image(GptRole::Backup, ss, disk_sectors, &Y700_PARTS);
+            let main = parse_gpt(&main, GptRole::Primary).unwrap();
+            let backup = parse_gpt(&backup, GptRole::Backup).unwrap();
+
+            let placement = GptPlacement::from_tables(&main, &backup, 2);
+            assert_eq!(placement.backup_lba, backup_lba);
+            assert_eq!(placement.primary_sectors, disk_sectors - backup_lba + 1);
+            assert_eq!(placement.backup_sectors, disk_sectors - backup_lba);
+            assert_eq!(placement.geometry.total_sectors, disk_sectors);
+
+            let commands = build_fix_gpt_commands(
+                "main.bin",
+                "backup.bin",
+                "prog.elf",
+                &placement,
+                &GptWriteOptions::default(),
+                None,
+            );
+            assert_eq!(
+                command_line(&commands[1]),
+                format!("sudo edl ws {backup_lba} backup.bin --lun=2 --loader=prog.elf")
+            );
+        }
+    }
+
+    fn placement() -> GptPlacement {
+        GptPlacement {
+            lun: DEFAULT_GPT_LUN,
+            primary_sectors: 6,
+            backup_lba: 32763,
+            backup_sectors: 5,
+            geometry: LunGeometry {
+                sector_size: 4096,
+                total_sectors: 32768,
+            },
+        }
+    }
+
+    #[test]
+    fn test_check_device_adds_geometry_step() {
+        let options = GptWriteOptions {
+            check_device: true,
+            backup_dir: None,
+        };
+        let commands = build_fix_gpt_commands(
+            "main.bin",
+            "backup.bin",
+            "prog.elf",
+            &placement(),
+            &options,
+            Some("1234"),
+        );
+        assert_eq!(commands.len(), 3);
+        assert_eq!(
+            command_line(&commands[0]),
+            "sudo edl getstorageinfo --lun=4 --loader=prog.elf --serial_number=1234"
+        );
+        assert_eq!(commands[0].matchers.geometry, Some(placement().geometry));
+    }
+
+    #[test]
+    fn test_backup_dir_adds_read_steps_before_writes() {
+        let options = GptWriteOptions {
+            check_device: false,
+            backup_dir: Some(PathBuf::from("/backups/1234/now-gpt-lun4")),
+        };
+        let commands = build_fix_gpt_commands(
+            "main.bin",
+            "backup.bin",
+            "prog.elf",
+            &placement(),
+            &options,
+            None,
+        );
+        let lines: Vec<String> = commands.iter().map(command_line).collect();
+        assert_eq!(
+            lines[..2],
+            [
+                "sudo edl rs 0 6 /backups/1234/now-gpt-lun4/gpt_main.bin --lun=4 --loader=prog.elf",
+                "sudo edl rs 32763 5 /backups/1234/now-gpt-lun4/gpt_backup.bin --lun=4 --loader=prog.elf",
+            ]
+        );
+        assert!(lines[2].starts_with("sudo edl ws 0 main.bin"));
+        assert_eq!(
+            commands[1].post_step,
+            Some(PostStep::CompareGpt {
+                dump: "/backups/1234/now-gpt-lun4/gpt_backup.bin".to_string(),
+                role: GptRole::Backup,
+                incoming: "backup.bin".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_dry_run_does_not_save_generated_pairs() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let main = dir.path().join("main.bin");
+        fs::write(
+            &main,
+            build_image(GptRole::Primary, 4096, 32768, &Y700_PARTS),
+        )
+        .unwrap();
+        let main = main.to_str().unwrap();
+        let out = dir.path().join("gpt");
+
+        let pair = generated_backup_pair(main, None).unwrap();
+        let staged = StagedGpt::generated(&out, pair.clone(), true).unwrap();
+        assert!(!out.exists());
+        assert!(!staged.saved);
+        assert!(staged.gpt_backup.starts_with(out.to_str().unwrap()));
+        assert_eq!(staged.backup.header.my_lba, 32767);
+        assert_eq!(
+            staged.inputs(main, "prog.elf"),
+            [("gpt_main", main), ("loader", "prog.elf")]
+        );
+
+        let edit = SlotEdit::SetActive {
+            slot: crate::models::Slot::B,
+        };
+        let staged = StagedGpt::generated(&out, slot_edit_pair(main, edit).unwrap(), true).unwrap();
+        assert!(!out.exists());
+        assert!(staged.main.partitions[1].slot.unwrap().active);
+
+        let staged = StagedGpt::generated(&out, pair, false).unwrap();
+        assert!(Path::new(&staged.gpt_backup).is_file());
+        assert_eq!(staged.inputs(main, "prog.elf").len(), 3);
+    }
 }
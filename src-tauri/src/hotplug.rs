@@ -0,0 +1,263 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the watcher re-checks the stop flag while waiting.
+const WAKE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Why [`HotplugWatcher::wait`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// A USB device was attached or detached.
+    Hotplug,
+    /// The fallback poll interval elapsed without an event.
+    PollInterval,
+    /// Shutdown was requested.
+    Stopped,
+}
+
+/// Waits for USB attach/detach events.
+///
+/// On Linux this listens to kernel uevents over netlink, so re-enumeration
+/// (e.g. the short EDL window after `fastboot oem edl`) is seen within
+/// milliseconds. Where no event source is available — other platforms, or a
+/// sandbox that refuses the netlink socket — it degrades to plain polling.
+pub struct HotplugWatcher {
+    #[cfg(target_os = "linux")]
+    socket: Option<linux::UeventSocket>,
+}
+
+impl HotplugWatcher {
+    pub fn new() -> Self {
+        #[cfg(target_os = "linux")]
+        {
+            let socket = match linux::UeventSocket::open(WAKE_INTERVAL) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    eprintln!("[hotplug] Warning: uevent socket unavailable ({e}), polling only");
+                    None
+                }
+            };
+            Self { socket }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            Self {}
+        }
+    }
+
+    /// A watcher that never sees events, i.e. pure polling.
+    pub fn polling_only() -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            socket: None,
+        }
+    }
+
+    /// Block until a USB hotplug event, `poll_interval` elapses, or `stop` is set.
+    pub fn wait(&mut self, poll_interval: Duration, stop: &AtomicBool) -> Wake {
+        let deadline = Instant::now() + poll_interval;
+
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return Wake::Stopped;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Wake::PollInterval;
+            }
+
+            #[cfg(target_os = "linux")]
+            if let Some(socket) = &self.socket {
+                let mut buf = [0u8; 8192];
+                match socket.recv(&mut buf) {
+                    Ok(Some(n)) if is_usb_device_uevent(&buf[..n]) => return Wake::Hotplug,
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("[hotplug] Warning: uevent socket failed ({e}), polling only");
+                        self.socket = None;
+                    }
+                }
+                continue;
+            }
+
+            std::thread::sleep(WAKE_INTERVAL.min(deadline - now));
+        }
+    }
+}
+
+impl Default for HotplugWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Check whether a raw kernel uevent describes a whole USB device being
+/// added or removed (interface-level events are ignored).
+///
+/// Messages look like `add@/devices/...\0ACTION=add\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0...`.
+pub fn is_usb_device_uevent(msg: &[u8]) -> bool {
+    let mut action = None;
+    let mut subsystem = None;
+    let mut devtype = None;
+
+    for field in msg.split(|b| *b == 0) {
+        let field = String::from_utf8_lossy(field);
+        if let Some((key, value)) = field.split_once('=') {
+            match key {
+                "ACTION" => action = Some(value.to_string()),
+                "SUBSYSTEM" => subsystem = Some(value.to_string()),
+                "DEVTYPE" => devtype = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    matches!(action.as_deref(), Some("add") | Some("remove"))
+        && subsystem.as_deref() == Some("usb")
+        && devtype.as_deref() == Some("usb_device")
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::Duration;
+
+    /// Multicast group the kernel broadcasts uevents on.
+    const KERNEL_UEVENT_GROUP: u32 = 1;
+
+    /// Netlink socket subscribed to kernel uevents.
+    pub struct UeventSocket {
+        fd: OwnedFd,
+    }
+
+    impl UeventSocket {
+        /// Open and bind the socket; `recv` gives up after `read_timeout`.
+        pub fn open(read_timeout: Duration) -> io::Result<Self> {
+            // SAFETY: plain socket(2) call; the fd is owned immediately below.
+            let raw = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_KOBJECT_UEVENT,
+                )
+            };
+            if raw < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `raw` is a freshly created, valid descriptor we own.
+            let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+            // SAFETY: sockaddr_nl is plain data; all-zero is a valid value.
+            let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = KERNEL_UEVENT_GROUP;
+
+            // SAFETY: `addr` is a valid sockaddr_nl and the length matches.
+            let rc = unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let tv = libc::timeval {
+                tv_sec: read_timeout.as_secs() as libc::time_t,
+                tv_usec: read_timeout.subsec_micros() as libc::suseconds_t,
+            };
+            // SAFETY: `tv` is a valid timeval and the length matches.
+            let rc = unsafe {
+                libc::setsockopt(
+                    fd.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_RCVTIMEO,
+                    &tv as *const libc::timeval as *const libc::c_void,
+                    mem::size_of::<libc::timeval>() as libc::socklen_t,
+                )
+            };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Self { fd })
+        }
+
+        /// Receive one uevent. `Ok(None)` means the read timed out.
+        pub fn recv(&self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+            // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+            let n = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n >= 0 {
+                return Ok(Some(n as usize));
+            }
+
+            let err = io::Error::last_os_error();
+            match err.kind() {
+                io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::Interrupted => Ok(None),
+                // The kernel dropped events for us: treat as "something changed"
+                _ if err.raw_os_error() == Some(libc::ENOBUFS) => Ok(Some(0)),
+                _ => Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usb_device_add_is_hotplug() {
+        let msg = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-2\0ACTION=add\0DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-2\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0PRODUCT=5c6/9008/0\0";
+        assert!(is_usb_device_uevent(msg));
+    }
+
+    #[test]
+    fn test_usb_device_remove_is_hotplug() {
+        let msg = b"remove@/devices/usb1/1-2\0ACTION=remove\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0";
+        assert!(is_usb_device_uevent(msg));
+    }
+
+    #[test]
+    fn test_interface_and_other_events_ignored() {
+        let iface =
+            b"add@/devices/usb1/1-2/1-2:1.0\0ACTION=add\0SUBSYSTEM=usb\0DEVTYPE=usb_interface\0";
+        assert!(!is_usb_device_uevent(iface));
+
+        let bind = b"bind@/devices/usb1/1-2\0ACTION=bind\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0";
+        assert!(!is_usb_device_uevent(bind));
+
+        let block =
+            b"add@/devices/virtual/block/loop0\0ACTION=add\0SUBSYSTEM=block\0DEVTYPE=disk\0";
+        assert!(!is_usb_device_uevent(block));
+
+        assert!(!is_usb_device_uevent(b""));
+    }
+
+    #[test]
+    fn test_wait_returns_on_poll_interval_and_stop() {
+        let mut watcher = HotplugWatcher::polling_only();
+        let stop = AtomicBool::new(false);
+        assert_eq!(
+            watcher.wait(Duration::from_millis(10), &stop),
+            Wake::PollInterval
+        );
+
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(watcher.wait(Duration::from_secs(60), &stop), Wake::Stopped);
+    }
+}
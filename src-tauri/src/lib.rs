@@ -1,18 +1,33 @@
+pub mod audit;
 pub mod avb_signer;
+pub mod backup;
 pub mod config;
 pub mod device;
+pub mod events;
 pub mod executor;
 pub mod flasher;
+pub mod gpt;
 pub mod gpt_fixer;
+pub mod hotplug;
+pub mod log_export;
 pub mod models;
+pub mod plan;
+pub mod progress;
+pub mod runner;
 
+#[cfg(feature = "gui")]
+use tauri::Manager;
+
+#[cfg(feature = "gui")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
+        .manage(executor::OperationRegistry::default())
         .setup(|app| {
-            device::start_device_polling(app.handle().clone());
+            let monitor = device::start_device_monitor(app.handle().clone());
+            app.manage(monitor);
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -20,10 +35,33 @@ pub fn run() {
             config::cmd_save_config,
             flasher::flash_boot,
             flasher::restore_boot,
+            flasher::flash_partition,
+            backup::list_backups,
+            backup::restore_backup,
             gpt_fixer::fix_gpt_slot,
+            gpt_fixer::edit_gpt_slots,
+            gpt_fixer::diff_gpt_with_device,
+            gpt::inspect_gpt,
+            gpt::diff_gpt,
+            gpt::generate_backup_gpt,
             avb_signer::sign_avb,
             device::get_device_status,
+            executor::cancel_operation,
+            audit::list_operations,
+            audit::get_operation,
+            audit::export_operation,
+            log_export::export_log,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<device::DeviceMonitor>().shutdown();
+                // Kill running tools rather than leave an `edl w` orphaned
+                let registry = app.state::<executor::OperationRegistry>();
+                if registry.cancel_all() > 0 {
+                    registry.wait_idle(std::time::Duration::from_secs(5));
+                }
+            }
+        });
 }
@@ -0,0 +1,214 @@
+use std::fmt::Write as _;
+#[cfg(feature = "gui")]
+use std::fs;
+
+use serde_json::json;
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, State};
+#[cfg(feature = "gui")]
+use tokio::process::Command;
+#[cfg(feature = "gui")]
+use tokio::time::{timeout, Duration};
+
+#[cfg(feature = "gui")]
+use crate::audit::{history_dir, list_records, load_record};
+#[cfg(feature = "gui")]
+use crate::config::{fs_error, load_config};
+#[cfg(feature = "gui")]
+use crate::executor::OperationRegistry;
+#[cfg(feature = "gui")]
+use crate::models::{GeekFlashError, LogFormat, ToolVersion};
+use crate::models::{LogHeader, OperationRecord};
+
+/// Tools whose versions are listed in the header.
+#[cfg(feature = "gui")]
+const TOOLS: [&str; 3] = ["edl", "fastboot", "python3"];
+
+/// How long a `--version` probe may take before it is reported as unknown.
+#[cfg(feature = "gui")]
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// First non-empty line of `<tool> --version` (stdout, then stderr).
+#[cfg(feature = "gui")]
+async fn tool_version(tool: &str) -> Option<String> {
+    let output = timeout(
+        VERSION_TIMEOUT,
+        Command::new(tool)
+            .arg("--version")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(feature = "gui")]
+async fn build_header(app: &AppHandle) -> LogHeader {
+    let mut tools = Vec::new();
+    for tool in TOOLS {
+        tools.push(ToolVersion {
+            tool: tool.to_string(),
+            version: tool_version(tool).await,
+        });
+    }
+
+    LogHeader {
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        tools,
+        config: load_config(app),
+        exported_at: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+/// Render a session as plain text: a `#`-commented header, then one line per
+/// log entry with a `=== step i/n ===` marker wherever a new step begins.
+pub fn render_text(header: &LogHeader, record: &OperationRecord) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# GeekFlash session log");
+    let _ = writeln!(out, "# app version: {}", header.app_version);
+    let _ = writeln!(out, "# os: {} ({})", header.os, header.arch);
+    for tool in &header.tools {
+        let version = tool.version.as_deref().unwrap_or("not found");
+        let _ = writeln!(out, "# {}: {version}", tool.tool);
+    }
+    let config = serde_json::to_string(&header.config).unwrap_or_default();
+    let _ = writeln!(out, "# config: {config}");
+    let _ = writeln!(out, "# exported at: {}", header.exported_at);
+    let _ = writeln!(
+        out,
+        "# operation: {} ({})",
+        record.operation_id, record.kind
+    );
+    let serial = record.serial.as_deref().unwrap_or("any");
+    let _ = writeln!(out, "# serial: {serial}");
+    for input in &record.inputs {
+        let sha = input.sha256.as_deref().unwrap_or("unreadable");
+        let _ = writeln!(out, "# input {}: {} sha256={sha}", input.label, input.path);
+    }
+    let finished = record.finished_at.as_deref().unwrap_or("still running");
+    let _ = writeln!(
+        out,
+        "# started: {}, finished: {finished}",
+        record.started_at
+    );
+    let _ = writeln!(out, "# outcome: {}", outcome_text(record));
+
+    let total = record.plan.steps.len();
+    let mut current = None;
+    for line in &record.log {
+        if line.step_index.is_some() && line.step_index != current {
+            current = line.step_index;
+            let i = line.step_index.unwrap_or_default();
+            let cmd = record
+                .plan
+                .steps
+                .get(i)
+                .map(|s| s.command_line.as_str())
+                .unwrap_or("");
+            let _ = writeln!(out, "\n=== step {}/{total}: {cmd} ===", i + 1);
+        }
+        let _ = writeln!(out, "{} [{}] {}", line.timestamp, line.stream, line.content);
+    }
+
+    out
+}
+
+/// Render a session as JSON Lines: a `header` object, then one `step`
+/// object per step start and one `log` object per log line.
+pub fn render_jsonl(header: &LogHeader, record: &OperationRecord) -> String {
+    let mut lines = vec![json!({
+        "type": "header",
+        "app_version": header.app_version,
+        "os": header.os,
+        "arch": header.arch,
+        "tools": header.tools,
+        "config": header.config,
+        "exported_at": header.exported_at,
+        "operation": record.summary(),
+        "inputs": record.inputs,
+        "outcome": outcome_text(record),
+    })];
+
+    let mut current = None;
+    for line in &record.log {
+        if line.step_index.is_some() && line.step_index != current {
+            current = line.step_index;
+            let i = line.step_index.unwrap_or_default();
+            lines.push(json!({
+                "type": "step",
+                "step_index": i,
+                "command_line": record.plan.steps.get(i).map(|s| &s.command_line),
+            }));
+        }
+        lines.push(json!({
+            "type": "log",
+            "timestamp": line.timestamp,
+            "stream": line.stream,
+            "step_index": line.step_index,
+            "content": line.content,
+        }));
+    }
+
+    let mut out = String::new();
+    for line in lines {
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+fn outcome_text(record: &OperationRecord) -> String {
+    match (&record.result, &record.error) {
+        (Some(_), _) => "success".to_string(),
+        (None, Some(e)) => format!("{} ({})", e.code, e.message),
+        (None, None) if record.finished_at.is_none() => "running".to_string(),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// Write a session's log to `dest_path` (Tauri command).
+///
+/// Without `operation_id`, exports the current (or most recent) session,
+/// falling back to the newest one in the history.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn export_log(
+    app: AppHandle,
+    registry: State<'_, OperationRegistry>,
+    operation_id: Option<String>,
+    format: LogFormat,
+    dest_path: String,
+) -> Result<(), GeekFlashError> {
+    let dir = history_dir(&app)?;
+    // The live recorder has log lines the on-disk record only gets at the end
+    let record = match (operation_id, registry.last_session()) {
+        (None, Some(live)) => live,
+        (Some(id), Some(live)) if live.operation_id == id => live,
+        (Some(id), _) => load_record(&dir, &id)?,
+        (None, None) => {
+            let latest = list_records(&dir).into_iter().next().ok_or_else(|| {
+                GeekFlashError::invalid_input("operation_id", "no session to export yet")
+            })?;
+            load_record(&dir, &latest.operation_id)?
+        }
+    };
+
+    let header = build_header(&app).await;
+    let content = match format {
+        LogFormat::Text => render_text(&header, &record),
+        LogFormat::Jsonl => render_jsonl(&header, &record),
+    };
+    fs::write(&dest_path, content).map_err(|e| fs_error("write log export", e))
+}
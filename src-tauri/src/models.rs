@@ -64,23 +64,651 @@ impl Default for AppConfig {
     }
 }
 
+/// What a connected device is currently doing, identified by its USB VID/PID.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeviceMode {
+    Disconnected,
+    /// Android with USB debugging.
+    Adb,
+    /// Recovery / adb sideload.
+    Recovery,
+    /// Userspace fastboot running from recovery.
+    Fastbootd,
+    /// Bootloader (ABL) fastboot.
+    Fastboot,
+    /// Qualcomm emergency download, 05c6:9008 (Sahara/Firehose).
+    Edl,
+    /// Qualcomm diagnostic or Sahara crash-dump mode (05c6:900e, 05c6:901d).
+    Diag {
+        product_id: u16,
+    },
+    /// Any other Qualcomm PID; reported so the user sees something is attached.
+    QualcommUnknown {
+        product_id: u16,
+    },
+}
+
+impl DeviceMode {
+    /// Firehose operations (`edl r`/`w`/`ws`) only work in 9008 mode.
+    pub fn accepts_edl(&self) -> bool {
+        matches!(self, DeviceMode::Edl)
+    }
+
+    /// `fastboot` commands are answered by both the bootloader and fastbootd.
+    pub fn accepts_fastboot(&self) -> bool {
+        matches!(self, DeviceMode::Fastboot | DeviceMode::Fastbootd)
+    }
+
+    /// `fastboot oem edl` is an ABL command; fastbootd does not implement it.
+    pub fn accepts_oem_edl(&self) -> bool {
+        matches!(self, DeviceMode::Fastboot)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+    pub product: Option<String>,
+    /// Host-specific USB location (`1-1.2` on Linux, Location ID on macOS).
+    pub location: String,
+    pub mode: DeviceMode,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DeviceStatus {
-    pub mode: String,
+    /// Summary across all devices: the most flash-relevant mode wins
+    /// (EDL, then fastboot, fastbootd, recovery, adb, diag).
+    pub mode: DeviceMode,
+    pub devices: Vec<DeviceInfo>,
     pub timestamp: u64,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// How a command (or sequence) ended.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandOutcome {
+    /// The process exited on its own; see `exit_code`.
+    #[default]
+    Exited,
+    /// Stopped via `cancel_operation`; `exit_code` is meaningless (-1).
+    Cancelled,
+    /// Killed after exceeding its timeout.
+    TimedOut,
+    /// A write step was killed after exceeding its timeout: the partition
+    /// may be half-written and the device in an inconsistent state.
+    WriteTimedOut,
+    /// Exited 0 but the output shows it failed; `message` has the reason.
+    OutputMismatch,
+    /// Nothing was run; `plan` has what would have been.
+    DryRun,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct CommandResult {
     pub exit_code: i32,
     pub duration_ms: u64,
+    #[serde(default)]
+    pub outcome: CommandOutcome,
+    /// Human-readable explanation for anything other than a clean exit.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Set for dry runs only.
+    #[serde(default)]
+    pub plan: Option<CommandPlan>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+impl CommandResult {
+    pub fn cancelled(duration_ms: u64) -> Self {
+        Self {
+            exit_code: -1,
+            duration_ms,
+            outcome: CommandOutcome::Cancelled,
+            message: Some("Cancelled by user".to_string()),
+            plan: None,
+        }
+    }
+
+    pub fn dry_run(plan: CommandPlan) -> Self {
+        Self {
+            exit_code: 0,
+            duration_ms: 0,
+            outcome: CommandOutcome::DryRun,
+            message: None,
+            plan: Some(plan),
+        }
+    }
+
+    pub fn timed_out(kind: StepKind, timeout_ms: u64, duration_ms: u64) -> Self {
+        let secs = timeout_ms / 1000;
+        let (outcome, message) = if kind.is_write() {
+            (
+                CommandOutcome::WriteTimedOut,
+                format!(
+                    "WRITE TIMED OUT after {secs}s and was killed mid-write: \
+                     the device may be in an inconsistent state. Do not reboot it; \
+                     re-run the write or restore a backup while it is still in EDL."
+                ),
+            )
+        } else {
+            (
+                CommandOutcome::TimedOut,
+                format!("Command timed out after {secs} seconds"),
+            )
+        };
+        Self {
+            exit_code: -1,
+            duration_ms,
+            outcome,
+            message: Some(message),
+            plan: None,
+        }
+    }
+}
+
+/// Result of checking one input file before an operation runs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileCheck {
+    /// Which input this is, e.g. `boot_img` or `loader`.
+    pub label: String,
+    pub path: String,
+    pub size_bytes: Option<u64>,
+    /// Why the file is unusable; `None` if the check passed.
+    pub problem: Option<String>,
+}
+
+/// One step of a dry-run plan.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlannedStep {
+    /// Shell-quoted command line, including the `sudo` prefix.
+    pub command_line: String,
+    pub sudo: bool,
+    pub delay_before_ms: u64,
+    pub wait_for: Option<DeviceWait>,
+    pub kind: StepKind,
+    /// Timeout that would apply (explicit or the kind's default).
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub post_step: Option<PostStep>,
+}
+
+/// What an operation would do, as returned by a dry run.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommandPlan {
+    pub operation: String,
+    pub file_checks: Vec<FileCheck>,
+    pub steps: Vec<PlannedStep>,
+    /// The whole plan as commented shell text, for reviews and support tickets.
+    pub script: String,
+}
+
+/// Payload of the `progress` event, parsed from edl/fastboot output.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProgressEvent {
+    pub operation_id: String,
+    /// Zero-based index into the operation's command sequence.
+    pub step_index: usize,
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub percent: f32,
+}
+
+/// Payload of the `operation-started` event.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OperationInfo {
+    pub operation_id: String,
+    /// e.g. "flash_boot", "fix_gpt_slot"
+    pub kind: String,
+}
+
+/// Payload of the `step-started` event.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StepStarted {
+    pub operation_id: String,
+    /// Zero-based index into the operation's command sequence.
+    pub step_index: usize,
+    pub total_steps: usize,
+    pub command_line: String,
+}
+
+/// Payload of the `step-finished` event, sent for every step that ran.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StepFinished {
+    pub operation_id: String,
+    pub step_index: usize,
+    pub command_line: String,
+    pub result: CommandResult,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LogLine {
     pub timestamp: String,
     pub stream: String,
     pub content: String,
+    /// Zero-based step the line belongs to; `None` outside any step.
+    #[serde(default)]
+    pub step_index: Option<usize>,
+}
+
+/// Export format for `export_log`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Jsonl,
+}
+
+/// Version of an external tool, as reported by `<tool> --version`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ToolVersion {
+    pub tool: String,
+    /// First line of its output; `None` if it could not be run.
+    pub version: Option<String>,
+}
+
+/// Environment snapshot written at the top of an exported log.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogHeader {
+    pub app_version: String,
+    pub os: String,
+    pub arch: String,
+    pub tools: Vec<ToolVersion>,
+    pub config: AppConfig,
+    pub exported_at: String,
+}
+
+/// An input file as it was when an operation started.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InputFileRecord {
+    pub label: String,
+    pub path: String,
+    pub size_bytes: Option<u64>,
+    /// Lowercase hex SHA-256; `None` if the file could not be read.
+    pub sha256: Option<String>,
+}
+
+/// The result of one executed step.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StepRecord {
+    pub step_index: usize,
+    pub command_line: String,
+    pub result: CommandResult,
+}
+
+/// A `GeekFlashError` as stored in the audit log.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorRecord {
+    pub code: String,
+    pub message: String,
+    pub recovery: String,
+}
+
+impl From<&GeekFlashError> for ErrorRecord {
+    fn from(err: &GeekFlashError) -> Self {
+        Self {
+            code: err.code().to_string(),
+            message: err.to_string(),
+            recovery: err.recovery().to_string(),
+        }
+    }
+}
+
+/// Everything persisted about one operation, for the audit log.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OperationRecord {
+    pub operation_id: String,
+    pub kind: String,
+    /// RFC 3339 timestamps.
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    /// Target device serial, if the operation was pinned to one.
+    pub serial: Option<String>,
+    pub inputs: Vec<InputFileRecord>,
+    pub plan: CommandPlan,
+    pub log: Vec<LogLine>,
+    pub steps: Vec<StepRecord>,
+    /// Final result on success; `None` while running or on error.
+    pub result: Option<CommandResult>,
+    pub error: Option<ErrorRecord>,
+}
+
+impl OperationRecord {
+    pub fn summary(&self) -> OperationSummary {
+        OperationSummary {
+            operation_id: self.operation_id.clone(),
+            kind: self.kind.clone(),
+            started_at: self.started_at.clone(),
+            finished_at: self.finished_at.clone(),
+            serial: self.serial.clone(),
+            success: self.result.is_some(),
+            error_code: self.error.as_ref().map(|e| e.code.clone()),
+        }
+    }
+}
+
+/// One row of the operation history list.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OperationSummary {
+    pub operation_id: String,
+    pub kind: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub serial: Option<String>,
+    pub success: bool,
+    pub error_code: Option<String>,
+}
+
+/// Which copy of the partition table a GPT image holds.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GptRole {
+    /// Protective MBR, header at LBA 1, then the partition entries.
+    Primary,
+    /// Partition entries, then the header in the disk's last sector.
+    Backup,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: String,
+    pub partition_entry_lba: u64,
+    pub num_partition_entries: u32,
+    pub partition_entry_size: u32,
+    pub partition_entry_array_crc32: u32,
+}
+
+/// A used (non-zero type GUID) partition entry.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GptPartition {
+    /// Position in the partition entry array.
+    pub index: u32,
+    pub type_guid: String,
+    pub unique_guid: String,
+    pub name: String,
+    pub first_lba: u64,
+    /// Inclusive.
+    pub last_lba: u64,
+    pub size_bytes: u64,
+    pub attributes: u64,
+    /// Decoded A/B flags, for `_a`/`_b` partitions.
+    #[serde(default)]
+    pub slot: Option<SlotAttributes>,
+}
+
+/// Qualcomm A/B flags kept in bits 48-55 of a partition's attributes.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotAttributes {
+    /// 0-3; the bootloader tries higher priorities first.
+    pub priority: u8,
+    pub active: bool,
+    /// 0-7 boot attempts left before the slot is marked unbootable.
+    pub retry_count: u8,
+    /// Android booted from this slot and marked it good.
+    pub successful: bool,
+    pub unbootable: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    /// Partition name suffix, e.g. `_a`.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Slot::A => "_a",
+            Slot::B => "_b",
+        }
+    }
+
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+/// Which slot(s) a boot image is written to; `Active` is resolved against
+/// the device before the plan is built.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotSelector {
+    #[default]
+    A,
+    B,
+    Both,
+    Active,
+}
+
+/// How a partition image reaches the device.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashTransport {
+    /// `edl w` with a Firehose loader, from 9008 mode.
+    Edl,
+    /// `fastboot flash`, from bootloader fastboot.
+    Fastboot,
+}
+
+/// A change to the A/B flags of a GPT's `boot_a`/`boot_b` entries.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SlotEdit {
+    /// Make `slot` the active, highest-priority slot with full retries, and
+    /// clear the other slot's active flag.
+    SetActive { slot: Slot },
+    /// Give `slot` (or both) full retries and clear its unbootable flag.
+    ResetRetries { slot: Option<Slot> },
+}
+
+/// A parsed GPT image and everything wrong with it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GptTable {
+    pub role: GptRole,
+    /// 512 or 4096; UFS LUNs on the Y700 use 4096.
+    pub sector_size: u32,
+    pub header: GptHeader,
+    pub partitions: Vec<GptPartition>,
+    /// Failed checks (CRCs, LBA consistency); empty if the table is valid.
+    pub problems: Vec<String>,
+}
+
+/// One difference between two GPTs; partitions are matched by name.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum GptChange {
+    /// LBAs below are in each table's own sectors.
+    SectorSize {
+        old: u32,
+        new: u32,
+    },
+    DiskGuid {
+        old: String,
+        new: String,
+    },
+    Added {
+        partition: GptPartition,
+    },
+    Removed {
+        partition: GptPartition,
+    },
+    /// Moved, resized or both.
+    Resized {
+        name: String,
+        old_first_lba: u64,
+        old_last_lba: u64,
+        new_first_lba: u64,
+        new_last_lba: u64,
+    },
+    TypeGuid {
+        name: String,
+        old: String,
+        new: String,
+    },
+    UniqueGuid {
+        name: String,
+        old: String,
+        new: String,
+    },
+    /// The slot flags are decoded for `_a`/`_b` partitions.
+    Attributes {
+        name: String,
+        old: u64,
+        new: u64,
+        old_slot: Option<SlotAttributes>,
+        new_slot: Option<SlotAttributes>,
+    },
+}
+
+/// How the GPT from `new` differs from the one from `old`; each is a file
+/// path, or `device` for a table just read off the device.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GptDiff {
+    pub old: String,
+    pub new: String,
+    pub changes: Vec<GptChange>,
+    /// `changes` as text, one line each, as written to the log.
+    pub lines: Vec<String>,
+}
+
+/// A primary/backup pair generated under `$APP_DATA_DIR/gpt/`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GeneratedGpt {
+    pub gpt_main: String,
+    pub gpt_backup: String,
+}
+
+/// Size of one storage LUN, as a GPT pair describes it or the device reports it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LunGeometry {
+    pub sector_size: u32,
+    pub total_sectors: u64,
+}
+
+/// Condition a sequence step waits on: a device in `mode` (and with `serial`
+/// and on USB port `location`, if given) must be enumerated within
+/// `timeout_ms`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceWait {
+    pub mode: DeviceMode,
+    pub serial: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    pub timeout_ms: u64,
+}
+
+/// The unit a flash sequence is pinned to.
+///
+/// A tablet in 9008 EDL mode does not report its fastboot serial, so EDL
+/// steps find it by the USB port it is plugged into and address `edl` by its
+/// chip serial instead.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceTarget {
+    /// fastboot serial, for `fastboot -s`.
+    pub serial: Option<String>,
+    /// USB port path, e.g. `1-1.2`.
+    pub location: Option<String>,
+    /// Chip serial, for `edl --serial_number=`.
+    pub edl_serial: Option<String>,
+}
+
+/// What a sequence step does; decides its default timeout and how loudly a
+/// timeout is reported.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    #[default]
+    Generic,
+    /// `edl w`/`ws`: killing it mid-way leaves a partially written partition.
+    EdlWrite,
+    /// `edl r`/`rs`/`rl`: full dumps are slow over USB 2.0.
+    EdlRead,
+    /// avbtool runs, which hash the whole image.
+    Sign,
+}
+
+impl StepKind {
+    pub fn default_timeout_ms(self) -> u64 {
+        match self {
+            StepKind::Generic => 30_000,
+            StepKind::EdlWrite => 10 * 60_000,
+            StepKind::EdlRead => 15 * 60_000,
+            StepKind::Sign => 5 * 60_000,
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        self == StepKind::EdlWrite
+    }
+}
+
+/// Output checks applied when a command exits 0 (case-insensitive substrings
+/// over stdout and stderr).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OutputMatchers {
+    /// Any of these appearing marks the step failed.
+    pub failure: Vec<String>,
+    /// All of these must appear for the step to count as successful.
+    pub success: Vec<String>,
+    /// `edl getstorageinfo` must report exactly this LUN geometry.
+    #[serde(default)]
+    pub geometry: Option<LunGeometry>,
+}
+
+impl OutputMatchers {
+    /// `edl w`/`ws`: bkerler edl can exit 0 after failing to find the device
+    /// or after a firehose NAK, but only prints "Wrote ..." on success.
+    pub fn edl_write() -> Self {
+        Self {
+            failure: vec![
+                "Couldn't find".to_string(),
+                "Error:".to_string(),
+                "Error writing".to_string(),
+                r#"value="NAK""#.to_string(),
+            ],
+            success: vec!["Wrote ".to_string()],
+            geometry: None,
+        }
+    }
+
+    /// `edl r`/`rs`: the same failure messages as a write.
+    pub fn edl_read() -> Self {
+        Self {
+            success: Vec::new(),
+            ..Self::edl_write()
+        }
+    }
+
+    /// `edl getstorageinfo`: the LUN must have the geometry the GPT files
+    /// were built for.
+    pub fn edl_storage_info(expected: LunGeometry) -> Self {
+        Self {
+            failure: vec!["Couldn't find".to_string(), "Error:".to_string()],
+            success: Vec::new(),
+            geometry: Some(expected),
+        }
+    }
+
+    pub fn fastboot() -> Self {
+        Self {
+            failure: vec!["FAILED".to_string()],
+            success: Vec::new(),
+            geometry: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -89,4 +717,312 @@ pub struct CommandSpec {
     pub args: Vec<String>,
     pub sudo: bool,
     pub delay_before_ms: u64,
+    /// Block before running until the device re-enumerates in this mode.
+    #[serde(default)]
+    pub wait_for: Option<DeviceWait>,
+    #[serde(default)]
+    pub kind: StepKind,
+    /// Overrides the default timeout for `kind`.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub matchers: OutputMatchers,
+    /// Runs in-process once the command has succeeded.
+    #[serde(default)]
+    pub post_step: Option<PostStep>,
+}
+
+impl CommandSpec {
+    pub fn effective_timeout_ms(&self) -> u64 {
+        self.timeout_ms
+            .unwrap_or_else(|| self.kind.default_timeout_ms())
+    }
+}
+
+/// Work the executor does itself after a step succeeds; its output goes to
+/// the step's log and a failure fails the step.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PostStep {
+    /// Check the GPT dump the step saved and log how `incoming`, the image
+    /// about to be written, differs from it.
+    CompareGpt {
+        dump: String,
+        role: GptRole,
+        incoming: String,
+    },
+    /// Hash the partition dump the step saved at `path` and add it to the
+    /// `backup.json` manifest next to it.
+    RecordBackup { partition: String, path: String },
+}
+
+/// One partition read off the device before it was overwritten.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PartitionBackup {
+    pub partition: String,
+    pub path: String,
+    pub size_bytes: u64,
+    /// Checked again before the dump is restored.
+    pub sha256: String,
+    pub created_at: String,
+}
+
+/// A backup directory's `backup.json`: what an operation saved before it
+/// wrote anything.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BackupManifest {
+    pub operation: String,
+    pub serial: Option<String>,
+    pub created_at: String,
+    #[serde(default)]
+    pub partitions: Vec<PartitionBackup>,
+}
+
+/// A backup directory found on disk, for the restore list.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BackupSet {
+    pub dir: String,
+    pub manifest: BackupManifest,
+}
+
+/// Error returned by every Tauri command.
+///
+/// Serialises as `{ code, message, context, recovery }` so the frontend can
+/// branch on `code` instead of parsing text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeekFlashError {
+    /// The program (edl, fastboot, python3, sudo...) is not installed or not on PATH.
+    ToolNotFound {
+        tool: String,
+    },
+    SpawnFailed {
+        program: String,
+        reason: String,
+    },
+    Timeout {
+        program: String,
+        timeout_ms: u64,
+        /// Killed mid-write: the device may be in an inconsistent state.
+        during_write: bool,
+    },
+    NonZeroExit {
+        program: String,
+        exit_code: i32,
+    },
+    /// Exited 0, but its output shows the step failed.
+    OutputMismatch {
+        program: String,
+        reason: String,
+    },
+    Cancelled,
+    DeviceNotFound {
+        mode: DeviceMode,
+        serial: Option<String>,
+        timeout_ms: u64,
+    },
+    PermissionDenied {
+        context: String,
+    },
+    /// `sudo -n` needed a password to run `program`.
+    SudoPasswordRequired {
+        program: String,
+    },
+    InvalidInput {
+        field: String,
+        reason: String,
+    },
+    Io {
+        context: String,
+        reason: String,
+    },
+}
+
+impl GeekFlashError {
+    /// Map a failure to start `program` onto the most specific variant.
+    pub fn spawn(program: &str, err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => GeekFlashError::ToolNotFound {
+                tool: program.to_string(),
+            },
+            std::io::ErrorKind::PermissionDenied => GeekFlashError::PermissionDenied {
+                context: format!("running {program}: {err}"),
+            },
+            _ => GeekFlashError::SpawnFailed {
+                program: program.to_string(),
+                reason: err.to_string(),
+            },
+        }
+    }
+
+    pub fn io(context: impl Into<String>, err: impl std::fmt::Display) -> Self {
+        GeekFlashError::Io {
+            context: context.into(),
+            reason: err.to_string(),
+        }
+    }
+
+    pub fn invalid_input(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        GeekFlashError::InvalidInput {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Stable machine-readable code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            GeekFlashError::ToolNotFound { .. } => "TOOL_NOT_FOUND",
+            GeekFlashError::SpawnFailed { .. } => "SPAWN_FAILED",
+            GeekFlashError::Timeout { .. } => "TIMEOUT",
+            GeekFlashError::NonZeroExit { .. } => "NON_ZERO_EXIT",
+            GeekFlashError::OutputMismatch { .. } => "OUTPUT_MISMATCH",
+            GeekFlashError::Cancelled => "CANCELLED",
+            GeekFlashError::DeviceNotFound { .. } => "DEVICE_NOT_FOUND",
+            GeekFlashError::PermissionDenied { .. } => "PERMISSION_DENIED",
+            GeekFlashError::SudoPasswordRequired { .. } => "SUDO_PASSWORD_REQUIRED",
+            GeekFlashError::InvalidInput { .. } => "INVALID_INPUT",
+            GeekFlashError::Io { .. } => "IO",
+        }
+    }
+
+    /// Suggested next step for the user.
+    pub fn recovery(&self) -> &'static str {
+        match self {
+            GeekFlashError::ToolNotFound { .. } => {
+                "Install the missing tool (edl: `pip3 install edl`, fastboot: Android Platform Tools) and make sure it is on PATH."
+            }
+            GeekFlashError::SpawnFailed { .. } => {
+                "Check that the tool runs from a terminal with the same arguments."
+            }
+            GeekFlashError::Timeout {
+                during_write: true, ..
+            } => {
+                "The device may be in an inconsistent state. Do not reboot it: keep it in EDL and re-run the write or restore a backup."
+            }
+            GeekFlashError::Timeout { .. } => {
+                "Check the USB connection (avoid hubs) and retry, or raise the step timeout."
+            }
+            GeekFlashError::NonZeroExit { .. } | GeekFlashError::OutputMismatch { .. } => {
+                "See the log for the tool's error output, fix the cause and retry."
+            }
+            GeekFlashError::Cancelled => {
+                "The operation was stopped. If a write was interrupted, re-run it before rebooting."
+            }
+            GeekFlashError::DeviceNotFound { .. } => {
+                "Reconnect the device (directly, not through a hub) and check it is in the expected mode."
+            }
+            GeekFlashError::PermissionDenied { .. } => {
+                "Grant access to the file or USB device (udev rules / sudo) and retry."
+            }
+            GeekFlashError::SudoPasswordRequired { .. } => {
+                "Run `sudo -v` in a terminal first, or allow the tool to run without a password (sudoers NOPASSWD or udev rules), and retry."
+            }
+            GeekFlashError::InvalidInput { .. } => "Correct the highlighted input and retry.",
+            GeekFlashError::Io { .. } => "Check the path exists and is readable/writable.",
+        }
+    }
+
+    /// Variant fields, as a JSON object.
+    pub fn context(&self) -> serde_json::Value {
+        use serde_json::json;
+        match self {
+            GeekFlashError::ToolNotFound { tool } => json!({ "tool": tool }),
+            GeekFlashError::SpawnFailed { program, reason } => {
+                json!({ "program": program, "reason": reason })
+            }
+            GeekFlashError::Timeout {
+                program,
+                timeout_ms,
+                during_write,
+            } => json!({
+                "program": program,
+                "timeout_ms": timeout_ms,
+                "during_write": during_write,
+            }),
+            GeekFlashError::NonZeroExit { program, exit_code } => {
+                json!({ "program": program, "exit_code": exit_code })
+            }
+            GeekFlashError::OutputMismatch { program, reason } => {
+                json!({ "program": program, "reason": reason })
+            }
+            GeekFlashError::Cancelled => json!({}),
+            GeekFlashError::DeviceNotFound {
+                mode,
+                serial,
+                timeout_ms,
+            } => json!({ "mode": mode, "serial": serial, "timeout_ms": timeout_ms }),
+            GeekFlashError::PermissionDenied { context } => json!({ "context": context }),
+            GeekFlashError::SudoPasswordRequired { program } => json!({ "program": program }),
+            GeekFlashError::InvalidInput { field, reason } => {
+                json!({ "field": field, "reason": reason })
+            }
+            GeekFlashError::Io { context, reason } => {
+                json!({ "context": context, "reason": reason })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for GeekFlashError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeekFlashError::ToolNotFound { tool } => write!(f, "{tool} not found"),
+            GeekFlashError::SpawnFailed { program, reason } => {
+                write!(f, "Failed to spawn {program}: {reason}")
+            }
+            GeekFlashError::Timeout {
+                program,
+                timeout_ms,
+                during_write,
+            } => {
+                write!(f, "{program} timed out after {}s", timeout_ms / 1000)?;
+                if *during_write {
+                    write!(f, " mid-write; the device may be in an inconsistent state")?;
+                }
+                Ok(())
+            }
+            GeekFlashError::NonZeroExit { program, exit_code } => {
+                write!(f, "{program} exited with code {exit_code}")
+            }
+            GeekFlashError::OutputMismatch { program, reason } => {
+                write!(f, "{program} failed: {reason}")
+            }
+            GeekFlashError::Cancelled => write!(f, "Operation cancelled by user"),
+            GeekFlashError::DeviceNotFound {
+                mode,
+                serial,
+                timeout_ms,
+            } => {
+                write!(f, "No {mode:?} device")?;
+                if let Some(serial) = serial {
+                    write!(f, " with serial {serial}")?;
+                }
+                write!(f, " appeared within {timeout_ms}ms")
+            }
+            GeekFlashError::PermissionDenied { context } => {
+                write!(f, "Permission denied: {context}")
+            }
+            GeekFlashError::SudoPasswordRequired { program } => {
+                write!(f, "sudo needs a password to run {program}")
+            }
+            GeekFlashError::InvalidInput { field, reason } => {
+                write!(f, "Invalid {field}: {reason}")
+            }
+            GeekFlashError::Io { context, reason } => write!(f, "Failed to {context}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for GeekFlashError {}
+
+impl Serialize for GeekFlashError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("GeekFlashError", 4)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.serialize_field("context", &self.context())?;
+        s.serialize_field("recovery", self.recovery())?;
+        s.end()
+    }
 }
@@ -0,0 +1,245 @@
+use std::fs;
+use std::path::Path;
+
+use crate::models::{CommandPlan, CommandSpec, FileCheck, GeekFlashError, PlannedStep, PostStep};
+
+/// Quote `arg` for a POSIX shell, leaving plain words untouched.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// The command line `spec` runs, shell-quoted and with the `sudo` prefix.
+pub fn command_line(spec: &CommandSpec) -> String {
+    let mut words = Vec::with_capacity(spec.args.len() + 2);
+    if spec.sudo {
+        words.push("sudo".to_string());
+    }
+    words.push(shell_quote(&spec.program));
+    words.extend(spec.args.iter().map(|a| shell_quote(a)));
+    words.join(" ")
+}
+
+/// Check that an input file exists, is a regular file and is not empty.
+pub fn check_file(label: &str, path: &str) -> FileCheck {
+    let (size_bytes, problem) = if path.is_empty() {
+        (None, Some("no file selected".to_string()))
+    } else {
+        match fs::metadata(Path::new(path)) {
+            Ok(meta) if !meta.is_file() => (None, Some("not a regular file".to_string())),
+            Ok(meta) if meta.len() == 0 => (Some(0), Some("file is empty".to_string())),
+            Ok(meta) => (Some(meta.len()), None),
+            Err(e) => (None, Some(e.to_string())),
+        }
+    };
+    FileCheck {
+        label: label.to_string(),
+        path: path.to_string(),
+        size_bytes,
+        problem,
+    }
+}
+
+/// Check every `(label, path)` input of an operation.
+pub fn check_inputs(inputs: &[(&str, &str)]) -> Vec<FileCheck> {
+    inputs
+        .iter()
+        .map(|(label, path)| check_file(label, path))
+        .collect()
+}
+
+/// The first failed check as an `InvalidInput` error.
+pub fn first_problem(checks: &[FileCheck]) -> Option<GeekFlashError> {
+    checks.iter().find_map(|c| {
+        c.problem.as_ref().map(|problem| {
+            GeekFlashError::invalid_input(&c.label, format!("{}: {problem}", c.path))
+        })
+    })
+}
+
+/// Resolve an operation into a plan without running anything.
+pub fn build_plan(
+    operation: &str,
+    file_checks: Vec<FileCheck>,
+    commands: &[CommandSpec],
+) -> CommandPlan {
+    let steps: Vec<PlannedStep> = commands
+        .iter()
+        .map(|spec| PlannedStep {
+            command_line: command_line(spec),
+            sudo: spec.sudo,
+            delay_before_ms: spec.delay_before_ms,
+            wait_for: spec.wait_for.clone(),
+            kind: spec.kind,
+            timeout_ms: spec.effective_timeout_ms(),
+            post_step: spec.post_step.clone(),
+        })
+        .collect();
+    let script = render_script(operation, &file_checks, &steps);
+
+    CommandPlan {
+        operation: operation.to_string(),
+        file_checks,
+        steps,
+        script,
+    }
+}
+
+fn render_script(operation: &str, checks: &[FileCheck], steps: &[PlannedStep]) -> String {
+    let mut out = format!("# geekflash dry run: {operation}\n");
+
+    for check in checks {
+        let status = match (&check.problem, check.size_bytes) {
+            (Some(problem), _) => format!("FAIL ({problem})"),
+            (None, Some(size)) => format!("ok, {size} bytes"),
+            (None, None) => "ok".to_string(),
+        };
+        out.push_str(&format!(
+            "# check {}: {} — {status}\n",
+            check.label,
+            shell_quote(&check.path)
+        ));
+    }
+
+    for (i, step) in steps.iter().enumerate() {
+        out.push_str(&format!(
+            "\n# step {}/{} ({:?}, timeout {}s)\n",
+            i + 1,
+            steps.len(),
+            step.kind,
+            step.timeout_ms / 1000
+        ));
+        if step.delay_before_ms > 0 {
+            out.push_str(&format!("sleep {}\n", step.delay_before_ms as f64 / 1000.0));
+        }
+        if let Some(wait) = &step.wait_for {
+            out.push_str(&format!(
+                "# wait up to {}ms for {:?} device",
+                wait.timeout_ms, wait.mode
+            ));
+            if let Some(serial) = &wait.serial {
+                out.push_str(&format!(" {serial}"));
+            }
+            if let Some(location) = &wait.location {
+                out.push_str(&format!(" on USB port {location}"));
+            }
+            out.push('\n');
+        }
+        out.push_str(&step.command_line);
+        out.push('\n');
+        match &step.post_step {
+            Some(PostStep::CompareGpt { dump, incoming, .. }) => out.push_str(&format!(
+                "# then compare {} with {}\n",
+                shell_quote(dump),
+                shell_quote(incoming)
+            )),
+            Some(PostStep::RecordBackup { path, .. }) => out.push_str(&format!(
+                "# then hash {} and record it for restore\n",
+                shell_quote(path)
+            )),
+            None => {}
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flasher::build_flash_boot_commands;
+    use crate::models::{DeviceTarget, Slot};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("boot_a"), "boot_a");
+        assert_eq!(
+            shell_quote("--loader=/tmp/prog.elf"),
+            "--loader=/tmp/prog.elf"
+        );
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("/My Files/boot.img"), "'/My Files/boot.img'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("$(reboot)"), "'$(reboot)'");
+    }
+
+    #[test]
+    fn test_command_line_includes_sudo_and_quotes_paths() {
+        let commands = build_flash_boot_commands(
+            "/tmp/my boot.img",
+            "/tmp/prog.elf",
+            &[Slot::A],
+            &DeviceTarget {
+                serial: Some("1234".to_string()),
+                location: None,
+                edl_serial: Some("0xcafe".to_string()),
+            },
+        );
+        assert_eq!(command_line(&commands[0]), "fastboot -s 1234 oem edl");
+        assert_eq!(
+            command_line(&commands[1]),
+            "sudo edl w boot_a '/tmp/my boot.img' --loader=/tmp/prog.elf --serial_number=0xcafe"
+        );
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir = TempDir::new().unwrap();
+        let good = dir.path().join("boot.img");
+        let empty = dir.path().join("empty.img");
+        fs::write(&good, b"ANDROID!").unwrap();
+        fs::write(&empty, b"").unwrap();
+
+        let check = check_file("boot_img", good.to_str().unwrap());
+        assert_eq!(check.size_bytes, Some(8));
+        assert_eq!(check.problem, None);
+
+        let check = check_file("boot_img", empty.to_str().unwrap());
+        assert_eq!(check.problem.as_deref(), Some("file is empty"));
+
+        let check = check_file("loader", dir.path().to_str().unwrap());
+        assert_eq!(check.problem.as_deref(), Some("not a regular file"));
+
+        let check = check_file("loader", "");
+        assert_eq!(check.problem.as_deref(), Some("no file selected"));
+
+        let missing = dir.path().join("missing.elf");
+        let checks = check_inputs(&[
+            ("boot_img", good.to_str().unwrap()),
+            ("loader", missing.to_str().unwrap()),
+        ]);
+        assert!(checks[1].problem.is_some());
+        assert_eq!(first_problem(&checks).unwrap().code(), "INVALID_INPUT");
+        assert_eq!(first_problem(&checks[..1]), None);
+    }
+
+    #[test]
+    fn test_build_plan_renders_script() {
+        let commands =
+            build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+        let checks = vec![check_file("boot_img", "")];
+        let plan = build_plan("flash_boot", checks, &commands);
+
+        assert_eq!(plan.steps.len(), 2);
+        assert!(plan.steps[1].sudo);
+        assert_eq!(plan.steps[1].timeout_ms, 600_000);
+        assert!(plan.script.starts_with("# geekflash dry run: flash_boot\n"));
+        assert!(plan
+            .script
+            .contains("# check boot_img: '' — FAIL (no file selected)"));
+        assert!(plan
+            .script
+            .contains("# wait up to 30000ms for Edl device\n"));
+        assert!(plan
+            .script
+            .contains("\nsudo edl w boot_a boot.img --loader=prog.elf\n"));
+    }
+}
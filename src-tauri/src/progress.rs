@@ -0,0 +1,205 @@
+/// Sector size used to turn edl's sector counts into bytes (Y700 UFS LUNs).
+pub const EDL_SECTOR_SIZE: u64 = 4096;
+
+/// A progress reading extracted from one line of tool output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressUpdate {
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub percent: f32,
+    /// In-place progress bar redraw (edl's `\r` updates) rather than a line
+    /// worth keeping in the log.
+    pub transient: bool,
+}
+
+/// Recognises edl's progress bar and fastboot's `Sending`/`Writing ... OKAY`
+/// lines. Keeps state across lines because a fastboot flash is reported in
+/// several phases (send + write, per sparse chunk).
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    chunks_total: u32,
+    chunks_sent: u32,
+    chunks_written: u32,
+    bytes_sent: u64,
+    bytes_total: Option<u64>,
+}
+
+impl ProgressParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, line: &str) -> Option<ProgressUpdate> {
+        parse_edl_progress(line).or_else(|| self.parse_fastboot(line))
+    }
+
+    fn parse_fastboot(&mut self, line: &str) -> Option<ProgressUpdate> {
+        let line = line.trim();
+        if !line.contains("OKAY") {
+            return None;
+        }
+
+        if let Some(rest) = line.strip_prefix("Sending") {
+            let chunk_bytes = parse_kb_in_parens(rest)? * 1024;
+            match parse_sparse_chunk(rest) {
+                Some((_, n)) => {
+                    self.chunks_total = n;
+                    self.bytes_total = None;
+                }
+                None => {
+                    self.chunks_total = 1;
+                    self.bytes_total = Some(chunk_bytes);
+                }
+            }
+            self.chunks_sent += 1;
+            self.bytes_sent += chunk_bytes;
+        } else if line.starts_with("Writing") {
+            self.chunks_written += 1;
+        } else {
+            return None;
+        }
+
+        // Each chunk counts twice: once when sent, once when written
+        let phases = self.chunks_total.max(1) * 2;
+        let done = (self.chunks_sent + self.chunks_written).min(phases);
+        Some(ProgressUpdate {
+            bytes_done: Some(self.bytes_sent),
+            bytes_total: self.bytes_total,
+            percent: done as f32 / phases as f32 * 100.0,
+            transient: false,
+        })
+    }
+}
+
+/// Parse an edl progress bar redraw, e.g.
+/// `Progress: |█████-----| 45.2% Write (Sector 0x1200 of 0x6000) 22.43 MB/s`.
+fn parse_edl_progress(line: &str) -> Option<ProgressUpdate> {
+    let rest = line.trim_start().strip_prefix("Progress:")?;
+
+    let pct_end = rest.find('%')?;
+    let pct_str = rest[..pct_end]
+        .rsplit(|c: char| c.is_whitespace() || c == '|')
+        .next()?;
+    let percent: f32 = pct_str.parse().ok()?;
+
+    let (bytes_done, bytes_total) = match parse_sector_range(&rest[pct_end..]) {
+        Some((done, total)) => (Some(done * EDL_SECTOR_SIZE), Some(total * EDL_SECTOR_SIZE)),
+        None => (None, None),
+    };
+
+    Some(ProgressUpdate {
+        bytes_done,
+        bytes_total,
+        percent: percent.clamp(0.0, 100.0),
+        transient: percent < 100.0,
+    })
+}
+
+/// `(Sector 0x1200 of 0x6000)` → `(0x1200, 0x6000)`.
+fn parse_sector_range(s: &str) -> Option<(u64, u64)> {
+    let rest = &s[s.find("Sector 0x")? + "Sector 0x".len()..];
+    let (done, rest) = rest.split_once(" of 0x")?;
+    let total: String = rest.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+    Some((
+        u64::from_str_radix(done, 16).ok()?,
+        u64::from_str_radix(&total, 16).ok()?,
+    ))
+}
+
+/// `'boot_a' (98304 KB)` → `98304`.
+fn parse_kb_in_parens(s: &str) -> Option<u64> {
+    let open = s.find('(')?;
+    let close = open + s[open..].find(')')?;
+    s[open + 1..close]
+        .trim()
+        .strip_suffix("KB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// `sparse 'super' 3/9 (...)` → `(3, 9)`.
+fn parse_sparse_chunk(s: &str) -> Option<(u32, u32)> {
+    if !s.trim_start().starts_with("sparse") {
+        return None;
+    }
+    s.split_whitespace().find_map(|word| {
+        let (i, n) = word.split_once('/')?;
+        Some((i.parse().ok()?, n.parse().ok()?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edl_progress_bar() {
+        let mut parser = ProgressParser::new();
+        let p = parser
+            .feed(
+                "Progress: |██████████----------| 45.2% Write (Sector 0x1200 of 0x6000) 22.43 MB/s",
+            )
+            .unwrap();
+        assert!((p.percent - 45.2).abs() < f32::EPSILON);
+        assert_eq!(p.bytes_done, Some(0x1200 * 4096));
+        assert_eq!(p.bytes_total, Some(0x6000 * 4096));
+        assert!(p.transient);
+    }
+
+    #[test]
+    fn test_edl_progress_complete_without_sectors() {
+        let mut parser = ProgressParser::new();
+        let p = parser
+            .feed("Progress: |████████████████████| 100.0% Complete")
+            .unwrap();
+        assert_eq!(p.percent, 100.0);
+        assert_eq!(p.bytes_done, None);
+        assert!(!p.transient);
+    }
+
+    #[test]
+    fn test_fastboot_send_and_write() {
+        let mut parser = ProgressParser::new();
+        let sent = parser
+            .feed("Sending 'boot_a' (98304 KB)                        OKAY [  2.345s]")
+            .unwrap();
+        assert_eq!(sent.percent, 50.0);
+        assert_eq!(sent.bytes_done, Some(98304 * 1024));
+        assert_eq!(sent.bytes_total, Some(98304 * 1024));
+
+        let written = parser
+            .feed("Writing 'boot_a'                                   OKAY [  0.456s]")
+            .unwrap();
+        assert_eq!(written.percent, 100.0);
+        assert!(!written.transient);
+    }
+
+    #[test]
+    fn test_fastboot_sparse_chunks() {
+        let mut parser = ProgressParser::new();
+        let p = parser
+            .feed("Sending sparse 'super' 1/4 (786428 KB)             OKAY [ 20.123s]")
+            .unwrap();
+        assert_eq!(p.percent, 12.5);
+        assert_eq!(p.bytes_total, None);
+        let p = parser
+            .feed("Writing 'super'                                    OKAY [  3.210s]")
+            .unwrap();
+        assert_eq!(p.percent, 25.0);
+        assert_eq!(p.bytes_done, Some(786428 * 1024));
+    }
+
+    #[test]
+    fn test_unrelated_lines_ignored() {
+        let mut parser = ProgressParser::new();
+        assert!(parser
+            .feed("main - Using loader prog_firehose_ddr.elf ...")
+            .is_none());
+        assert!(parser
+            .feed("Sending 'boot_a' (98304 KB)  FAILED (remote: 'unknown')")
+            .is_none());
+        assert!(parser.feed("Finished. Total time: 2.801s").is_none());
+        assert!(parser.feed("").is_none());
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use tokio::io::AsyncRead;
+use tokio::process::{Child, Command};
+use tokio::time::{timeout, Duration};
+
+use crate::device::wait_for_mode;
+use crate::models::{CommandSpec, DeviceInfo, DeviceWait, GeekFlashError};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+pub type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Grace period between SIGTERM and SIGKILL when killing a command.
+const CANCEL_GRACE: Duration = Duration::from_secs(3);
+
+/// How the executor starts processes and waits for devices.
+pub trait CommandRunner: Send + Sync {
+    /// Start `spec` (under `sudo` if it asks for it) with piped output.
+    fn spawn(&self, spec: &CommandSpec) -> io::Result<SpawnedProcess>;
+
+    /// Wait for the device a step needs to enumerate.
+    fn wait_for_device<'a>(
+        &'a self,
+        wait: &'a DeviceWait,
+    ) -> BoxFuture<'a, Result<DeviceInfo, GeekFlashError>> {
+        Box::pin(wait_for_mode(
+            wait.mode,
+            wait.serial.as_deref(),
+            wait.location.as_deref(),
+            Duration::from_millis(wait.timeout_ms),
+        ))
+    }
+}
+
+/// A started process: its output streams and a handle to wait on or kill it.
+pub struct SpawnedProcess {
+    pub stdout: BoxReader,
+    pub stderr: BoxReader,
+    pub handle: Box<dyn ProcessHandle>,
+}
+
+pub trait ProcessHandle: Send {
+    /// Wait for exit; the exit code is -1 if killed by a signal.
+    fn wait(&mut self) -> BoxFuture<'_, io::Result<i32>>;
+    /// Kill the process and anything it started.
+    fn kill(&mut self) -> BoxFuture<'_, ()>;
+}
+
+/// Runs real processes, each in its own process group.
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn spawn(&self, spec: &CommandSpec) -> io::Result<SpawnedProcess> {
+        let mut cmd = if spec.sudo {
+            // Never prompt: the child is not in the terminal's foreground
+            // group, so a password prompt would stop it until the step times out
+            let mut c = Command::new("sudo");
+            c.arg("-n").arg(&spec.program);
+            c
+        } else {
+            Command::new(&spec.program)
+        };
+        cmd.args(&spec.args);
+        cmd.stdout(std::process::Stdio::piped());
+        cmd.stderr(std::process::Stdio::piped());
+
+        // Own process group, so cancellation can signal everything we started
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd.spawn()?;
+        let missing = |stream| io::Error::other(format!("failed to capture {stream}"));
+        let stdout = child.stdout.take().ok_or_else(|| missing("stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| missing("stderr"))?;
+
+        Ok(SpawnedProcess {
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            handle: Box::new(SystemProcess {
+                child,
+                sudo: spec.sudo,
+            }),
+        })
+    }
+}
+
+struct SystemProcess {
+    child: Child,
+    /// Started through `sudo`, so what it runs belongs to root.
+    sudo: bool,
+}
+
+impl ProcessHandle for SystemProcess {
+    fn wait(&mut self) -> BoxFuture<'_, io::Result<i32>> {
+        Box::pin(async move { Ok(self.child.wait().await?.code().unwrap_or(-1)) })
+    }
+
+    fn kill(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(kill_child_tree(&mut self.child, self.sudo))
+    }
+}
+
+/// Send `signal` to the child's process group.
+///
+/// The child is spawned as a group leader, so this reaches `sudo` and,
+/// without a pty, the tool it runs.
+#[cfg(unix)]
+fn signal_process_group(child: &Child, signal: libc::c_int) {
+    if let Some(pid) = child.id() {
+        // SAFETY: killpg only sends a signal; an invalid pgid just returns an error.
+        unsafe {
+            libc::killpg(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// Every process below `pid`, found with `pgrep -P`; empty if pgrep fails.
+#[cfg(unix)]
+async fn descendants(pid: u32) -> Vec<u32> {
+    let mut found = Vec::new();
+    let mut parents = vec![pid];
+    while let Some(parent) = parents.pop() {
+        let Ok(output) = Command::new("pgrep")
+            .arg("-P")
+            .arg(parent.to_string())
+            .output()
+            .await
+        else {
+            break;
+        };
+        for child in String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .filter_map(|p| p.parse().ok())
+        {
+            found.push(child);
+            parents.push(child);
+        }
+    }
+    found
+}
+
+/// Send `signal` to each of `pids`, through `sudo -n kill` when they belong
+/// to root.
+#[cfg(unix)]
+async fn signal_pids(pids: &[u32], signal: libc::c_int, sudo: bool) {
+    if pids.is_empty() {
+        return;
+    }
+    if sudo {
+        Command::new("sudo")
+            .arg("-n")
+            .arg("kill")
+            .arg(format!("-{signal}"))
+            .args(pids.iter().map(u32::to_string))
+            .output()
+            .await
+            .ok();
+    } else {
+        for &pid in pids {
+            // SAFETY: kill only sends a signal; a stale pid just returns an error.
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+}
+
+/// Whether `pid` still exists; one owned by root reports EPERM.
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists.
+    let found = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Wait up to `grace` for the child and `pids` to be gone.
+#[cfg(unix)]
+async fn wait_all_exited(child: &mut Child, pids: &[u32], grace: Duration) -> bool {
+    timeout(grace, async {
+        loop {
+            let exited = matches!(child.try_wait(), Ok(Some(_)) | Err(_));
+            if exited && !pids.iter().any(|&pid| is_alive(pid)) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
+/// Terminate a child and everything it started: SIGTERM, then SIGKILL
+/// after a grace period for whatever is still alive.
+///
+/// With a pty, sudo runs the tool in its own session, outside the child's
+/// group, and it cannot relay a SIGKILL. So the tool is found up front and
+/// signalled itself, and `sudo` is only killed once it has had the chance
+/// to exit after it.
+async fn kill_child_tree(child: &mut Child, sudo: bool) {
+    #[cfg(unix)]
+    {
+        let tree = match child.id() {
+            Some(pid) => descendants(pid).await,
+            None => Vec::new(),
+        };
+        signal_process_group(child, libc::SIGTERM);
+        signal_pids(&tree, libc::SIGTERM, sudo).await;
+        if wait_all_exited(child, &tree, CANCEL_GRACE).await {
+            return;
+        }
+        let alive: Vec<u32> = tree.into_iter().filter(|&pid| is_alive(pid)).collect();
+        signal_pids(&alive, libc::SIGKILL, sudo).await;
+        if wait_all_exited(child, &alive, CANCEL_GRACE).await {
+            return;
+        }
+        signal_process_group(child, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = sudo;
+
+    child.kill().await.ok();
+}
+
+/// One expected invocation for [`ScriptedRunner`] and its canned behaviour.
+#[derive(Clone, Debug)]
+pub struct ScriptedCommand {
+    pub program: String,
+    /// Expected arguments; `None` accepts any.
+    pub args: Option<Vec<String>>,
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+    /// How long the process "runs" after printing its output.
+    pub delay: Duration,
+    /// Fail to spawn with this error instead of running.
+    pub spawn_error: Option<io::ErrorKind>,
+    /// A file the command writes, the way `edl r`/`rs` saves a dump.
+    pub writes_file: Option<(PathBuf, Vec<u8>)>,
+}
+
+impl ScriptedCommand {
+    /// Expect `program` with any arguments, exiting 0 with no output.
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            args: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+            delay: Duration::ZERO,
+            spawn_error: None,
+            writes_file: None,
+        }
+    }
+}
+
+/// What [`ScriptedRunner`] saw for one spawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedCall {
+    pub program: String,
+    pub args: Vec<String>,
+    pub sudo: bool,
+    pub killed: bool,
+}
+
+/// Fake runner for tests: plays back [`ScriptedCommand`]s in order.
+///
+/// A spawn that does not match the next expected command fails with an
+/// "unexpected command" error. Device waits succeed immediately with a
+/// device in the requested mode unless [`ScriptedRunner::without_devices`]
+/// was used.
+pub struct ScriptedRunner {
+    expected: Mutex<VecDeque<ScriptedCommand>>,
+    calls: std::sync::Arc<Mutex<Vec<RecordedCall>>>,
+    devices_present: bool,
+}
+
+impl ScriptedRunner {
+    pub fn new(expected: Vec<ScriptedCommand>) -> Self {
+        Self {
+            expected: Mutex::new(expected.into()),
+            calls: Default::default(),
+            devices_present: true,
+        }
+    }
+
+    /// Make every device wait time out.
+    pub fn without_devices(mut self) -> Self {
+        self.devices_present = false;
+        self
+    }
+
+    /// Every spawn so far, in order.
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Expected commands that were never spawned.
+    pub fn remaining(&self) -> usize {
+        self.expected.lock().unwrap().len()
+    }
+}
+
+impl CommandRunner for ScriptedRunner {
+    fn spawn(&self, spec: &CommandSpec) -> io::Result<SpawnedProcess> {
+        let mut expected = self.expected.lock().unwrap();
+        let matches = expected.front().is_some_and(|next| {
+            next.program == spec.program && next.args.as_ref().is_none_or(|a| *a == spec.args)
+        });
+        if !matches {
+            return Err(io::Error::other(format!(
+                "unexpected command: {} {}",
+                spec.program,
+                spec.args.join(" ")
+            )));
+        }
+        let script = expected.pop_front().unwrap_or_else(|| unreachable!());
+        if let Some(kind) = script.spawn_error {
+            return Err(io::Error::from(kind));
+        }
+        if let Some((path, data)) = &script.writes_file {
+            std::fs::write(path, data)?;
+        }
+
+        let mut calls = self.calls.lock().unwrap();
+        calls.push(RecordedCall {
+            program: spec.program.clone(),
+            args: spec.args.clone(),
+            sudo: spec.sudo,
+            killed: false,
+        });
+
+        Ok(SpawnedProcess {
+            stdout: Box::new(io::Cursor::new(script.stdout.into_bytes())),
+            stderr: Box::new(io::Cursor::new(script.stderr.into_bytes())),
+            handle: Box::new(ScriptedProcess {
+                exit_code: script.exit_code,
+                delay: script.delay,
+                calls: self.calls.clone(),
+                index: calls.len() - 1,
+            }),
+        })
+    }
+
+    fn wait_for_device<'a>(
+        &'a self,
+        wait: &'a DeviceWait,
+    ) -> BoxFuture<'a, Result<DeviceInfo, GeekFlashError>> {
+        let result = if self.devices_present {
+            Ok(fake_device(wait))
+        } else {
+            Err(GeekFlashError::DeviceNotFound {
+                mode: wait.mode,
+                serial: wait.serial.clone(),
+                timeout_ms: wait.timeout_ms,
+            })
+        };
+        Box::pin(std::future::ready(result))
+    }
+}
+
+fn fake_device(wait: &DeviceWait) -> DeviceInfo {
+    DeviceInfo {
+        vendor_id: 0x05c6,
+        product_id: 0x9008,
+        serial: wait.serial.clone(),
+        product: Some("QUSB__BULK".to_string()),
+        location: wait
+            .location
+            .clone()
+            .unwrap_or_else(|| "scripted".to_string()),
+        mode: wait.mode,
+    }
+}
+
+struct ScriptedProcess {
+    exit_code: i32,
+    delay: Duration,
+    calls: std::sync::Arc<Mutex<Vec<RecordedCall>>>,
+    index: usize,
+}
+
+impl ProcessHandle for ScriptedProcess {
+    fn wait(&mut self) -> BoxFuture<'_, io::Result<i32>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.delay).await;
+            Ok(self.exit_code)
+        })
+    }
+
+    fn kill(&mut self) -> BoxFuture<'_, ()> {
+        self.calls.lock().unwrap()[self.index].killed = true;
+        Box::pin(std::future::ready(()))
+    }
+}
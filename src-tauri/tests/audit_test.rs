@@ -0,0 +1,107 @@
+use geekflash_lib::audit::{hash_inputs, list_records, load_record, save_record, sha256_file};
+use geekflash_lib::flasher::build_flash_boot_commands;
+use geekflash_lib::models::{CommandResult, DeviceTarget, OperationRecord, Slot};
+use geekflash_lib::plan::build_plan;
+use std::fs;
+use tempfile::TempDir;
+
+fn record(operation_id: &str, started_at: &str, result: Option<CommandResult>) -> OperationRecord {
+    let commands = build_flash_boot_commands(
+        "boot.img",
+        "prog.elf",
+        &[Slot::A],
+        &DeviceTarget {
+            serial: Some("1234".to_string()),
+            ..Default::default()
+        },
+    );
+    OperationRecord {
+        operation_id: operation_id.to_string(),
+        kind: "flash_boot".to_string(),
+        started_at: started_at.to_string(),
+        finished_at: None,
+        serial: Some("1234".to_string()),
+        inputs: Vec::new(),
+        plan: build_plan("flash_boot", Vec::new(), &commands),
+        log: Vec::new(),
+        steps: Vec::new(),
+        result,
+        error: None,
+    }
+}
+
+#[test]
+fn test_sha256_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("boot.img");
+    fs::write(&path, b"abc").unwrap();
+    assert_eq!(
+        sha256_file(&path).unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
+
+#[test]
+fn test_hash_inputs_tolerates_missing_files() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("boot.img");
+    fs::write(&path, b"abc").unwrap();
+
+    let inputs = hash_inputs(&[
+        ("boot_img".to_string(), path.to_string_lossy().into_owned()),
+        ("loader".to_string(), "/nonexistent/prog.elf".to_string()),
+    ]);
+    assert_eq!(inputs[0].size_bytes, Some(3));
+    assert!(inputs[0].sha256.is_some());
+    assert_eq!(inputs[1].size_bytes, None);
+    assert_eq!(inputs[1].sha256, None);
+}
+
+#[test]
+fn test_save_load_and_list_records() {
+    let dir = TempDir::new().unwrap();
+    let history = dir.path().join("history");
+
+    let ok = CommandResult {
+        exit_code: 0,
+        duration_ms: 1200,
+        outcome: Default::default(),
+        message: None,
+        plan: None,
+    };
+    save_record(
+        &history,
+        &record("flash_boot-1", "2026-01-01T10:00:00Z", Some(ok)),
+    )
+    .unwrap();
+    save_record(
+        &history,
+        &record("flash_boot-2", "2026-01-02T10:00:00Z", None),
+    )
+    .unwrap();
+    fs::write(history.join("corrupt.json"), "{").unwrap();
+
+    let loaded = load_record(&history, "flash_boot-1").unwrap();
+    assert_eq!(loaded.serial.as_deref(), Some("1234"));
+    assert_eq!(loaded.plan.steps.len(), 2);
+
+    let list = list_records(&history);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].operation_id, "flash_boot-2");
+    assert!(!list[0].success);
+    assert!(list[1].success);
+}
+
+#[test]
+fn test_load_record_rejects_unknown_and_unsafe_ids() {
+    let dir = TempDir::new().unwrap();
+    assert_eq!(
+        load_record(dir.path(), "missing").unwrap_err().code(),
+        "INVALID_INPUT"
+    );
+    assert_eq!(
+        load_record(dir.path(), "../config").unwrap_err().code(),
+        "INVALID_INPUT"
+    );
+    assert!(list_records(&dir.path().join("nope")).is_empty());
+}
@@ -1,3 +1,6 @@
+#![allow(clippy::field_reassign_with_default)]
+
+use geekflash_lib::config::{get_config_value, load_config_from, save_config_to, set_config_value};
 use geekflash_lib::models::AppConfig;
 use std::fs;
 use tempfile::TempDir;
@@ -100,3 +103,58 @@ fn test_save_creates_directory_and_file() {
     let restored: AppConfig = serde_json::from_str(&data).unwrap();
     assert_eq!(restored.avb_params.partition_name, "boot");
 }
+
+#[test]
+fn test_save_config_to_and_load_config_from() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("nested").join("config.json");
+
+    let cfg = AppConfig {
+        firehose_loader: Some("/path/to/prog.elf".to_string()),
+        ..AppConfig::default()
+    };
+    save_config_to(&path, &cfg).unwrap();
+    assert_eq!(
+        load_config_from(&path).firehose_loader.as_deref(),
+        Some("/path/to/prog.elf")
+    );
+
+    // Missing file falls back to defaults
+    let missing = load_config_from(&dir.path().join("missing.json"));
+    assert!(missing.firehose_loader.is_none());
+}
+
+#[test]
+fn test_get_config_value_by_dotted_key() {
+    let cfg = AppConfig::default();
+    assert_eq!(
+        get_config_value(&cfg, "avb_params.partition_name").unwrap(),
+        serde_json::json!("boot")
+    );
+    assert_eq!(
+        get_config_value(&cfg, "firehose_loader").unwrap(),
+        serde_json::Value::Null
+    );
+    assert!(get_config_value(&cfg, "avb_params.nope").is_err());
+}
+
+#[test]
+fn test_set_config_value_parses_json_or_falls_back_to_string() {
+    let cfg = AppConfig::default();
+
+    let cfg = set_config_value(&cfg, "avb_params.partition_size", "123").unwrap();
+    assert_eq!(cfg.avb_params.partition_size, 123);
+
+    // Numeric-looking text still fits a string field
+    let cfg = set_config_value(&cfg, "avb_params.salt", "0011").unwrap();
+    assert_eq!(cfg.avb_params.salt, "0011");
+
+    let cfg = set_config_value(&cfg, "firehose_loader", "/tmp/prog.elf").unwrap();
+    assert_eq!(cfg.firehose_loader.as_deref(), Some("/tmp/prog.elf"));
+    let cfg = set_config_value(&cfg, "firehose_loader", "null").unwrap();
+    assert!(cfg.firehose_loader.is_none());
+
+    let err = set_config_value(&cfg, "avb_params.partition_size", "big").err();
+    assert_eq!(err.unwrap().code(), "INVALID_INPUT");
+    assert!(set_config_value(&cfg, "unknown", "1").is_err());
+}
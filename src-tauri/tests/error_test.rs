@@ -0,0 +1,66 @@
+use geekflash_lib::models::{DeviceMode, GeekFlashError};
+
+#[test]
+fn test_error_serializes_code_message_context_and_recovery() {
+    let err = GeekFlashError::NonZeroExit {
+        program: "edl".to_string(),
+        exit_code: 1,
+    };
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json["code"], "NON_ZERO_EXIT");
+    assert_eq!(json["message"], "edl exited with code 1");
+    assert_eq!(json["context"]["program"], "edl");
+    assert_eq!(json["context"]["exit_code"], 1);
+    assert!(!json["recovery"].as_str().unwrap().is_empty());
+}
+
+#[test]
+fn test_device_not_found_context_uses_mode_tag() {
+    let err = GeekFlashError::DeviceNotFound {
+        mode: DeviceMode::Edl,
+        serial: Some("12345678".to_string()),
+        timeout_ms: 30_000,
+    };
+    let json = serde_json::to_value(&err).unwrap();
+    assert_eq!(json["code"], "DEVICE_NOT_FOUND");
+    assert_eq!(json["context"]["mode"]["kind"], "edl");
+    assert_eq!(json["context"]["serial"], "12345678");
+}
+
+#[test]
+fn test_write_timeout_recovery_warns_about_inconsistent_state() {
+    let write = GeekFlashError::Timeout {
+        program: "edl".to_string(),
+        timeout_ms: 600_000,
+        during_write: true,
+    };
+    let read = GeekFlashError::Timeout {
+        program: "edl".to_string(),
+        timeout_ms: 900_000,
+        during_write: false,
+    };
+    assert_eq!(write.code(), "TIMEOUT");
+    assert!(write.recovery().contains("inconsistent state"));
+    assert!(write.to_string().contains("mid-write"));
+    assert!(!read.recovery().contains("inconsistent state"));
+}
+
+#[test]
+fn test_spawn_error_mapping() {
+    let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+    assert_eq!(
+        GeekFlashError::spawn("edl", missing),
+        GeekFlashError::ToolNotFound {
+            tool: "edl".to_string()
+        }
+    );
+
+    let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+    assert_eq!(
+        GeekFlashError::spawn("sudo", denied).code(),
+        "PERMISSION_DENIED"
+    );
+
+    let other = std::io::Error::other("boom");
+    assert_eq!(GeekFlashError::spawn("edl", other).code(), "SPAWN_FAILED");
+}
@@ -0,0 +1,114 @@
+use geekflash_lib::flasher::build_flash_boot_commands;
+use geekflash_lib::log_export::{render_jsonl, render_text};
+use geekflash_lib::models::{
+    AppConfig, DeviceTarget, ErrorRecord, InputFileRecord, LogHeader, LogLine, OperationRecord,
+    Slot, ToolVersion,
+};
+use geekflash_lib::plan::build_plan;
+
+fn header() -> LogHeader {
+    LogHeader {
+        app_version: "0.2.2".to_string(),
+        os: "linux".to_string(),
+        arch: "x86_64".to_string(),
+        tools: vec![
+            ToolVersion {
+                tool: "fastboot".to_string(),
+                version: Some("fastboot version 35.0.1".to_string()),
+            },
+            ToolVersion {
+                tool: "edl".to_string(),
+                version: None,
+            },
+        ],
+        config: AppConfig::default(),
+        exported_at: "2026-01-01T12:00:00Z".to_string(),
+    }
+}
+
+fn log(content: &str, step_index: Option<usize>) -> LogLine {
+    LogLine {
+        timestamp: "2026-01-01T10:00:00Z".to_string(),
+        stream: "stdout".to_string(),
+        content: content.to_string(),
+        step_index,
+    }
+}
+
+fn record() -> OperationRecord {
+    let commands = build_flash_boot_commands(
+        "boot.img",
+        "prog.elf",
+        &[Slot::A],
+        &DeviceTarget {
+            serial: Some("1234".to_string()),
+            ..Default::default()
+        },
+    );
+    OperationRecord {
+        operation_id: "flash_boot-20260101100000-1".to_string(),
+        kind: "flash_boot".to_string(),
+        started_at: "2026-01-01T10:00:00Z".to_string(),
+        finished_at: Some("2026-01-01T10:01:00Z".to_string()),
+        serial: Some("1234".to_string()),
+        inputs: vec![InputFileRecord {
+            label: "boot_img".to_string(),
+            path: "boot.img".to_string(),
+            size_bytes: Some(3),
+            sha256: Some("ba7816bf".to_string()),
+        }],
+        plan: build_plan("flash_boot", Vec::new(), &commands),
+        log: vec![
+            log("Waiting up to 30000ms for Edl device...", None),
+            log("[1/2] Running: fastboot -s 1234 oem edl", Some(0)),
+            log("OKAY", Some(0)),
+            log("[2/2] Running: sudo edl w boot_a ...", Some(1)),
+            log("Error: sahara handshake failed", Some(1)),
+        ],
+        steps: Vec::new(),
+        result: None,
+        error: Some(ErrorRecord {
+            code: "OUTPUT_MISMATCH".to_string(),
+            message: "edl failed".to_string(),
+            recovery: "retry".to_string(),
+        }),
+    }
+}
+
+#[test]
+fn test_render_text_has_header_and_step_markers() {
+    let text = render_text(&header(), &record());
+    assert!(text.starts_with("# GeekFlash session log\n"));
+    assert!(text.contains("# app version: 0.2.2\n"));
+    assert!(text.contains("# fastboot: fastboot version 35.0.1\n"));
+    assert!(text.contains("# edl: not found\n"));
+    assert!(text.contains("# config: {"));
+    assert!(text.contains("# input boot_img: boot.img sha256=ba7816bf\n"));
+    assert!(text.contains("# outcome: OUTPUT_MISMATCH (edl failed)\n"));
+    assert!(text.contains("\n=== step 1/2: fastboot -s 1234 oem edl ===\n"));
+    assert!(text.contains("=== step 2/2: sudo edl w boot_a boot.img"));
+    assert_eq!(text.matches("=== step").count(), 2);
+    assert!(text.contains("2026-01-01T10:00:00Z [stdout] OKAY\n"));
+}
+
+#[test]
+fn test_render_jsonl_is_one_object_per_line() {
+    let jsonl = render_jsonl(&header(), &record());
+    let lines: Vec<serde_json::Value> = jsonl
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    assert_eq!(lines[0]["type"], "header");
+    assert_eq!(lines[0]["app_version"], "0.2.2");
+    assert_eq!(lines[0]["operation"]["serial"], "1234");
+    assert_eq!(lines[0]["tools"][1]["version"], serde_json::Value::Null);
+
+    let types: Vec<&str> = lines[1..]
+        .iter()
+        .map(|l| l["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["log", "step", "log", "log", "step", "log", "log"]);
+    assert_eq!(lines[2]["command_line"], "fastboot -s 1234 oem edl");
+    assert_eq!(lines[7]["step_index"], 1);
+}
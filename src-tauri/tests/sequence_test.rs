@@ -0,0 +1,499 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use geekflash_lib::audit::list_records;
+use geekflash_lib::backup::{create_backup_set, list_backups_in, with_partition_backups};
+use geekflash_lib::events::RecordingSink;
+use geekflash_lib::executor::{
+    execute_sequence, run_operation_with, CancelToken, OperationRegistry, OperationRequest,
+};
+use geekflash_lib::flasher::build_flash_boot_commands;
+use geekflash_lib::gpt_fixer::{
+    build_fix_gpt_commands, GptPlacement, GptWriteOptions, DEFAULT_GPT_LUN,
+};
+use geekflash_lib::models::{
+    CommandOutcome, DeviceMode, DeviceTarget, GeekFlashError, LunGeometry, Slot,
+};
+use geekflash_lib::runner::{ScriptedCommand, ScriptedRunner};
+use tempfile::TempDir;
+
+fn placement() -> GptPlacement {
+    GptPlacement {
+        lun: DEFAULT_GPT_LUN,
+        primary_sectors: 6,
+        backup_lba: 32763,
+        backup_sectors: 5,
+        geometry: LunGeometry {
+            sector_size: 4096,
+            total_sectors: 32768,
+        },
+    }
+}
+
+fn pinned() -> DeviceTarget {
+    DeviceTarget {
+        serial: Some("1234".to_string()),
+        ..Default::default()
+    }
+}
+
+fn edl_write(stdout: &str) -> ScriptedCommand {
+    ScriptedCommand {
+        stdout: stdout.to_string(),
+        ..ScriptedCommand::new("edl")
+    }
+}
+
+#[tokio::test]
+async fn test_flash_boot_runs_full_sequence() {
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand {
+            args: Some(vec![
+                "-s".to_string(),
+                "1234".to_string(),
+                "oem".to_string(),
+                "edl".to_string(),
+            ]),
+            stderr: "OKAY [  0.001s]".to_string(),
+            ..ScriptedCommand::new("fastboot")
+        },
+        edl_write("Progress: |####| 100.0% Write\nWrote boot.img to sector 262144.\n"),
+    ]);
+    let sink = RecordingSink::default();
+    let target = DeviceTarget {
+        serial: Some("1234".to_string()),
+        location: Some("1-1.2".to_string()),
+        edl_serial: Some("0xcafe".to_string()),
+    };
+    let commands = build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &target);
+
+    let result = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap();
+    assert_eq!(result.outcome, CommandOutcome::Exited);
+    assert_eq!(runner.remaining(), 0);
+
+    let calls = runner.calls();
+    assert_eq!(calls.len(), 2);
+    assert!(calls[1].sudo);
+    // The fastboot serial is not carried into EDL mode
+    assert!(calls[1]
+        .args
+        .contains(&"--serial_number=0xcafe".to_string()));
+    assert!(!calls[1].args.iter().any(|a| a.contains("1234")));
+
+    let logs = sink.log_lines();
+    assert!(logs
+        .iter()
+        .any(|l| l.starts_with("Found Edl device on USB port 1-1.2")));
+    assert!(logs.contains(&"Wrote boot.img to sector 262144.".to_string()));
+    assert_eq!(sink.finished_steps().len(), 2);
+}
+
+#[tokio::test]
+async fn test_fix_gpt_stops_after_failed_step() {
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand {
+            stderr: "firehose - Couldn't write sector".to_string(),
+            exit_code: 1,
+            ..ScriptedCommand::new("edl")
+        },
+        edl_write("Wrote gpt_backup.bin to sector 1048543."),
+    ]);
+    let sink = RecordingSink::default();
+    let commands = build_fix_gpt_commands(
+        "main.bin",
+        "backup.bin",
+        "prog.elf",
+        &placement(),
+        &GptWriteOptions::default(),
+        None,
+    );
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        GeekFlashError::NonZeroExit {
+            program: "edl".to_string(),
+            exit_code: 1
+        }
+    );
+    // The backup GPT is never written over a failed main GPT
+    assert_eq!(runner.calls().len(), 1);
+    assert_eq!(runner.remaining(), 1);
+    assert_eq!(sink.finished_steps().len(), 1);
+}
+
+#[tokio::test]
+async fn test_edl_error_output_fails_despite_exit_zero() {
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        edl_write("main - Error: sahara handshake failed"),
+    ]);
+    let sink = RecordingSink::default();
+    let commands =
+        build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "OUTPUT_MISMATCH");
+    let steps = sink.finished_steps();
+    assert_eq!(steps[1].result.exit_code, 0);
+    assert_eq!(steps[1].result.outcome, CommandOutcome::OutputMismatch);
+}
+
+#[tokio::test]
+async fn test_write_timeout_kills_step_and_reports_during_write() {
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        ScriptedCommand {
+            delay: Duration::from_secs(30),
+            ..edl_write("Progress: |#---| 25.0% Write")
+        },
+    ]);
+    let sink = RecordingSink::default();
+    let mut commands =
+        build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+    commands[1].timeout_ms = Some(100);
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err,
+        GeekFlashError::Timeout {
+            program: "edl".to_string(),
+            timeout_ms: 100,
+            during_write: true
+        }
+    );
+    assert!(runner.calls()[1].killed);
+    assert_eq!(
+        sink.finished_steps()[1].result.outcome,
+        CommandOutcome::WriteTimedOut
+    );
+}
+
+#[tokio::test]
+async fn test_missing_edl_device_stops_before_spawning_edl() {
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        edl_write("Wrote boot.img"),
+    ])
+    .without_devices();
+    let sink = RecordingSink::default();
+    let commands = build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &pinned());
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        GeekFlashError::DeviceNotFound {
+            mode: DeviceMode::Edl,
+            ..
+        }
+    ));
+    assert_eq!(runner.calls().len(), 1);
+    assert_eq!(runner.remaining(), 1);
+}
+
+#[tokio::test]
+async fn test_missing_tool_is_reported_as_tool_not_found() {
+    let runner = ScriptedRunner::new(vec![ScriptedCommand {
+        spawn_error: Some(ErrorKind::NotFound),
+        ..ScriptedCommand::new("fastboot")
+    }]);
+    let sink = RecordingSink::default();
+    let commands =
+        build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "TOOL_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn test_sudo_password_prompt_is_reported_not_waited_on() {
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        ScriptedCommand {
+            stderr: "sudo: a password is required".to_string(),
+            exit_code: 1,
+            ..ScriptedCommand::new("edl")
+        },
+    ]);
+    let sink = RecordingSink::default();
+    let commands =
+        build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), "SUDO_PASSWORD_REQUIRED");
+    assert_eq!(err.to_string(), "sudo needs a password to run edl");
+}
+
+#[tokio::test]
+async fn test_unexpected_command_fails_the_step() {
+    let runner = ScriptedRunner::new(vec![ScriptedCommand {
+        args: Some(vec!["reboot".to_string()]),
+        ..ScriptedCommand::new("fastboot")
+    }]);
+    let sink = RecordingSink::default();
+    let commands =
+        build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &DeviceTarget::default());
+
+    let err = execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("unexpected command"), "{err}");
+    assert!(runner.calls().is_empty());
+}
+
+#[tokio::test]
+async fn test_scripted_operation_is_recorded_in_history() {
+    let dir = TempDir::new().unwrap();
+    let boot = dir.path().join("boot.img");
+    let loader = dir.path().join("prog.elf");
+    fs::write(&boot, b"boot").unwrap();
+    fs::write(&loader, b"loader").unwrap();
+    let (boot, loader) = (boot.to_str().unwrap(), loader.to_str().unwrap());
+
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        ScriptedCommand {
+            exit_code: 2,
+            ..edl_write("")
+        },
+    ]);
+    let registry = OperationRegistry::default();
+    let history = dir.path().join("history");
+    let request = OperationRequest {
+        kind: "flash_boot",
+        inputs: vec![("boot_img", boot), ("loader", loader)],
+        serial: None,
+        commands: build_flash_boot_commands(boot, loader, &[Slot::A], &DeviceTarget::default()),
+        dry_run: false,
+    };
+
+    let err = run_operation_with(
+        &RecordingSink::default(),
+        &runner,
+        &registry,
+        Some(history.clone()),
+        request,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), "NON_ZERO_EXIT");
+
+    let records = list_records(&history);
+    assert_eq!(records.len(), 1);
+    assert!(!records[0].success);
+    assert_eq!(records[0].error_code.as_deref(), Some("NON_ZERO_EXIT"));
+    assert_eq!(registry.last_session().unwrap().steps.len(), 2);
+}
+
+#[tokio::test]
+async fn test_geometry_mismatch_stops_before_writing() {
+    let storage_info = |total_blocks: u64| ScriptedCommand {
+        stdout: format!(
+            r#"firehose - INFO: {{"storage_info": {{"total_blocks":{total_blocks}, "block_size":4096}}}}"#
+        ),
+        ..ScriptedCommand::new("edl")
+    };
+    let options = GptWriteOptions {
+        check_device: true,
+        backup_dir: None,
+    };
+    let commands = build_fix_gpt_commands(
+        "main.bin",
+        "backup.bin",
+        "prog.elf",
+        &placement(),
+        &options,
+        None,
+    );
+
+    let runner = ScriptedRunner::new(vec![storage_info(62265344)]);
+    let err = execute_sequence(
+        &RecordingSink::default(),
+        &runner,
+        "op-1",
+        commands.clone(),
+        &CancelToken::never(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), "OUTPUT_MISMATCH");
+    assert!(err.to_string().contains("62265344 sectors"), "{err}");
+    assert_eq!(runner.calls().len(), 1);
+
+    let runner = ScriptedRunner::new(vec![
+        storage_info(32768),
+        edl_write("Wrote main.bin to sector 0."),
+        edl_write("Wrote backup.bin to sector 32763."),
+    ]);
+    execute_sequence(
+        &RecordingSink::default(),
+        &runner,
+        "op-1",
+        commands,
+        &CancelToken::never(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(runner.remaining(), 0);
+}
+
+#[tokio::test]
+async fn test_live_gpt_is_backed_up_before_writing() {
+    let dir = TempDir::new().unwrap();
+    let options = GptWriteOptions {
+        check_device: false,
+        backup_dir: Some(dir.path().to_path_buf()),
+    };
+    let commands = build_fix_gpt_commands(
+        "main.bin",
+        "backup.bin",
+        "prog.elf",
+        &placement(),
+        &options,
+        None,
+    );
+    // A wiped table on the device: still saved, just not compared
+    let read = |name: &str, sectors: usize| ScriptedCommand {
+        writes_file: Some((dir.path().join(name), vec![0; sectors * 4096])),
+        ..ScriptedCommand::new("edl")
+    };
+    let runner = ScriptedRunner::new(vec![
+        read("gpt_main.bin", 6),
+        read("gpt_backup.bin", 5),
+        edl_write("Wrote main.bin to sector 0."),
+        edl_write("Wrote backup.bin to sector 32763."),
+    ]);
+    let sink = RecordingSink::default();
+
+    execute_sequence(&sink, &runner, "op-1", commands, &CancelToken::never())
+        .await
+        .unwrap();
+    assert_eq!(runner.calls()[0].args[..3], ["rs", "0", "6"]);
+    assert!(dir.path().join("gpt_backup.bin").exists());
+    let logs = sink.log_lines();
+    assert!(logs
+        .iter()
+        .any(|l| l.starts_with("The device's Primary GPT is not valid")));
+}
+
+#[tokio::test]
+async fn test_failed_gpt_backup_aborts_the_write() {
+    let dir = TempDir::new().unwrap();
+    let options = GptWriteOptions {
+        check_device: false,
+        backup_dir: Some(dir.path().to_path_buf()),
+    };
+    let commands = build_fix_gpt_commands(
+        "main.bin",
+        "backup.bin",
+        "prog.elf",
+        &placement(),
+        &options,
+        None,
+    );
+    // edl exits 0 but never writes the dump
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("edl"),
+        edl_write("Wrote main.bin to sector 0."),
+    ]);
+
+    let err = execute_sequence(
+        &RecordingSink::default(),
+        &runner,
+        "op-1",
+        commands,
+        &CancelToken::never(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), "IO");
+    assert_eq!(runner.calls().len(), 1);
+}
+
+#[tokio::test]
+async fn test_partition_is_backed_up_before_flashing() {
+    let root = TempDir::new().unwrap();
+    let dir = create_backup_set(root.path(), Some("1234"), "flash_boot", false).unwrap();
+    let commands = build_flash_boot_commands("boot.img", "prog.elf", &[Slot::A], &pinned());
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        ScriptedCommand {
+            writes_file: Some((dir.join("boot_a.img"), b"stock boot".to_vec())),
+            ..ScriptedCommand::new("edl")
+        },
+        edl_write("Wrote boot.img to sector 262144."),
+    ]);
+    let sink = RecordingSink::default();
+
+    execute_sequence(
+        &sink,
+        &runner,
+        "op-1",
+        with_partition_backups(commands, &dir),
+        &CancelToken::never(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(runner.calls()[1].args[..2], ["r", "boot_a"]);
+    assert_eq!(runner.calls()[2].args[..2], ["w", "boot_a"]);
+    assert!(sink
+        .log_lines()
+        .iter()
+        .any(|l| l.starts_with("Backed up boot_a")));
+
+    let sets = list_backups_in(root.path());
+    assert_eq!(sets.len(), 1);
+    assert_eq!(sets[0].manifest.partitions[0].size_bytes, 10);
+}
+
+#[tokio::test]
+async fn test_failed_partition_backup_aborts_the_write() {
+    let root = TempDir::new().unwrap();
+    let dir = create_backup_set(root.path(), None, "flash_boot", false).unwrap();
+    let commands = build_flash_boot_commands(
+        "boot.img",
+        "prog.elf",
+        &[Slot::A, Slot::B],
+        &DeviceTarget::default(),
+    );
+    // boot_a reads fine, boot_b does not: neither is written
+    let runner = ScriptedRunner::new(vec![
+        ScriptedCommand::new("fastboot"),
+        ScriptedCommand {
+            writes_file: Some((dir.join("boot_a.img"), b"stock boot".to_vec())),
+            ..ScriptedCommand::new("edl")
+        },
+        ScriptedCommand {
+            stderr: "ERROR: Couldn't find partition boot_b\n".to_string(),
+            exit_code: 1,
+            ..ScriptedCommand::new("edl")
+        },
+    ]);
+
+    let err = execute_sequence(
+        &RecordingSink::default(),
+        &runner,
+        "op-1",
+        with_partition_backups(commands, &dir),
+        &CancelToken::never(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), "NON_ZERO_EXIT");
+    assert!(runner.calls().iter().all(|c| c.args[0] != "w"));
+}
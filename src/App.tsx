@@ -8,6 +8,7 @@ import { useDeviceStatus } from "@/hooks/useDeviceStatus";
 import { useLogStream } from "@/hooks/useLogStream";
 import { useConfig } from "@/hooks/useConfig";
 import { useActionValidation } from "@/hooks/useActionValidation";
+import type { DeviceMode } from "@/types";
 import { Zap, RotateCcw, HardDrive, Shield, Usb, WifiOff, Wifi } from "lucide-react";
 
 type TabId = "flash-boot" | "restore-boot" | "gpt-fix" | "avb-sign";
@@ -31,6 +32,19 @@ const deviceColors = {
   disconnected: "bg-zinc-600",
 };
 
+/** Collapse the backend's detailed mode into the three sidebar states */
+function deviceCategory(mode: DeviceMode): keyof typeof deviceIcons {
+  switch (mode.kind) {
+    case "edl":
+      return "edl";
+    case "fastboot":
+    case "fastbootd":
+      return "fastboot";
+    default:
+      return "disconnected";
+  }
+}
+
 function App() {
   const [activeTab, setActiveTab] = useState<TabId>("flash-boot");
   const deviceStatus = useDeviceStatus();
@@ -38,8 +52,9 @@ function App() {
   const { config, updateConfig } = useConfig();
   const validation = useActionValidation(config);
 
-  const DevIcon = deviceIcons[deviceStatus.mode];
-  const isConnected = deviceStatus.mode !== "disconnected";
+  const category = deviceCategory(deviceStatus.mode);
+  const DevIcon = deviceIcons[category];
+  const isConnected = deviceStatus.mode.kind !== "disconnected";
 
   return (
     <div className="flex h-screen bg-background text-foreground overflow-hidden">
@@ -85,11 +100,11 @@ function App() {
           <div className="section-label px-0 mb-2">设备</div>
           <div className="flex items-center gap-2 px-1">
             <span
-              className={`status-dot ${deviceColors[deviceStatus.mode]} ${isConnected ? "connected" : ""}`}
+              className={`status-dot ${deviceColors[category]} ${isConnected ? "connected" : ""}`}
             />
             <DevIcon className="w-3.5 h-3.5 text-muted-foreground/60" />
             <span className="text-xs text-muted-foreground/70">
-              {deviceLabels[deviceStatus.mode]}
+              {deviceLabels[category]}
             </span>
           </div>
         </div>
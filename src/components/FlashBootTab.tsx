@@ -2,7 +2,8 @@ import { useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { Zap, Loader2, CheckCircle2, XCircle } from "lucide-react";
 import { FilePickerField } from "@/components/FilePickerField";
-import type { AppConfig, CommandResult } from "@/types";
+import { SlotSelectField } from "@/components/SlotSelectField";
+import type { AppConfig, CommandResult, SlotSelector } from "@/types";
 import type { ActionValidation } from "@/hooks/useActionValidation";
 
 interface FlashBootTabProps {
@@ -18,6 +19,7 @@ export function FlashBootTab({
 }: FlashBootTabProps) {
   const [loading, setLoading] = useState(false);
   const [result, setResult] = useState<"success" | "error" | null>(null);
+  const [slot, setSlot] = useState<SlotSelector>("a");
 
   const handleFlash = async () => {
     setLoading(true);
@@ -26,6 +28,8 @@ export function FlashBootTab({
       const res = await invoke<CommandResult>("flash_boot", {
         bootImg: config.patched_boot_img,
         loader: config.firehose_loader,
+        slot,
+        gptMain: config.gpt_main_bin,
       });
       setResult(res.exit_code === 0 ? "success" : "error");
     } catch {
@@ -43,7 +47,7 @@ export function FlashBootTab({
           刷入 Patched Boot
         </h1>
         <p className="text-sm text-muted-foreground/60 mt-1">
-          将 patched boot.img 通过 EDL 模式刷入 boot_a 分区
+          将 patched boot.img 通过 EDL 模式刷入 boot 分区
         </p>
       </div>
 
@@ -61,6 +65,7 @@ export function FlashBootTab({
           filters={[{ name: "ELF Loader", extensions: ["elf"] }]}
           onChange={(path) => updateConfig({ firehose_loader: path })}
         />
+        <SlotSelectField value={slot} onChange={setSlot} />
       </div>
 
       {/* Action */}
@@ -55,7 +55,7 @@ export function GptFixTab({
           onChange={(path) => updateConfig({ gpt_main_bin: path })}
         />
         <FilePickerField
-          label="GPT 备份分区 (.bin)"
+          label="GPT 备份分区 (.bin，留空则由主分区生成)"
           value={config.gpt_backup_bin}
           filters={[{ name: "GPT Binary", extensions: ["bin"] }]}
           onChange={(path) => updateConfig({ gpt_backup_bin: path })}
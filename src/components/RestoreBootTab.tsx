@@ -2,7 +2,8 @@ import { useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { RotateCcw, Loader2, CheckCircle2, XCircle } from "lucide-react";
 import { FilePickerField } from "@/components/FilePickerField";
-import type { AppConfig, CommandResult } from "@/types";
+import { SlotSelectField } from "@/components/SlotSelectField";
+import type { AppConfig, CommandResult, SlotSelector } from "@/types";
 import type { ActionValidation } from "@/hooks/useActionValidation";
 
 interface RestoreBootTabProps {
@@ -18,6 +19,7 @@ export function RestoreBootTab({
 }: RestoreBootTabProps) {
   const [loading, setLoading] = useState(false);
   const [result, setResult] = useState<"success" | "error" | null>(null);
+  const [slot, setSlot] = useState<SlotSelector>("a");
 
   const handleRestore = async () => {
     setLoading(true);
@@ -26,6 +28,8 @@ export function RestoreBootTab({
       const res = await invoke<CommandResult>("restore_boot", {
         bootImg: config.stock_boot_img,
         loader: config.firehose_loader,
+        slot,
+        gptMain: config.gpt_main_bin,
       });
       setResult(res.exit_code === 0 ? "success" : "error");
     } catch {
@@ -42,7 +46,7 @@ export function RestoreBootTab({
           恢复原厂 Boot
         </h1>
         <p className="text-sm text-muted-foreground/60 mt-1">
-          将原厂 boot.img 刷入 boot_a 分区，用于救砖恢复
+          将原厂 boot.img 刷入 boot 分区，用于救砖恢复
         </p>
       </div>
 
@@ -59,6 +63,7 @@ export function RestoreBootTab({
           filters={[{ name: "ELF Loader", extensions: ["elf"] }]}
           onChange={(path) => updateConfig({ firehose_loader: path })}
         />
+        <SlotSelectField value={slot} onChange={setSlot} />
       </div>
 
       <div className="flex items-center gap-3">
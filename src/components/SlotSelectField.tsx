@@ -0,0 +1,32 @@
+import type { SlotSelector } from "@/types";
+
+const OPTIONS: { value: SlotSelector; label: string }[] = [
+  { value: "a", label: "boot_a" },
+  { value: "b", label: "boot_b" },
+  { value: "both", label: "boot_a + boot_b" },
+  { value: "active", label: "当前活动 slot" },
+];
+
+interface SlotSelectFieldProps {
+  value: SlotSelector;
+  onChange: (slot: SlotSelector) => void;
+}
+
+export function SlotSelectField({ value, onChange }: SlotSelectFieldProps) {
+  return (
+    <div className="space-y-1.5">
+      <label className="section-label">目标分区</label>
+      <select
+        className="file-field w-full"
+        value={value}
+        onChange={(e) => onChange(e.target.value as SlotSelector)}
+      >
+        {OPTIONS.map((o) => (
+          <option key={o.value} value={o.value}>
+            {o.label}
+          </option>
+        ))}
+      </select>
+    </div>
+  );
+}
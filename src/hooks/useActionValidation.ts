@@ -16,9 +16,7 @@ export function validateActions(config: AppConfig): ActionValidation {
     canRestoreBoot:
       config.stock_boot_img != null && config.firehose_loader != null,
     canFixGpt:
-      config.gpt_main_bin != null &&
-      config.gpt_backup_bin != null &&
-      config.firehose_loader != null,
+      config.gpt_main_bin != null && config.firehose_loader != null,
     canSignAvb:
       config.patched_boot_img != null &&
       config.avbtool_path != null &&
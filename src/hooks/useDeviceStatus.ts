@@ -4,7 +4,8 @@ import type { DeviceStatus } from "@/types";
 
 export function useDeviceStatus() {
   const [status, setStatus] = useState<DeviceStatus>({
-    mode: "disconnected",
+    mode: { kind: "disconnected" },
+    devices: [],
     timestamp: Date.now(),
   });
 
@@ -25,18 +25,289 @@ export interface AppConfig {
   avb_params: AvbParams;
 }
 
+export type DeviceMode =
+  | { kind: "disconnected" }
+  | { kind: "adb" }
+  | { kind: "recovery" }
+  | { kind: "fastbootd" }
+  | { kind: "fastboot" }
+  | { kind: "edl" }
+  | { kind: "diag"; product_id: number }
+  | { kind: "qualcomm_unknown"; product_id: number };
+
+export interface DeviceInfo {
+  vendor_id: number;
+  product_id: number;
+  serial: string | null;
+  product: string | null;
+  location: string;
+  mode: DeviceMode;
+}
+
 export interface DeviceStatus {
-  mode: "edl" | "fastboot" | "disconnected";
+  mode: DeviceMode;
+  devices: DeviceInfo[];
   timestamp: number;
 }
 
+export type CommandOutcome =
+  | "exited"
+  | "cancelled"
+  | "timed_out"
+  | "write_timed_out"
+  | "output_mismatch"
+  | "dry_run";
+
 export interface CommandResult {
   exit_code: number;
   duration_ms: number;
+  outcome: CommandOutcome;
+  message: string | null;
+  plan: CommandPlan | null;
+}
+
+export interface FileCheck {
+  label: string;
+  path: string;
+  size_bytes: number | null;
+  problem: string | null;
+}
+
+export interface PlannedStep {
+  command_line: string;
+  sudo: boolean;
+  delay_before_ms: number;
+  wait_for: {
+    mode: DeviceMode;
+    serial: string | null;
+    location?: string | null;
+    timeout_ms: number;
+  } | null;
+  kind: "generic" | "edl_write" | "edl_read" | "sign";
+  timeout_ms: number;
+  post_step: PostStep | null;
+}
+
+/** Checked after a step succeeds, e.g. diffing a GPT read off the device. */
+export type PostStep =
+  | { action: "compare_gpt"; dump: string; role: GptRole; incoming: string }
+  | { action: "record_backup"; partition: string; path: string };
+
+/** A partition read off the device before it was overwritten. */
+export interface PartitionBackup {
+  partition: string;
+  path: string;
+  size_bytes: number;
+  sha256: string;
+  created_at: string;
+}
+
+export interface BackupManifest {
+  operation: string;
+  serial: string | null;
+  created_at: string;
+  partitions: PartitionBackup[];
+}
+
+/** A backup directory that `restore_backup` can write back. */
+export interface BackupSet {
+  dir: string;
+  manifest: BackupManifest;
+}
+
+export interface CommandPlan {
+  operation: string;
+  file_checks: FileCheck[];
+  steps: PlannedStep[];
+  script: string;
+}
+
+export type GeekFlashErrorCode =
+  | "TOOL_NOT_FOUND"
+  | "SPAWN_FAILED"
+  | "TIMEOUT"
+  | "NON_ZERO_EXIT"
+  | "OUTPUT_MISMATCH"
+  | "CANCELLED"
+  | "DEVICE_NOT_FOUND"
+  | "PERMISSION_DENIED"
+  | "SUDO_PASSWORD_REQUIRED"
+  | "INVALID_INPUT"
+  | "IO";
+
+/** Rejection value of every Tauri command. */
+export interface GeekFlashError {
+  code: GeekFlashErrorCode;
+  message: string;
+  context: Record<string, unknown>;
+  recovery: string;
+}
+
+export interface ProgressEvent {
+  operation_id: string;
+  step_index: number;
+  bytes_done: number | null;
+  bytes_total: number | null;
+  percent: number;
+}
+
+export interface OperationInfo {
+  operation_id: string;
+  kind: string;
 }
 
 export interface LogLine {
   timestamp: string;
   stream: "stdout" | "stderr";
   content: string;
+  step_index: number | null;
+}
+
+export type LogFormat = "text" | "jsonl";
+
+export interface InputFileRecord {
+  label: string;
+  path: string;
+  size_bytes: number | null;
+  sha256: string | null;
+}
+
+export interface StepRecord {
+  step_index: number;
+  command_line: string;
+  result: CommandResult;
+}
+
+export interface OperationRecord {
+  operation_id: string;
+  kind: string;
+  started_at: string;
+  finished_at: string | null;
+  serial: string | null;
+  inputs: InputFileRecord[];
+  plan: CommandPlan;
+  log: LogLine[];
+  steps: StepRecord[];
+  result: CommandResult | null;
+  error: Pick<GeekFlashError, "code" | "message" | "recovery"> | null;
+}
+
+export interface OperationSummary {
+  operation_id: string;
+  kind: string;
+  started_at: string;
+  finished_at: string | null;
+  serial: string | null;
+  success: boolean;
+  error_code: GeekFlashErrorCode | null;
+}
+
+export interface StepStarted {
+  operation_id: string;
+  step_index: number;
+  total_steps: number;
+  command_line: string;
+}
+
+export interface StepFinished {
+  operation_id: string;
+  step_index: number;
+  command_line: string;
+  result: CommandResult;
+}
+
+export type GptRole = "primary" | "backup";
+
+/** One difference between two GPTs; partitions are matched by name. */
+export type GptChange =
+  | { change: "sector_size"; old: number; new: number }
+  | { change: "disk_guid"; old: string; new: string }
+  | { change: "added"; partition: GptPartition }
+  | { change: "removed"; partition: GptPartition }
+  | {
+      change: "resized";
+      name: string;
+      old_first_lba: number;
+      old_last_lba: number;
+      new_first_lba: number;
+      new_last_lba: number;
+    }
+  | { change: "type_guid"; name: string; old: string; new: string }
+  | { change: "unique_guid"; name: string; old: string; new: string }
+  | {
+      change: "attributes";
+      name: string;
+      old: number;
+      new: number;
+      old_slot: SlotAttributes | null;
+      new_slot: SlotAttributes | null;
+    };
+
+/** `old`/`new` are file paths, or "device" for a table read off the device. */
+export interface GptDiff {
+  old: string;
+  new: string;
+  changes: GptChange[];
+  lines: string[];
+}
+
+/** A primary/backup pair generated under `$APP_DATA_DIR/gpt/`. */
+export interface GeneratedGpt {
+  gpt_main: string;
+  gpt_backup: string;
+}
+
+export interface GptHeader {
+  revision: number;
+  header_size: number;
+  header_crc32: number;
+  my_lba: number;
+  alternate_lba: number;
+  first_usable_lba: number;
+  last_usable_lba: number;
+  disk_guid: string;
+  partition_entry_lba: number;
+  num_partition_entries: number;
+  partition_entry_size: number;
+  partition_entry_array_crc32: number;
+}
+
+export interface GptPartition {
+  index: number;
+  type_guid: string;
+  unique_guid: string;
+  name: string;
+  first_lba: number;
+  last_lba: number;
+  size_bytes: number;
+  attributes: number;
+  slot: SlotAttributes | null;
+}
+
+/** How a partition image reaches the device (`flash_partition`). */
+export type FlashTransport = "edl" | "fastboot";
+
+/** Boot slot(s) to write; "active" is resolved against the device. */
+export type SlotSelector = "a" | "b" | "both" | "active";
+
+export interface SlotAttributes {
+  priority: number;
+  active: boolean;
+  retry_count: number;
+  successful: boolean;
+  unbootable: boolean;
+}
+
+export type Slot = "a" | "b";
+
+export type SlotEdit =
+  | { action: "set_active"; slot: Slot }
+  | { action: "reset_retries"; slot: Slot | null };
+
+export interface GptTable {
+  role: GptRole;
+  sector_size: number;
+  header: GptHeader;
+  partitions: GptPartition[];
+  problems: string[];
 }